@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use actix_cors::Cors;
 use actix_web::{App, HttpServer, web};
@@ -12,22 +13,42 @@ mod utils;
 
 use crate::utils::config::config;
 use crate::utils::{Requester, cli::Args};
-use utils::{HttpLogger, KeyJuggler, Logger};
+use utils::{
+    Clients, ConversationStore, HttpLogger, KeyJuggler, Logger, ModelCache, StateStore, WaitQueue,
+};
+
+const STATE_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_UPLOAD_SIZE: usize = 25 << 20;
 
 #[derive(Clone)]
 pub struct AppState {
     config: utils::Config,
     requester: Arc<Requester>,
     juggler: Arc<RwLock<KeyJuggler>>,
+    queue: Arc<WaitQueue>,
+    clients: Arc<Clients>,
+    models: Arc<ModelCache>,
+    conversations: Arc<ConversationStore>,
 }
 
 impl AppState {
-    fn new(config: utils::Config, juggler: Arc<RwLock<KeyJuggler>>) -> Self {
+    fn new(
+        config: utils::Config,
+        juggler: Arc<RwLock<KeyJuggler>>,
+        queue: Arc<WaitQueue>,
+        clients: Arc<Clients>,
+        models: Arc<ModelCache>,
+        conversations: Arc<ConversationStore>,
+    ) -> Self {
         Self {
             config: config.clone(),
             #[allow(clippy::arc_with_non_send_sync)]
-            requester: Arc::new(Requester::new()),
+            requester: Arc::new(Requester::new(&config)),
             juggler,
+            queue,
+            clients,
+            models,
+            conversations,
         }
     }
 }
@@ -42,9 +63,29 @@ async fn main() -> Result<()> {
 
     info!("initializing gemini-juggler...");
 
-    let shared_juggler = Arc::new(RwLock::new(KeyJuggler::new(config.keys.clone())));
+    let mut juggler = KeyJuggler::new(&config);
+    if let Some(path) = &config.state_file {
+        juggler.attach(StateStore::new(path.clone()))?;
+    }
+    let shared_juggler = Arc::new(RwLock::new(juggler));
+
+    let flusher = actix_web::rt::spawn({
+        let juggler = shared_juggler.clone();
+        async move {
+            let mut interval = actix_web::rt::time::interval(STATE_FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                juggler.write().await.flush();
+            }
+        }
+    });
 
-    HttpServer::new(move || {
+    let server_juggler = shared_juggler.clone();
+    let queue = Arc::new(WaitQueue::new(&config));
+    let clients = Arc::new(Clients::new(&config));
+    let models = Arc::new(ModelCache::new(&config));
+    let conversations = Arc::new(ConversationStore::new(&config));
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(
                 Cors::default()
@@ -54,17 +95,44 @@ async fn main() -> Result<()> {
                     .max_age(3600),
             )
             .wrap(HttpLogger)
+            // Large enough for audio uploads to the forwarded OpenAI routes.
+            .app_data(web::PayloadConfig::new(MAX_UPLOAD_SIZE))
             .app_data(web::Data::new(AppState::new(
                 config.clone(),
-                shared_juggler.clone(),
+                server_juggler.clone(),
+                queue.clone(),
+                clients.clone(),
+                models.clone(),
+                conversations.clone(),
             )))
             .service(routes::completion)
             .service(routes::stream_completion)
+            .service(routes::count_tokens)
+            .service(routes::embed_content)
+            .service(routes::batch_embed_contents)
             .service(routes::openai_completion)
+            .service(routes::list_models)
+            .service(routes::get_model)
+            .service(routes::openai_list_models)
+            .service(routes::openai_get_model)
+            .service(routes::openai_forward)
+            .service(routes::messages)
+            .service(routes::count_message_tokens)
+            .service(routes::create_response)
+            .service(routes::get_response)
+            .service(routes::delete_response)
+            .service(routes::ollama_chat)
+            .service(routes::ollama_generate)
+            .service(routes::ollama_tags)
+            .service(routes::ollama_embed)
             .service(routes::status)
     })
     .bind((host, port))?
-    .run()
-    .await
-    .map_err(Into::into)
+    .run();
+
+    server.await?;
+    flusher.abort();
+    shared_juggler.write().await.persist_now();
+
+    Ok(())
 }
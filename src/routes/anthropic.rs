@@ -0,0 +1,81 @@
+use actix_web::{Error, HttpResponse, post, web};
+use serde_json::{Value, json};
+
+use super::auth::Authenticated;
+use super::translate::{Generated, Translated, anthropic, call_method, generate, reshape_error};
+use crate::{AppState, utils::model_name};
+
+#[post("/v1/messages")]
+async fn messages(
+    Authenticated(client): Authenticated,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let data = data.into_inner();
+    let body = body.into_inner();
+    let requested = body
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let model = data
+        .config
+        .resolve_model(model_name(&requested))
+        .to_string();
+    let stream = body.get("stream").and_then(Value::as_bool).unwrap_or(false);
+
+    let gemini = match anthropic::request(&body) {
+        Ok(gemini) => gemini,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(anthropic::error(400, &message))),
+    };
+
+    Ok(match generate(data, &client, model, gemini, stream).await {
+        Ok(Generated::Complete(answer)) => {
+            HttpResponse::Ok().json(anthropic::response(&answer, &requested))
+        }
+        Ok(Generated::Streaming(events)) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(Translated::new(events, anthropic::Stream::new(&requested))),
+        Err(resp) => reshape_error(resp, anthropic::error).await,
+    })
+}
+
+#[post("/v1/messages/count_tokens")]
+async fn count_message_tokens(
+    Authenticated(client): Authenticated,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let data = data.into_inner();
+    let body = body.into_inner();
+    let requested = body
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let model = data.config.resolve_model(model_name(requested)).to_string();
+
+    let gemini = match anthropic::request(&body) {
+        Ok(gemini) => gemini,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(anthropic::error(400, &message))),
+    };
+    // Counted against the same quota as the native countTokens route.
+    let quota = format!("{model}:countTokens");
+    let body = json!({"generateContentRequest": {
+        "model": format!("models/{model}"),
+        "contents": gemini["contents"],
+        "systemInstruction": gemini.get("systemInstruction"),
+        "tools": gemini.get("tools"),
+    }});
+    let counted = match call_method(&data, &client, &model, "countTokens", &quota, body).await {
+        Ok(counted) => counted,
+        Err(resp) => return Ok(reshape_error(resp, anthropic::error).await),
+    };
+    Ok(match counted["totalTokens"].as_u64() {
+        Some(tokens) => HttpResponse::Ok().json(json!({"input_tokens": tokens})),
+        None => HttpResponse::BadGateway().json(anthropic::error(
+            502,
+            "Invalid upstream response: no totalTokens",
+        )),
+    })
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::future::{Ready, ready};
+use std::sync::Arc;
+
+use actix_web::{
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, dev::Payload, error::InternalError,
+    web,
+};
+
+use super::translate::error_body;
+use crate::{AppState, utils::Client};
+
+/// The client a request comes from, authenticated by the key it sent in the
+/// `x-goog-api-key` header, an `Authorization: Bearer` header, the `key`
+/// query parameter or the `x-api-key` header, the way the Gemini, OpenAI and
+/// Anthropic SDKs send it. Keyless requests to the Ollama endpoints are
+/// served as `ollama.client`, when set.
+pub struct Authenticated(pub Arc<Client>);
+
+impl FromRequest for Authenticated {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authenticate(req))
+    }
+}
+
+fn authenticate(req: &HttpRequest) -> Result<Authenticated, Error> {
+    let unauthorized = |message: &str| {
+        let body = error_body(req.path(), 401, message);
+        InternalError::from_response(message.to_string(), HttpResponse::Unauthorized().json(body))
+            .into()
+    };
+
+    let data = req
+        .app_data::<web::Data<AppState>>()
+        .expect("app state is registered");
+    let client = match presented_key(req) {
+        Some(key) => data.clients.authenticate(&key),
+        None => match &data.config.ollama.client {
+            Some(name) if req.path().starts_with("/api/") => data.clients.named(name),
+            _ => {
+                return Err(unauthorized(
+                    "Missing API key, send it in the x-goog-api-key header, as a bearer token or as the key query parameter",
+                ));
+            }
+        },
+    };
+    let Some(client) = client else {
+        return Err(unauthorized("Invalid API key"));
+    };
+
+    // For the request log.
+    req.extensions_mut().insert(client.clone());
+    Ok(Authenticated(client))
+}
+
+fn presented_key(req: &HttpRequest) -> Option<String> {
+    let header = |name| req.headers().get(name)?.to_str().ok();
+    if let Some(key) = header("x-goog-api-key").or_else(|| header("x-api-key")) {
+        return Some(key.to_string());
+    }
+    if let Some(token) = header("Authorization").and_then(|value| value.strip_prefix("Bearer ")) {
+        return Some(token.to_string());
+    }
+    web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()?
+        .into_inner()
+        .remove("key")
+}
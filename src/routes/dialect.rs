@@ -0,0 +1,207 @@
+use actix_web::Error;
+use serde_json::{Value, json};
+
+use crate::{
+    AppState,
+    utils::{Event, Requested},
+};
+
+/// The API a client speaks, which decides how its requests are forwarded,
+/// how partial answers are read and what errors look like.
+#[derive(Clone, Copy)]
+pub enum Dialect {
+    Gemini,
+    OpenAi,
+}
+
+impl Dialect {
+    /// The dialect of the route at `path`.
+    pub fn of(path: &str) -> Self {
+        if path.starts_with("/v1beta/openai/") {
+            Dialect::OpenAi
+        } else {
+            Dialect::Gemini
+        }
+    }
+
+    pub async fn forward(
+        self,
+        data: &AppState,
+        key: &str,
+        model: &str,
+        body: &Value,
+    ) -> Result<Event, Error> {
+        match self {
+            Dialect::Gemini => data.requester.forward_gemini(key, model, body, true).await,
+            Dialect::OpenAi => data.requester.forward_openai(key, body).await,
+        }
+    }
+
+    /// Text an event adds to the answer.
+    pub fn text(self, event: &Value) -> String {
+        match self {
+            Dialect::Gemini => event
+                .pointer("/candidates/0/content/parts")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|part| part.get("text")?.as_str())
+                .collect(),
+            Dialect::OpenAi => event
+                .pointer("/choices/0/delta/content")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    /// Whether `event` is the last one of a complete answer. Streams that end
+    /// before it were cut off, even if the connection closed cleanly.
+    pub fn finishes(self, event: &str) -> bool {
+        match self {
+            Dialect::Gemini => serde_json::from_str::<Value>(event)
+                .ok()
+                .and_then(|value| value.pointer("/candidates/0/finishReason").cloned())
+                .is_some_and(|reason| !reason.is_null()),
+            Dialect::OpenAi => event == "[DONE]",
+        }
+    }
+
+    /// `body` with `text` appended as the model's turn, for another key to
+    /// carry on from.
+    pub fn continuation(self, body: &Value, text: &str) -> Value {
+        let mut body = body.clone();
+        let (field, turn) = match self {
+            Dialect::Gemini => (
+                "contents",
+                json!({"role": "model", "parts": [{"text": text}]}),
+            ),
+            Dialect::OpenAi => ("messages", json!({"role": "assistant", "content": text})),
+        };
+        if let Some(turns) = body.get_mut(field).and_then(Value::as_array_mut) {
+            turns.push(turn);
+        }
+        body
+    }
+
+    /// What `body` asks of `model`, to check against client policies.
+    pub fn requested<'a>(self, model: &'a str, body: &Value) -> Requested<'a> {
+        let tools = body.get("tools").and_then(Value::as_array);
+        match self {
+            Dialect::Gemini => {
+                let grounds = |tool: &Value| {
+                    GEMINI_GROUNDING_TOOLS
+                        .iter()
+                        .any(|name| tool.get(name).is_some())
+                };
+                Requested {
+                    model,
+                    // The largest of any spelling, so a second one can't
+                    // slip past the policy.
+                    max_output_tokens: GEMINI_GENERATION_CONFIG
+                        .iter()
+                        .filter_map(|config| body.get(config))
+                        .flat_map(|config| {
+                            GEMINI_MAX_OUTPUT_TOKENS
+                                .iter()
+                                .filter_map(|field| config.get(field)?.as_u64())
+                        })
+                        .max(),
+                    tools: tools.is_some_and(|tools| tools.iter().any(|tool| !grounds(tool))),
+                    grounding: tools.is_some_and(|tools| tools.iter().any(grounds)),
+                }
+            }
+            Dialect::OpenAi => Requested {
+                model,
+                max_output_tokens: ["max_completion_tokens", "max_tokens"]
+                    .iter()
+                    .find_map(|field| body.get(field)?.as_u64()),
+                tools: tools.is_some_and(|tools| !tools.is_empty())
+                    || body.get("functions").is_some(),
+                grounding: body.get("web_search_options").is_some(),
+            },
+        }
+    }
+
+    /// Caps a request that doesn't say how many output tokens it wants to
+    /// `max`.
+    pub fn cap_output_tokens(self, body: &mut Value, max: u64) {
+        if self.requested("", body).max_output_tokens.is_some() {
+            return;
+        }
+        match self {
+            Dialect::Gemini => {
+                if let Some(body) = body.as_object_mut() {
+                    // Written in the spelling the request already uses.
+                    let spelling = GEMINI_GENERATION_CONFIG
+                        .iter()
+                        .position(|config| body.contains_key(*config))
+                        .unwrap_or(0);
+                    let config = body
+                        .entry(GEMINI_GENERATION_CONFIG[spelling])
+                        .or_insert_with(|| json!({}));
+                    if let Some(config) = config.as_object_mut() {
+                        config.insert(GEMINI_MAX_OUTPUT_TOKENS[spelling].to_string(), max.into());
+                    }
+                }
+            }
+            Dialect::OpenAi => {
+                if let Some(body) = body.as_object_mut() {
+                    body.insert("max_tokens".to_string(), max.into());
+                }
+            }
+        }
+    }
+
+    pub fn error(self, status: u16, message: &str) -> Value {
+        match self {
+            Dialect::Gemini => json!({
+                "error": {"code": status, "message": message, "status": rpc_status(status)}
+            }),
+            Dialect::OpenAi => json!({
+                "error": {"message": message, "type": error_type(status), "code": status}
+            }),
+        }
+    }
+}
+
+/// Gemini tools that ground answers rather than let the model call out, in
+/// both the JSON and proto field spellings the API accepts.
+const GEMINI_GROUNDING_TOOLS: [&str; 6] = [
+    "googleSearch",
+    "google_search",
+    "googleSearchRetrieval",
+    "google_search_retrieval",
+    "urlContext",
+    "url_context",
+];
+
+/// `generationConfig` and its `maxOutputTokens`, in both the JSON and proto
+/// field spellings the API accepts.
+const GEMINI_GENERATION_CONFIG: [&str; 2] = ["generationConfig", "generation_config"];
+const GEMINI_MAX_OUTPUT_TOKENS: [&str; 2] = ["maxOutputTokens", "max_output_tokens"];
+
+/// The `google.rpc.Code` name Gemini reports along an HTTP status.
+fn rpc_status(status: u16) -> &'static str {
+    match status {
+        400 => "INVALID_ARGUMENT",
+        401 => "UNAUTHENTICATED",
+        403 => "PERMISSION_DENIED",
+        404 => "NOT_FOUND",
+        429 => "RESOURCE_EXHAUSTED",
+        503 => "UNAVAILABLE",
+        504 => "DEADLINE_EXCEEDED",
+        _ => "INTERNAL",
+    }
+}
+
+/// The `type` OpenAI reports along an HTTP status.
+fn error_type(status: u16) -> &'static str {
+    match status {
+        401 => "authentication_error",
+        403 => "permission_error",
+        429 => "rate_limit_error",
+        500.. => "upstream_error",
+        _ => "invalid_request_error",
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, body::to_bytes, web::Bytes};
+use colored::Colorize;
+use futures_util::{StreamExt, stream, stream::LocalBoxStream};
+use log::warn;
+use serde_json::Value;
+
+use super::dialect::Dialect;
+use super::juggle::{Juggled, juggle_excluding, stream_body};
+use crate::{
+    AppState,
+    utils::{Admission, Response, SseDecoder, UpstreamError, UsageTap, classify, in_band_error},
+};
+
+/// The message of an error body, in either dialect.
+pub fn error_message(body: &[u8]) -> Option<String> {
+    let value: Value = serde_json::from_slice(body).ok()?;
+    value
+        .pointer("/error/message")
+        .or_else(|| value.pointer("/0/error/message"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+struct Failure {
+    status: u16,
+    message: String,
+    /// The error event the upstream sent, relayed as is if the stream can't
+    /// recover.
+    event: Option<String>,
+}
+
+impl Failure {
+    async fn from_response(resp: HttpResponse) -> Self {
+        let status = resp.status().as_u16();
+        let body = to_bytes(resp.into_body()).await.unwrap_or_default();
+        Self {
+            status,
+            message: error_message(&body)
+                .unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned()),
+            event: None,
+        }
+    }
+}
+
+/// Relays an upstream SSE stream event by event, moving on to another key
+/// when it fails or sends an error event. Streams that already sent part of
+/// their answer only move on when `streaming.resume` is set, otherwise they
+/// end with an error event in `dialect`.
+pub fn relay(
+    data: Arc<AppState>,
+    admission: Admission,
+    dialect: Dialect,
+    model: String,
+    body: Value,
+    key: String,
+    resp: Response,
+) -> LocalBoxStream<'static, Result<Bytes, Infallible>> {
+    let upstream = stream_body(
+        &data,
+        admission.client().clone(),
+        key.clone(),
+        model.clone(),
+        resp,
+    );
+    let relay = Relay {
+        data,
+        admission,
+        dialect,
+        model,
+        body,
+        key,
+        failed: Vec::new(),
+        upstream: Some(upstream),
+        decoder: SseDecoder::default(),
+        events: VecDeque::new(),
+        text: String::new(),
+        started: false,
+        finished: false,
+        retries: 0,
+    };
+
+    stream::unfold(relay, |mut relay| async move {
+        let chunk = relay.next_chunk().await?;
+        Some((Ok(chunk), relay))
+    })
+    .boxed_local()
+}
+
+struct Relay {
+    data: Arc<AppState>,
+    /// Keeps the request in flight for the client until the stream ends.
+    admission: Admission,
+    dialect: Dialect,
+    model: String,
+    body: Value,
+    key: String,
+    /// Keys the stream already failed on.
+    failed: Vec<String>,
+    upstream: Option<UsageTap<Response>>,
+    decoder: SseDecoder,
+    events: VecDeque<String>,
+    /// The answer sent so far.
+    text: String,
+    started: bool,
+    finished: bool,
+    retries: u32,
+}
+
+impl Relay {
+    async fn next_chunk(&mut self) -> Option<Bytes> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                if let Some(status) = in_band_error(event.as_bytes()) {
+                    let failure = Failure {
+                        status,
+                        message: error_message(event.as_bytes())
+                            .unwrap_or_else(|| "Upstream error".to_string()),
+                        event: Some(event),
+                    };
+                    match self.recover(failure).await {
+                        Some(chunk) => return Some(chunk),
+                        None => continue,
+                    }
+                }
+
+                if let Ok(value) = serde_json::from_str::<Value>(&event) {
+                    self.text.push_str(&self.dialect.text(&value));
+                }
+                self.finished |= self.dialect.finishes(&event);
+                self.started = true;
+                return Some(sse_event(&event));
+            }
+
+            let Some(upstream) = self.upstream.as_mut() else {
+                if self.finished {
+                    return None;
+                }
+                let failure = Failure {
+                    status: 502,
+                    message: "Upstream stream ended before finishing".to_string(),
+                    event: None,
+                };
+                match self.recover(failure).await {
+                    Some(chunk) => return Some(chunk),
+                    None => continue,
+                }
+            };
+            match upstream.next().await {
+                Some(Ok(chunk)) => {
+                    let events = self.decoder.feed(&chunk);
+                    self.events.extend(events);
+                }
+                Some(Err(e)) => {
+                    let failure = Failure {
+                        status: 502,
+                        message: format!("Error reading upstream stream: {}", e),
+                        event: None,
+                    };
+                    if let Some(chunk) = self.recover(failure).await {
+                        return Some(chunk);
+                    }
+                }
+                None => {
+                    self.upstream = None;
+                    self.events.extend(self.decoder.finish());
+                }
+            }
+        }
+    }
+
+    /// Moves the stream on to another key if it may, returning `None`, or
+    /// returns the event that ends it.
+    async fn recover(&mut self, mut failure: Failure) -> Option<Bytes> {
+        self.upstream = None;
+        self.events.clear();
+        self.decoder = SseDecoder::default();
+        self.report(&failure).await;
+        self.failed.push(self.key.clone());
+
+        let streaming = &self.data.config.streaming;
+        if (!self.started || streaming.resume) && self.retries < streaming.max_retries {
+            self.retries += 1;
+            warn!(
+                "stream on key {} failed ({}), moving on to another key",
+                self.key.cyan(),
+                failure.message
+            );
+            match self.reconnect().await {
+                Ok(()) => return None,
+                Err(next) => failure = next,
+            }
+        }
+
+        // Nothing follows the error event.
+        self.finished = true;
+        let event = failure.event.unwrap_or_else(|| {
+            self.dialect
+                .error(failure.status, &failure.message)
+                .to_string()
+        });
+        Some(sse_event(&event))
+    }
+
+    /// Penalizes the key for errors that are its own, like `juggle` does for
+    /// errors that come before the stream.
+    async fn report(&self, failure: &Failure) {
+        let Some(event) = &failure.event else {
+            return;
+        };
+
+        match classify(failure.status, event.as_bytes()) {
+            UpstreamError::Ratelimited { quota, retry_after } => self
+                .data
+                .juggler
+                .write()
+                .await
+                .ratelimit(&self.key, &self.model, quota, retry_after),
+            UpstreamError::InvalidKey { .. } => self.data.juggler.write().await.remove(&self.key),
+            UpstreamError::Other => {}
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Failure> {
+        let body = match self.text.is_empty() {
+            true => self.body.clone(),
+            false => self.dialect.continuation(&self.body, &self.text),
+        };
+
+        let (data, dialect, model) = (&self.data, self.dialect, &self.model);
+        let juggled = juggle_excluding(data, model, &self.failed, |key| {
+            let body = &body;
+            async move { dialect.forward(data, &key, model, body).await }
+        })
+        .await
+        .map_err(|e| Failure {
+            status: e.as_response_error().status_code().as_u16(),
+            message: e.to_string(),
+            event: None,
+        })?;
+
+        match juggled {
+            Juggled::Forward { key, resp } => {
+                self.upstream = Some(stream_body(
+                    &self.data,
+                    self.admission.client().clone(),
+                    key.clone(),
+                    self.model.clone(),
+                    resp,
+                ));
+                self.key = key;
+                Ok(())
+            }
+            Juggled::Done(resp) => Err(Failure::from_response(resp).await),
+        }
+    }
+}
+
+fn sse_event(data: &str) -> Bytes {
+    Bytes::from(format!("data: {data}\n\n"))
+}
@@ -1,111 +1,202 @@
-use actix_web::{Error, HttpResponse, post, web};
-use futures_util::TryStreamExt;
-use log::error;
+use std::convert::Infallible;
+
+use actix_web::{Error, HttpResponse, post, web, web::Bytes};
+use futures_util::Stream;
 use serde::Deserialize;
-use serde_json::{Value, json};
+use serde_json::Value;
 
-use crate::{AppState, utils::Event};
+use super::auth::Authenticated;
+use super::dialect::Dialect;
+use super::failover::relay;
+use super::juggle::{Juggled, admit, admit_requested, juggle, read_body};
+use super::translate::{Generated, provide};
+use crate::{
+    AppState,
+    utils::{JsonArray, Requested, model_name},
+};
 
 #[derive(Deserialize)]
 struct Query {
-    key: String,
+    /// `sse` for server-sent events, otherwise the response streams as a JSON
+    /// array.
+    alt: Option<String>,
 }
 
 #[post("/v1beta/models/{model}:generateContent")]
 async fn completion(
+    Authenticated(client): Authenticated,
     path: web::Path<String>,
-    query: web::Query<Query>,
     body: web::Json<Value>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
-    let key = query.into_inner().key;
     let data = data.into_inner();
-
-    if key != data.config.api_key {
-        return Ok(HttpResponse::Unauthorized().json(json!({"error": "Invalid API key"})));
+    let model = data
+        .config
+        .resolve_model(model_name(&path.into_inner()))
+        .to_string();
+    let mut body = body.into_inner();
+    // Held until the response body has been read, so it counts as in flight.
+    let admission = match admit(Dialect::Gemini, &client, &model, &mut body) {
+        Ok(admission) => admission,
+        Err(resp) => return Ok(resp),
+    };
+    if let Some(provider) = data.config.provider(&model).cloned() {
+        let provided = provide(&data, admission, &provider, &model, &body, false).await;
+        return Ok(answer(provided, None));
     }
+    let juggled = juggle(&data, &model, |key| {
+        let (data, model, body) = (&data, &model, &body);
+        async move {
+            data.requester
+                .forward_gemini(&key, model, body, false)
+                .await
+        }
+    })
+    .await?;
 
-    let model = path.into_inner();
-    let body = body.into_inner();
-    let mut juggler = data.juggler.write().await;
-
-    loop {
-        let key = juggler.current().to_string();
-        match data
-            .requester
-            .forward_gemini(&key, &model, &body, false)
-            .await?
-        {
-            Event::Ok(resp) => return Ok(resp),
-            Event::Forward(mut resp) => {
-                let body_bytes = resp.body().await.map_err(|e| {
-                    actix_web::error::ErrorBadGateway(format!("Error reading response: {}", e))
-                })?;
-                return Ok(HttpResponse::build(resp.status()).body(body_bytes));
-            }
-            Event::Fail(e) => return Err(e),
-            Event::Retry => {
-                juggler
-                    .ratelimit(&key)
-                    .ok_or(actix_web::error::ErrorTooManyRequests(
-                        "All API keys are ratelimited",
-                    ))?;
-                continue;
-            }
-            Event::BadKey => {
-                error!("received indication of bad key, removing from rotation and retrying...");
-                juggler.remove(&key);
-                continue;
-            }
+    match juggled {
+        Juggled::Done(resp) => Ok(resp),
+        Juggled::Forward { key, mut resp } => {
+            let body_bytes = read_body(&data, &client, &key, &model, &mut resp).await?;
+            Ok(HttpResponse::build(resp.status()).body(body_bytes))
         }
     }
 }
 
 #[post("/v1beta/models/{model}:streamGenerateContent")]
 async fn stream_completion(
+    Authenticated(client): Authenticated,
     path: web::Path<String>,
     query: web::Query<Query>,
     body: web::Json<Value>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
-    let key = query.into_inner().key;
+    let Query { alt } = query.into_inner();
     let data = data.into_inner();
+    let model = data
+        .config
+        .resolve_model(model_name(&path.into_inner()))
+        .to_string();
+    let mut body = body.into_inner();
+    let admission = match admit(Dialect::Gemini, &client, &model, &mut body) {
+        Ok(admission) => admission,
+        Err(resp) => return Ok(resp),
+    };
+    if let Some(provider) = data.config.provider(&model).cloned() {
+        let provided = provide(&data, admission, &provider, &model, &body, true).await;
+        return Ok(answer(provided, alt.as_deref()));
+    }
+    let juggled = juggle(&data, &model, |key| {
+        let (data, model, body) = (&data, &model, &body);
+        async move { data.requester.forward_gemini(&key, model, body, true).await }
+    })
+    .await?;
 
-    if key != data.config.api_key {
-        return Ok(HttpResponse::Unauthorized().json(json!({"error": "Invalid API key"})));
+    match juggled {
+        Juggled::Done(resp) => Ok(resp),
+        Juggled::Forward { key, resp } => {
+            let stream = relay(data, admission, Dialect::Gemini, model, body, key, resp);
+            Ok(streaming(stream, alt.as_deref()))
+        }
     }
+}
+
+/// The response to a request a provider was asked for.
+fn answer(provided: Result<Generated, HttpResponse>, alt: Option<&str>) -> HttpResponse {
+    match provided {
+        Ok(Generated::Complete(answer)) => HttpResponse::Ok().json(answer),
+        Ok(Generated::Streaming(events)) => streaming(events, alt),
+        Err(resp) => resp,
+    }
+}
 
-    let model = path.into_inner();
+/// Streams SSE events to the client. The upstream is always asked for SSE,
+/// which arrives event by event, and reframed when the client wants a JSON
+/// array.
+fn streaming<S>(events: S, alt: Option<&str>) -> HttpResponse
+where
+    S: Stream<Item = Result<Bytes, Infallible>> + Unpin + 'static,
+{
+    match alt {
+        Some("sse") => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(events),
+        _ => HttpResponse::Ok()
+            .content_type("application/json")
+            .streaming(JsonArray::new(events)),
+    }
+}
+
+#[post("/v1beta/models/{model}:countTokens")]
+async fn count_tokens(
+    auth: Authenticated,
+    path: web::Path<String>,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    forward_method(auth, path, "countTokens", "countTokens", body, data).await
+}
+
+#[post("/v1beta/models/{model}:embedContent")]
+async fn embed_content(
+    auth: Authenticated,
+    path: web::Path<String>,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    forward_method(auth, path, "embedContent", "embed", body, data).await
+}
+
+#[post("/v1beta/models/{model}:batchEmbedContents")]
+async fn batch_embed_contents(
+    auth: Authenticated,
+    path: web::Path<String>,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    forward_method(auth, path, "batchEmbedContents", "embed", body, data).await
+}
+
+/// Forwards a call to one of the model's methods other than generation.
+/// Their quotas are separate from generation's, so keys are juggled for them
+/// under a name of their own, `{model}:{quota}`.
+async fn forward_method(
+    Authenticated(client): Authenticated,
+    path: web::Path<String>,
+    method: &str,
+    quota: &str,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let data = data.into_inner();
+
+    let model = data
+        .config
+        .resolve_model(model_name(&path.into_inner()))
+        .to_string();
+    let _admission = match admit_requested(Dialect::Gemini, &client, &Requested::model(&model)) {
+        Ok(admission) => admission,
+        Err(resp) => return Ok(resp),
+    };
+
+    let quota = format!("{model}:{quota}");
     let body = body.into_inner();
-    let mut juggler = data.juggler.write().await;
-
-    loop {
-        let key = juggler.current().to_string();
-        match data
-            .requester
-            .forward_gemini(&key, &model, &body, false)
-            .await?
-        {
-            Event::Ok(resp) => return Ok(resp),
-            Event::Forward(resp) => {
-                let stream = resp.into_stream();
-                let mut response = HttpResponse::Ok();
-                return Ok(response.streaming(stream));
-            }
-            Event::Fail(e) => return Err(e),
-            Event::Retry => {
-                juggler
-                    .ratelimit(&key)
-                    .ok_or(actix_web::error::ErrorTooManyRequests(
-                        "All API keys are ratelimited",
-                    ))?;
-                continue;
-            }
-            Event::BadKey => {
-                error!("received indication of bad key, removing from rotation and retrying...");
-                juggler.remove(&key);
-                continue;
-            }
+    let juggled = juggle(&data, &quota, |key| {
+        let (data, model, body) = (&data, &model, &body);
+        async move {
+            data.requester
+                .forward_method(&key, model, method, body)
+                .await
+        }
+    })
+    .await?;
+
+    match juggled {
+        Juggled::Done(resp) => Ok(resp),
+        Juggled::Forward { key, mut resp } => {
+            let body_bytes = read_body(&data, &client, &key, &quota, &mut resp).await?;
+            Ok(HttpResponse::build(resp.status()).body(body_bytes))
         }
     }
 }
@@ -0,0 +1,299 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_web::{Error, HttpResponse, web::Bytes};
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use futures_util::{Stream, StreamExt};
+use log::{debug, error, warn};
+use serde_json::Value;
+use tokio::time::{Instant, sleep, timeout_at};
+
+use crate::{
+    AppState,
+    utils::{
+        Admission, Client, Event, OverLimit, Requested, Response, Turn, UsageTap, total_tokens,
+    },
+};
+
+use super::dialect::Dialect;
+
+pub enum Juggled {
+    Done(HttpResponse),
+    /// A successful upstream response, still to be read, and the key that got
+    /// it.
+    Forward {
+        key: String,
+        resp: Response,
+    },
+}
+
+/// Runs `forward` with the best available key for `model` until the upstream
+/// gives an answer that isn't a key problem. The juggler is only locked while
+/// picking or reporting on a key, never while waiting for the upstream, so any
+/// number of requests can be in flight at once.
+pub async fn juggle<F, Fut>(data: &AppState, model: &str, forward: F) -> Result<Juggled, Error>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<Event, Error>>,
+{
+    juggle_excluding(data, model, &[], forward).await
+}
+
+/// Like [`juggle`], without ever picking one of the `exclude`d keys. When
+/// every key is ratelimited, waits in line for one to come back if the queue
+/// allows it.
+pub async fn juggle_excluding<F, Fut>(
+    data: &AppState,
+    model: &str,
+    exclude: &[String],
+    forward: F,
+) -> Result<Juggled, Error>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<Event, Error>>,
+{
+    // Held until the request is answered, so a retry doesn't lose its place
+    // at the front of the line.
+    let mut turn = None;
+    loop {
+        let selected = {
+            let mut juggler = data.juggler.write().await;
+            // Keys that come back go to requests already waiting first.
+            if turn.is_none() && data.queue.is_waiting(model) {
+                Err(juggler.next_available(model, exclude))
+            } else {
+                juggler
+                    .select(model, exclude)
+                    .ok_or_else(|| juggler.next_available(model, exclude))
+            }
+        };
+
+        let key = match selected {
+            Ok(key) => key,
+            Err(next_available) => match wait_for_key(data, model, next_available, &mut turn).await
+            {
+                Some(resp) => return Ok(Juggled::Done(resp)),
+                None => continue,
+            },
+        };
+
+        let started = Instant::now();
+        match forward(key.clone()).await? {
+            Event::Ok(resp) => return Ok(Juggled::Done(resp)),
+            Event::Forward(resp) => {
+                data.juggler
+                    .write()
+                    .await
+                    .record_latency(&key, model, started.elapsed());
+                return Ok(Juggled::Forward { key, resp });
+            }
+            Event::Fail(e) => return Err(e),
+            Event::Retry { quota, retry_after } => {
+                data.juggler
+                    .write()
+                    .await
+                    .ratelimit(&key, model, quota, retry_after);
+                continue;
+            }
+            Event::BadKey => {
+                error!("received indication of bad key, removing from rotation and retrying...");
+                data.juggler.write().await.remove(&key);
+                continue;
+            }
+        }
+    }
+}
+
+/// Reads a forwarded response to the end, counting the tokens it used
+/// against the key and the client.
+pub async fn read_body(
+    data: &AppState,
+    client: &Client,
+    key: &str,
+    model: &str,
+    resp: &mut Response,
+) -> Result<Bytes, Error> {
+    let body = resp
+        .body()
+        .await
+        .map_err(|e| actix_web::error::ErrorBadGateway(format!("Error reading response: {}", e)))?;
+
+    if let Some(tokens) = total_tokens(&body) {
+        client.record_tokens(tokens);
+        data.juggler.write().await.record_usage(key, model, tokens);
+    }
+
+    Ok(body)
+}
+
+/// Streams a forwarded response through, counting the tokens it used against
+/// the key and the client once it ends.
+pub fn stream_body(
+    data: &AppState,
+    client: Arc<Client>,
+    key: String,
+    model: String,
+    resp: Response,
+) -> UsageTap<Response> {
+    let juggler = data.juggler.clone();
+    UsageTap::new(resp, move |tokens| {
+        client.record_tokens(tokens);
+        actix_web::rt::spawn(async move {
+            juggler.write().await.record_usage(&key, &model, tokens);
+        });
+    })
+}
+
+/// Passes a response stream through, keeping the request in flight for the
+/// client until it ends or is dropped.
+pub struct InFlight<S> {
+    inner: S,
+    _admission: Admission,
+}
+
+impl<S> InFlight<S> {
+    pub fn new(inner: S, admission: Admission) -> Self {
+        Self {
+            inner,
+            _admission: admission,
+        }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for InFlight<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+/// Waits in line for a key for `model` to come back, or returns the response
+/// to fail with if it won't be back in time. Returns `None` when it's worth
+/// trying to get a key again.
+async fn wait_for_key(
+    data: &AppState,
+    model: &str,
+    next_available: Option<DateTime<Utc>>,
+    turn: &mut Option<(Turn, Instant)>,
+) -> Option<HttpResponse> {
+    let queue = &data.queue;
+
+    let Some((_, deadline)) = turn else {
+        if !queue.enabled() {
+            return Some(all_ratelimited(next_available));
+        }
+        let waiting = queue.is_waiting(model);
+        let comes_back_in_time = next_available.is_some_and(|at| {
+            (at - Utc::now())
+                .to_std()
+                .map_or(true, |wait| wait <= queue.max_wait)
+        });
+        if !waiting && !comes_back_in_time {
+            return Some(all_ratelimited(next_available));
+        }
+
+        let Some(place) = queue.join(model) else {
+            return Some(queue_full(next_available));
+        };
+        debug!("all keys ratelimited for {}, waiting in line", model);
+        let deadline = Instant::now() + queue.max_wait;
+        return match timeout_at(deadline, place.front()).await {
+            Ok(front) => {
+                *turn = Some((front, deadline));
+                None
+            }
+            Err(_) => Some(all_ratelimited(next_available)),
+        };
+    };
+
+    // First in line, the earliest key to come back is ours.
+    let Some(at) = next_available else {
+        return Some(all_ratelimited(None));
+    };
+    let wait = (at - Utc::now()).to_std().unwrap_or_default();
+    if Instant::now() + wait > *deadline {
+        return Some(all_ratelimited(Some(at)));
+    }
+    sleep(wait).await;
+    None
+}
+
+/// Lets a client's request through if its policy allows it and it is within
+/// its limits, in that order, so requests it may not make don't count against
+/// them. Requests that don't say how many output tokens they want are capped
+/// to the policy's maximum.
+pub fn admit(
+    dialect: Dialect,
+    client: &Arc<Client>,
+    model: &str,
+    body: &mut Value,
+) -> Result<Admission, HttpResponse> {
+    let admission = admit_requested(dialect, client, &dialect.requested(model, body))?;
+    if let Some(max) = client.policy.max_output_tokens {
+        dialect.cap_output_tokens(body, max);
+    }
+    Ok(admission)
+}
+
+/// Like [`admit`], for requests that don't generate anything and so are only
+/// checked for what they say they ask for.
+pub fn admit_requested(
+    dialect: Dialect,
+    client: &Arc<Client>,
+    requested: &Requested,
+) -> Result<Admission, HttpResponse> {
+    if let Err(message) = client.policy.check(requested) {
+        warn!("client {} was refused: {message}", client.name.cyan());
+        return Err(HttpResponse::Forbidden().json(dialect.error(403, &message)));
+    }
+    client
+        .admit()
+        .map_err(|over| over_limit(dialect, client, over))
+}
+
+/// Lets a request that names no model through if the client is within its
+/// limits. Such a request asks for nothing a policy could refuse.
+pub fn admit_unnamed(dialect: Dialect, client: &Arc<Client>) -> Result<Admission, HttpResponse> {
+    client
+        .admit()
+        .map_err(|over| over_limit(dialect, client, over))
+}
+
+/// Turns a client away for going over one of its own limits, before any key
+/// is spent on it.
+fn over_limit(dialect: Dialect, client: &Client, over: OverLimit) -> HttpResponse {
+    warn!(
+        "client {} is over its {} limit",
+        client.name.cyan(),
+        over.limit
+    );
+    let mut response = HttpResponse::TooManyRequests();
+    if let Some(at) = over.retry_after {
+        let seconds = (at - Utc::now()).num_seconds().max(1);
+        response.insert_header(("Retry-After", seconds.to_string()));
+    }
+    response.json(dialect.error(
+        429,
+        &format!("Client {} is over its {} limit", client.name, over.limit),
+    ))
+}
+
+fn queue_full(next_available: Option<DateTime<Utc>>) -> HttpResponse {
+    let seconds = next_available.map_or(1, |at| (at - Utc::now()).num_seconds().max(1));
+    HttpResponse::ServiceUnavailable()
+        .insert_header(("Retry-After", seconds.to_string()))
+        .body("Too many requests waiting for an API key")
+}
+
+fn all_ratelimited(next_available: Option<DateTime<Utc>>) -> HttpResponse {
+    let mut response = HttpResponse::TooManyRequests();
+    if let Some(at) = next_available {
+        let seconds = (at - Utc::now()).num_seconds().max(1);
+        response.insert_header(("Retry-After", seconds.to_string()));
+    }
+    response.body("All API keys are ratelimited")
+}
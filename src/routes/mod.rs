@@ -1,7 +1,20 @@
+mod anthropic;
+mod auth;
+mod dialect;
+mod failover;
 mod gemini;
+mod juggle;
+mod models;
+mod ollama;
 mod openai;
+mod responses;
 mod status;
+mod translate;
 
+pub use anthropic::*;
 pub use gemini::*;
+pub use models::*;
+pub use ollama::*;
 pub use openai::*;
+pub use responses::*;
 pub use status::*;
@@ -0,0 +1,171 @@
+use actix_web::{Error, HttpResponse, get, web};
+use serde_json::{Value, json};
+
+use super::auth::Authenticated;
+use super::dialect::Dialect;
+use super::juggle::{Juggled, juggle, read_body};
+use crate::{
+    AppState,
+    utils::{Client, model_name},
+};
+
+/// Name the key juggler tracks listing requests under, apart from any model.
+const LISTING_QUOTA: &str = "models";
+
+#[get("/v1beta/models")]
+async fn list_models(
+    Authenticated(client): Authenticated,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    Ok(match listing(&data, &client, Dialect::Gemini).await? {
+        Ok(models) => HttpResponse::Ok().json(json!({"models": models})),
+        Err(resp) => resp,
+    })
+}
+
+#[get("/v1beta/models/{model}")]
+async fn get_model(
+    Authenticated(client): Authenticated,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    find(&data, &client, Dialect::Gemini, &path.into_inner()).await
+}
+
+#[get("/v1beta/openai/models")]
+async fn openai_list_models(
+    Authenticated(client): Authenticated,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    Ok(match listing(&data, &client, Dialect::OpenAi).await? {
+        Ok(models) => HttpResponse::Ok().json(json!({"object": "list", "data": models})),
+        Err(resp) => resp,
+    })
+}
+
+#[get("/v1beta/openai/models/{model:.*}")]
+async fn openai_get_model(
+    Authenticated(client): Authenticated,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    find(&data, &client, Dialect::OpenAi, &path.into_inner()).await
+}
+
+/// Where a dialect's listing is fetched from, the field holding its models
+/// and the field naming each of them.
+fn shape(dialect: Dialect) -> (&'static str, &'static str, &'static str) {
+    match dialect {
+        Dialect::Gemini => ("/v1beta/models", "models", "name"),
+        Dialect::OpenAi => ("/v1beta/openai/models", "data", "id"),
+    }
+}
+
+async fn find(
+    data: &AppState,
+    client: &Client,
+    dialect: Dialect,
+    model: &str,
+) -> Result<HttpResponse, Error> {
+    let models = match listing(data, client, dialect).await? {
+        Ok(models) => models,
+        Err(resp) => return Ok(resp),
+    };
+
+    let (_, _, id) = shape(dialect);
+    let model = model_name(model);
+    Ok(
+        match models
+            .into_iter()
+            .find(|entry| entry[id].as_str().map(model_name) == Some(model))
+        {
+            Some(entry) => HttpResponse::Ok().json(entry),
+            None => HttpResponse::NotFound()
+                .json(dialect.error(404, &format!("Model {model} was not found"))),
+        },
+    )
+}
+
+/// The models the upstream lists for `dialect`, with aliases added and,
+/// if configured, only those `client` may call.
+pub(super) async fn listing(
+    data: &AppState,
+    client: &Client,
+    dialect: Dialect,
+) -> Result<Result<Vec<Value>, HttpResponse>, Error> {
+    let (path, field, id) = shape(dialect);
+    let listing = match data.models.get(path) {
+        Some(listing) => listing,
+        None => match fetch(data, client, dialect).await? {
+            Ok(listing) => {
+                data.models.put(path, listing.clone());
+                listing
+            }
+            Err(resp) => return Ok(Err(resp)),
+        },
+    };
+
+    let mut models = match listing.get(field) {
+        Some(Value::Array(models)) => models.clone(),
+        _ => Vec::new(),
+    };
+    let name = |entry: &Value| entry[id].as_str().map(model_name).map(str::to_string);
+
+    let mut aliases: Vec<_> = data.config.models.aliases.iter().collect();
+    aliases.sort();
+    for (alias, target) in aliases {
+        let Some(mut entry) = models
+            .iter()
+            .find(|entry| name(entry).as_deref() == Some(target.as_str()))
+            .cloned()
+        else {
+            continue;
+        };
+        let prefixed = entry[id]
+            .as_str()
+            .is_some_and(|name| name.starts_with("models/"));
+        entry[id] = match prefixed {
+            true => format!("models/{alias}"),
+            false => alias.clone(),
+        }
+        .into();
+        models.push(entry);
+    }
+
+    // Aliases are allowed whenever the model they stand for is, as requests
+    // naming them are checked against that model.
+    if data.config.models.filter_by_policy {
+        models.retain(|entry| {
+            name(entry).is_some_and(|model| {
+                client
+                    .policy
+                    .allows_model(data.config.resolve_model(&model))
+            })
+        });
+    }
+    Ok(Ok(models))
+}
+
+/// Fetches the listing from the upstream with whichever key is healthy.
+async fn fetch(
+    data: &AppState,
+    client: &Client,
+    dialect: Dialect,
+) -> Result<Result<Value, HttpResponse>, Error> {
+    let openai = matches!(dialect, Dialect::OpenAi);
+    let juggled = juggle(data, LISTING_QUOTA, |key| async move {
+        data.requester.list_models(&key, openai).await
+    })
+    .await?;
+
+    match juggled {
+        Juggled::Done(resp) => Ok(Err(resp)),
+        Juggled::Forward { key, mut resp } => {
+            let body = read_body(data, client, &key, LISTING_QUOTA, &mut resp).await?;
+            let listing = serde_json::from_slice(&body).map_err(|e| {
+                actix_web::error::ErrorBadGateway(format!("Invalid model listing: {}", e))
+            })?;
+            Ok(Ok(listing))
+        }
+    }
+}
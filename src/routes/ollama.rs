@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use actix_web::{Error, HttpResponse, get, post, web};
+use serde_json::{Value, json};
+
+use super::auth::Authenticated;
+use super::dialect::Dialect;
+use super::models::listing;
+use super::translate::ollama::{self, Endpoint};
+use super::translate::{Generated, Translated, call_method, generate, reshape_error};
+use crate::{AppState, utils::Client};
+
+#[post("/api/chat")]
+async fn ollama_chat(
+    Authenticated(client): Authenticated,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let body = body.into_inner();
+    let gemini = ollama::chat_request(&body);
+    Ok(answer(data, client, Endpoint::Chat, &body, gemini).await)
+}
+
+#[post("/api/generate")]
+async fn ollama_generate(
+    Authenticated(client): Authenticated,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let body = body.into_inner();
+    let gemini = ollama::generate_request(&body);
+    Ok(answer(data, client, Endpoint::Generate, &body, gemini).await)
+}
+
+/// Runs `gemini`, the translation of `body`, and answers in the shape of
+/// `endpoint`. Ollama streams unless told not to.
+async fn answer(
+    data: web::Data<AppState>,
+    client: Arc<Client>,
+    endpoint: Endpoint,
+    body: &Value,
+    gemini: Result<Value, String>,
+) -> HttpResponse {
+    let data = data.into_inner();
+    let requested = ollama::model(body).to_string();
+    let model = data.config.resolve_model(&requested).to_string();
+    let stream = body.get("stream").and_then(Value::as_bool).unwrap_or(true);
+
+    let gemini = match gemini {
+        Ok(gemini) => gemini,
+        Err(message) => return HttpResponse::BadRequest().json(ollama::error(400, &message)),
+    };
+    let translator = ollama::Stream::new(endpoint, &requested);
+
+    match generate(data, &client, model, gemini, stream).await {
+        Ok(Generated::Complete(answer)) => HttpResponse::Ok().json(translator.complete(&answer)),
+        Ok(Generated::Streaming(events)) => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(Translated::new(events, translator)),
+        Err(resp) => reshape_error(resp, ollama::error).await,
+    }
+}
+
+#[get("/api/tags")]
+async fn ollama_tags(
+    Authenticated(client): Authenticated,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    Ok(match listing(&data, &client, Dialect::Gemini).await? {
+        Ok(models) => {
+            let models: Vec<Value> = models.iter().filter_map(ollama::tag).collect();
+            HttpResponse::Ok().json(json!({"models": models}))
+        }
+        Err(resp) => reshape_error(resp, ollama::error).await,
+    })
+}
+
+#[post("/api/embed")]
+async fn ollama_embed(
+    Authenticated(client): Authenticated,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let started = Instant::now();
+    let body = body.into_inner();
+    let requested = ollama::model(&body);
+    let model = data.config.resolve_model(requested).to_string();
+
+    let gemini = match ollama::embed_request(&body, &model) {
+        Ok(gemini) => gemini,
+        Err(message) => return HttpResponse::BadRequest().json(ollama::error(400, &message)),
+    };
+    // Counted against the same quota as the native embedding routes.
+    let quota = format!("{model}:embed");
+    match call_method(&data, &client, &model, "batchEmbedContents", &quota, gemini).await {
+        Ok(embedded) => {
+            HttpResponse::Ok().json(ollama::embed_response(&embedded, requested, started))
+        }
+        Err(resp) => reshape_error(resp, ollama::error).await,
+    }
+}
@@ -1,74 +1,212 @@
-use actix_web::{Error, HttpRequest, HttpResponse, post, web};
-use futures_util::TryStreamExt;
-use log::error;
-use serde_json::{Value, json};
-
-use crate::{AppState, utils::Event};
-
-fn extract_bearer_token(req: &HttpRequest) -> Option<String> {
-    req.headers()
-        .get("Authorization")?
-        .to_str()
-        .ok()?
-        .strip_prefix("Bearer ")
-        .map(|s| s.to_string())
-}
+use std::sync::Arc;
+
+use actix_web::{Error, HttpRequest, HttpResponse, http::header, post, web};
+use serde_json::Value;
+
+use super::auth::Authenticated;
+use super::dialect::Dialect;
+use super::failover::relay;
+use super::juggle::{
+    InFlight, Juggled, admit, admit_requested, admit_unnamed, juggle, read_body, stream_body,
+};
+use super::translate::{Generated, Translated, chat, generate, reshape_error};
+use crate::{
+    AppState,
+    utils::{Client, Requested, model_name},
+};
+
+/// OpenAI-compatible endpoints other than chat completions that are forwarded
+/// as they are.
+const FORWARDED_PATHS: [&str; 5] = [
+    "embeddings",
+    "images/generations",
+    "audio/speech",
+    "audio/transcriptions",
+    "audio/translations",
+];
 
 #[post("/v1beta/openai/chat/completions")]
 async fn openai_completion(
-    req: HttpRequest,
+    Authenticated(client): Authenticated,
     body: web::Json<Value>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
-    let key = extract_bearer_token(&req).ok_or_else(|| {
-        actix_web::error::ErrorUnauthorized("Missing or invalid Authorization header")
-    })?;
-
     let data = data.into_inner();
 
-    if key != data.config.api_key {
-        return Ok(HttpResponse::Unauthorized().json(json!({"error": "Invalid API key"})));
-    }
-
-    let body = body.into_inner();
+    let mut body = body.into_inner();
     let is_streaming = body
         .get("stream")
         .and_then(|v| v.as_bool())
         .unwrap_or(false);
 
-    let mut juggler = data.juggler.write().await;
-
-    loop {
-        let key = juggler.current().to_string();
-        match data.requester.forward_openai(&key, &body).await? {
-            Event::Ok(resp) => return Ok(resp),
-            Event::Forward(mut resp) => match is_streaming {
-                true => {
-                    let stream = resp.into_stream();
-                    let mut response = HttpResponse::Ok();
-                    return Ok(response.streaming(stream));
-                }
-                false => {
-                    let body_bytes = resp.body().await.map_err(|e| {
-                        actix_web::error::ErrorBadGateway(format!("Error reading response: {}", e))
-                    })?;
-                    return Ok(HttpResponse::build(resp.status()).body(body_bytes));
-                }
-            },
-            Event::Fail(e) => return Err(e),
-            Event::Retry => {
-                juggler
-                    .ratelimit(&key)
-                    .ok_or(actix_web::error::ErrorTooManyRequests(
-                        "All API keys are ratelimited",
-                    ))?;
-                continue;
+    let requested = body
+        .get("model")
+        .and_then(|v| v.as_str())
+        .map(model_name)
+        .unwrap_or_default()
+        .to_string();
+    let model = data.config.resolve_model(&requested).to_string();
+    if data.config.native_openai(&model) {
+        return Ok(native_completion(data, &client, &body, &requested, model, is_streaming).await);
+    }
+    if model != requested {
+        body["model"] = model.clone().into();
+    }
+    let admission = match admit(Dialect::OpenAi, &client, &model, &mut body) {
+        Ok(admission) => admission,
+        Err(resp) => return Ok(resp),
+    };
+
+    let juggled = juggle(&data, &model, |key| {
+        let (data, body) = (&data, &body);
+        async move { data.requester.forward_openai(&key, body).await }
+    })
+    .await?;
+
+    match juggled {
+        Juggled::Done(resp) => Ok(resp),
+        Juggled::Forward { key, mut resp } => match is_streaming {
+            true => Ok(HttpResponse::Ok()
+                .content_type("text/event-stream")
+                .insert_header(("Cache-Control", "no-cache"))
+                .streaming(relay(
+                    data,
+                    admission,
+                    Dialect::OpenAi,
+                    model,
+                    body,
+                    key,
+                    resp,
+                ))),
+            false => {
+                let body_bytes = read_body(&data, &client, &key, &model, &mut resp).await?;
+                Ok(HttpResponse::build(resp.status()).body(body_bytes))
             }
-            Event::BadKey => {
-                error!("received indication of bad key, removing from rotation and retrying...");
-                juggler.remove(&key);
-                continue;
+        },
+    }
+}
+
+/// Serves a chat completion through native `generateContent`, for models
+/// configured under `openai`.
+async fn native_completion(
+    data: Arc<AppState>,
+    client: &Arc<Client>,
+    body: &Value,
+    requested: &str,
+    model: String,
+    stream: bool,
+) -> HttpResponse {
+    let gemini = match chat::request(body) {
+        Ok(gemini) => gemini,
+        Err(message) => return HttpResponse::BadRequest().json(chat::error(400, &message)),
+    };
+
+    match generate(data, client, model, gemini, stream).await {
+        Ok(Generated::Complete(answer)) => {
+            HttpResponse::Ok().json(chat::response(&answer, requested))
+        }
+        Ok(Generated::Streaming(events)) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(Translated::new(events, chat::Stream::new(body, requested))),
+        Err(resp) => reshape_error(resp, chat::error).await,
+    }
+}
+
+#[post("/v1beta/openai/{path:.*}")]
+async fn openai_forward(
+    Authenticated(client): Authenticated,
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let data = data.into_inner();
+    let path = path.into_inner();
+    if !FORWARDED_PATHS.contains(&path.as_str()) {
+        return Ok(HttpResponse::NotFound()
+            .json(Dialect::OpenAi.error(404, &format!("Unknown endpoint /v1beta/openai/{path}"))));
+    }
+
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let (model, body) = requested_model(&data, content_type.as_deref(), body);
+    // Requests that don't name a model are juggled under the endpoint's name.
+    let quota = model.clone().unwrap_or_else(|| path.clone());
+    let admitted = match &model {
+        Some(model) => admit_requested(Dialect::OpenAi, &client, &Requested::model(model)),
+        None => admit_unnamed(Dialect::OpenAi, &client),
+    };
+    let admission = match admitted {
+        Ok(admission) => admission,
+        Err(resp) => return Ok(resp),
+    };
+
+    let juggled = juggle(&data, &quota, |key| {
+        let (data, path, content_type, body) = (&data, &path, &content_type, body.clone());
+        async move {
+            data.requester
+                .forward_openai_path(&key, path, content_type.as_deref(), body)
+                .await
+        }
+    })
+    .await?;
+
+    match juggled {
+        Juggled::Done(resp) => Ok(resp),
+        Juggled::Forward { key, resp } => {
+            let mut response = HttpResponse::build(resp.status());
+            if let Some(content_type) = resp.headers().get(header::CONTENT_TYPE) {
+                response.insert_header((header::CONTENT_TYPE, content_type.clone()));
             }
+            // Responses can be large or binary, like generated speech, so they
+            // stream through, keeping the request in flight until they end.
+            let body = stream_body(&data, client, key, quota, resp);
+            Ok(response.streaming(InFlight::new(body, admission)))
         }
     }
 }
+
+/// The model a forwarded request names, following aliases, and its body with
+/// the alias replaced. Uploads only have the model read from their form, and
+/// are passed on as they are.
+fn requested_model(
+    data: &AppState,
+    content_type: Option<&str>,
+    body: web::Bytes,
+) -> (Option<String>, web::Bytes) {
+    if content_type.is_some_and(|content_type| content_type.starts_with("multipart/form-data")) {
+        return (form_field(&body, "model"), body);
+    }
+
+    let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+        return (None, body);
+    };
+    let Some(requested) = json.get("model").and_then(Value::as_str).map(model_name) else {
+        return (None, body);
+    };
+    let model = data.config.resolve_model(requested).to_string();
+    if model == requested {
+        return (Some(model), body);
+    }
+    json["model"] = model.clone().into();
+    (Some(model), web::Bytes::from(json.to_string()))
+}
+
+/// The value of a plain text field in a multipart form.
+fn form_field(body: &[u8], name: &str) -> Option<String> {
+    let marker = format!("name=\"{name}\"");
+    let start = find(body, marker.as_bytes())? + marker.len();
+    let value_start = start + find(&body[start..], b"\r\n\r\n")? + 4;
+    let value_len = find(&body[value_start..], b"\r\n")?;
+    String::from_utf8(body[value_start..value_start + value_len].to_vec()).ok()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -0,0 +1,90 @@
+use actix_web::{Error, HttpResponse, delete, get, post, web};
+use serde_json::{Value, json};
+
+use super::auth::Authenticated;
+use super::translate::responses::{self, Keep};
+use super::translate::{Generated, Translated, generate, reshape_error};
+use crate::{AppState, utils::model_name};
+
+#[post("/v1/responses")]
+async fn create_response(
+    Authenticated(client): Authenticated,
+    body: web::Json<Value>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let data = data.into_inner();
+    let body = body.into_inner();
+    let requested = body
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let model = data
+        .config
+        .resolve_model(model_name(&requested))
+        .to_string();
+    let stream = body.get("stream").and_then(Value::as_bool).unwrap_or(false);
+
+    let history = match body.get("previous_response_id").and_then(Value::as_str) {
+        Some(id) => match data.conversations.get(id, &client.name) {
+            Some(previous) => previous.contents.clone(),
+            None => return Ok(not_found(id)),
+        },
+        None => Vec::new(),
+    };
+    let gemini = match responses::request(&body, &history) {
+        Ok(gemini) => gemini,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(responses::error(400, &message))),
+    };
+
+    let keep = (body.get("store").and_then(Value::as_bool) != Some(false)).then(|| Keep {
+        store: data.conversations.clone(),
+        client: client.name.clone(),
+        contents: gemini["contents"].as_array().cloned().unwrap_or_default(),
+    });
+    let translator = responses::Stream::new(&body, &requested, keep);
+
+    Ok(match generate(data, &client, model, gemini, stream).await {
+        Ok(Generated::Complete(answer)) => HttpResponse::Ok().json(translator.complete(&answer)),
+        Ok(Generated::Streaming(events)) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(Translated::new(events, translator)),
+        Err(resp) => reshape_error(resp, responses::error).await,
+    })
+}
+
+#[get("/v1/responses/{id}")]
+async fn get_response(
+    Authenticated(client): Authenticated,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    match data.conversations.get(&id, &client.name) {
+        Some(stored) => HttpResponse::Ok().json(&stored.response),
+        None => not_found(&id),
+    }
+}
+
+#[delete("/v1/responses/{id}")]
+async fn delete_response(
+    Authenticated(client): Authenticated,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    match data.conversations.remove(&id, &client.name) {
+        true => HttpResponse::Ok().json(json!({"id": id, "object": "response", "deleted": true})),
+        false => not_found(&id),
+    }
+}
+
+/// Responses are only found by the client that made them, for as long as
+/// they're stored.
+fn not_found(id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(responses::error(
+        404,
+        &format!("Response with id '{id}' not found."),
+    ))
+}
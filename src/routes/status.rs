@@ -1,37 +1,22 @@
-use actix_web::{HttpRequest, HttpResponse, get, web};
+use actix_web::{HttpResponse, get, web};
 use serde_json::json;
 
+use super::auth::Authenticated;
 use crate::AppState;
 
 #[get("/status")]
-async fn status(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
-    let auth_header = req.headers().get("Authorization");
-    let is_authenticated = match auth_header {
-        Some(header) => match header.to_str() {
-            Ok(value) => {
-                if let Some(token) = value.strip_prefix("Bearer ") {
-                    token == data.config.api_key
-                } else {
-                    false
-                }
-            }
-            Err(_) => false,
-        },
-        None => false,
+async fn status(_: Authenticated, data: web::Data<AppState>) -> HttpResponse {
+    let (statuses, models) = {
+        let mut juggler = data.juggler.write().await;
+        (juggler.get_status(), juggler.get_model_status())
     };
 
-    if !is_authenticated {
-        return HttpResponse::Unauthorized()
-            .json(json!({"error": "missing or invalid authorization header"}));
-    }
-
-    let mut juggler = data.juggler.write().await;
-    let statuses = juggler.get_status();
-
     HttpResponse::Ok().json(json!({
         "keys": statuses,
+        "models": models,
         "total_keys": statuses.len(),
         "active_keys": statuses.iter().filter(|s| !s.is_ratelimited).count(),
         "ratelimited_keys": statuses.iter().filter(|s| s.is_ratelimited).count(),
+        "clients": data.clients.get_status(),
     }))
 }
@@ -0,0 +1,495 @@
+//! The Anthropic Messages API.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value, json};
+
+use super::{Translator, Usage, blocked, filtered, finish_reason, parts, random_id};
+
+/// An `error` body as the Messages API sends it.
+pub fn error(status: u16, message: &str) -> Value {
+    let kind = match status {
+        400 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        503 | 529 => "overloaded_error",
+        _ => "api_error",
+    };
+    json!({"type": "error", "error": {"type": kind, "message": message}})
+}
+
+/// Translates a Messages request into a `generateContent` body.
+pub fn request(body: &Value) -> Result<Value, String> {
+    let messages = body
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("messages: field required")?;
+
+    // Tool results only carry the id of the call they answer, while Gemini
+    // wants the function's name too.
+    let mut tool_names = HashMap::new();
+    let mut contents = Vec::new();
+    for message in messages {
+        let role = match message.get("role").and_then(Value::as_str) {
+            Some("user") => "user",
+            Some("assistant") => "model",
+            role => return Err(format!("messages: unexpected role {role:?}")),
+        };
+        let parts = content_parts(message.get("content"), &mut tool_names)?;
+        contents.push(json!({"role": role, "parts": parts}));
+    }
+
+    let mut gemini = Map::new();
+    gemini.insert("contents".into(), contents.into());
+    let system = match body.get("system") {
+        Some(Value::String(text)) => vec![json!({"text": text})],
+        Some(Value::Array(blocks)) => blocks
+            .iter()
+            .filter_map(|block| block.get("text"))
+            .map(|text| json!({"text": text}))
+            .collect(),
+        _ => Vec::new(),
+    };
+    if !system.is_empty() {
+        gemini.insert("systemInstruction".into(), json!({"parts": system}));
+    }
+    if let Some(tools) = body.get("tools").and_then(Value::as_array) {
+        gemini.insert("tools".into(), self::tools(tools)?.into());
+    }
+    if let Some(choice) = body.get("tool_choice") {
+        let (mode, only) = match choice.get("type").and_then(Value::as_str) {
+            Some("any") => ("ANY", None),
+            Some("tool") => ("ANY", choice.get("name")),
+            Some("none") => ("NONE", None),
+            _ => ("AUTO", None),
+        };
+        let mut config = json!({"mode": mode});
+        if let Some(name) = only {
+            config["allowedFunctionNames"] = json!([name]);
+        }
+        gemini.insert(
+            "toolConfig".into(),
+            json!({"functionCallingConfig": config}),
+        );
+    }
+
+    let mut config = Map::new();
+    for (from, to) in [
+        ("max_tokens", "maxOutputTokens"),
+        ("temperature", "temperature"),
+        ("top_p", "topP"),
+        ("top_k", "topK"),
+        ("stop_sequences", "stopSequences"),
+    ] {
+        if let Some(value) = body.get(from) {
+            config.insert(to.into(), value.clone());
+        }
+    }
+    if let Some(thinking) = body.get("thinking")
+        && thinking.get("type").and_then(Value::as_str) == Some("enabled")
+    {
+        let mut thinking_config = json!({"includeThoughts": true});
+        if let Some(budget) = thinking.get("budget_tokens") {
+            thinking_config["thinkingBudget"] = budget.clone();
+        }
+        config.insert("thinkingConfig".into(), thinking_config);
+    }
+    if !config.is_empty() {
+        gemini.insert("generationConfig".into(), config.into());
+    }
+
+    Ok(gemini.into())
+}
+
+fn content_parts(
+    content: Option<&Value>,
+    tool_names: &mut HashMap<String, String>,
+) -> Result<Vec<Value>, String> {
+    let blocks = match content {
+        Some(Value::String(text)) => return Ok(vec![json!({"text": text})]),
+        Some(Value::Array(blocks)) => blocks,
+        _ => return Err("messages: content must be a string or a list of blocks".into()),
+    };
+
+    let mut parts = Vec::new();
+    // Thought signatures come back on thinking blocks, and belong to the part
+    // that follows them.
+    let mut signature = None;
+    for block in blocks {
+        let mut part = match block.get("type").and_then(Value::as_str) {
+            Some("text") => json!({"text": block.get("text").cloned().unwrap_or_default()}),
+            Some("image" | "document") => match media_part(block) {
+                Some(part) => part,
+                None => continue,
+            },
+            Some("tool_use") => {
+                let id = block.get("id").and_then(Value::as_str).unwrap_or_default();
+                let name = block
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                tool_names.insert(id.to_string(), name.to_string());
+                json!({"functionCall": {
+                    "id": id,
+                    "name": name,
+                    "args": block.get("input").cloned().unwrap_or_else(|| json!({})),
+                }})
+            }
+            Some("tool_result") => {
+                let id = block
+                    .get("tool_use_id")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let (text, media) = tool_result_content(block.get("content"));
+                let outcome = match block.get("is_error").and_then(Value::as_bool) {
+                    Some(true) => json!({"error": text}),
+                    _ => json!({"content": text}),
+                };
+                parts.push(json!({"functionResponse": {
+                    "id": id,
+                    "name": tool_names.get(id).cloned().unwrap_or_default(),
+                    "response": outcome,
+                }}));
+                parts.extend(media);
+                continue;
+            }
+            Some("thinking") => {
+                signature = block.get("signature").filter(|s| s != &"").cloned();
+                continue;
+            }
+            _ => continue,
+        };
+        if let Some(signature) = signature.take() {
+            part["thoughtSignature"] = signature;
+        }
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+/// An image or document block as inline data or a file reference.
+fn media_part(block: &Value) -> Option<Value> {
+    let source = block.get("source")?;
+    match source.get("type").and_then(Value::as_str)? {
+        "base64" => Some(json!({"inlineData": {
+            "mimeType": source.get("media_type")?,
+            "data": source.get("data")?,
+        }})),
+        "url" => Some(json!({"fileData": {"fileUri": source.get("url")?}})),
+        "text" => Some(json!({"text": source.get("data")?})),
+        _ => None,
+    }
+}
+
+/// The text of a tool result, and any images in it, which Gemini takes as
+/// parts of their own.
+fn tool_result_content(content: Option<&Value>) -> (String, Vec<Value>) {
+    match content {
+        Some(Value::String(text)) => (text.clone(), Vec::new()),
+        Some(Value::Array(blocks)) => {
+            let text = blocks
+                .iter()
+                .filter_map(|block| block.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let media = blocks
+                .iter()
+                .filter(|block| block.get("type").and_then(Value::as_str) == Some("image"))
+                .filter_map(media_part)
+                .collect();
+            (text, media)
+        }
+        _ => (String::new(), Vec::new()),
+    }
+}
+
+fn tools(tools: &[Value]) -> Result<Vec<Value>, String> {
+    let mut declarations = Vec::new();
+    let mut gemini = Vec::new();
+    for tool in tools {
+        match tool.get("type").and_then(Value::as_str) {
+            None | Some("custom") => declarations.push(json!({
+                "name": tool.get("name"),
+                "description": tool.get("description").cloned().unwrap_or_default(),
+                "parametersJsonSchema": tool.get("input_schema").cloned().unwrap_or_else(|| json!({"type": "object"})),
+            })),
+            Some(kind) if kind.starts_with("web_search") => {
+                gemini.push(json!({"googleSearch": {}}));
+            }
+            Some(kind) => return Err(format!("tools: unsupported tool type {kind}")),
+        }
+    }
+    if !declarations.is_empty() {
+        gemini.push(json!({"functionDeclarations": declarations}));
+    }
+    Ok(gemini)
+}
+
+/// Why a Gemini answer stopped, as a Messages `stop_reason`.
+fn stop_reason(finish_reason: Option<&str>, used_tools: bool) -> &'static str {
+    match finish_reason {
+        _ if used_tools => "tool_use",
+        Some("MAX_TOKENS") => "max_tokens",
+        Some(reason) if filtered(reason) => "refusal",
+        _ => "end_turn",
+    }
+}
+
+fn usage(usage: Usage) -> Value {
+    json!({
+        "input_tokens": usage.prompt - usage.cached.min(usage.prompt),
+        "output_tokens": usage.output,
+        "cache_read_input_tokens": usage.cached,
+    })
+}
+
+/// Translates a `generateContent` answer into a Messages response for
+/// `model`, the model the client asked for.
+pub fn response(gemini: &Value, model: &str) -> Value {
+    let mut content: Vec<Value> = Vec::new();
+    let mut used_tools = false;
+    for part in parts(gemini) {
+        let text = part.get("text").and_then(Value::as_str);
+        let signature = part.get("thoughtSignature").and_then(Value::as_str);
+        if part.get("thought").and_then(Value::as_bool) == Some(true) {
+            content.push(json!({
+                "type": "thinking",
+                "thinking": text.unwrap_or_default(),
+                "signature": signature.unwrap_or_default(),
+            }));
+        } else if let Some(call) = part.get("functionCall") {
+            if let Some(signature) = signature {
+                content.push(json!({"type": "thinking", "thinking": "", "signature": signature}));
+            }
+            content.push(tool_use(call));
+            used_tools = true;
+        } else if let Some(text) = text {
+            match content.last_mut() {
+                Some(last) if last["type"] == "text" => {
+                    let joined = format!("{}{text}", last["text"].as_str().unwrap_or_default());
+                    last["text"] = joined.into();
+                }
+                _ => content.push(json!({"type": "text", "text": text})),
+            }
+        }
+    }
+
+    let stop_reason = match blocked(gemini) {
+        true => "refusal",
+        false => stop_reason(finish_reason(gemini), used_tools),
+    };
+    json!({
+        "id": random_id("msg_"),
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": content,
+        "stop_reason": stop_reason,
+        "stop_sequence": null,
+        "usage": usage(Usage::of(gemini).unwrap_or_default()),
+    })
+}
+
+fn tool_use(call: &Value) -> Value {
+    json!({
+        "type": "tool_use",
+        "id": call
+            .get("id")
+            .and_then(Value::as_str)
+            .map_or_else(|| random_id("toolu_"), str::to_string),
+        "name": call.get("name"),
+        "input": call.get("args").cloned().unwrap_or_else(|| json!({})),
+    })
+}
+
+#[derive(PartialEq)]
+enum Block {
+    Text,
+    Thinking,
+}
+
+/// Translates a `streamGenerateContent` stream into Messages stream events.
+pub struct Stream {
+    id: String,
+    model: String,
+    started: bool,
+    /// The content block being streamed, if it can take more deltas.
+    open: Option<Block>,
+    /// Index of the next content block.
+    index: usize,
+    used_tools: bool,
+    finish_reason: Option<String>,
+    blocked: bool,
+    usage: Usage,
+    /// Set once an error event ended the stream.
+    failed: bool,
+}
+
+impl Stream {
+    pub fn new(model: &str) -> Self {
+        Self {
+            id: random_id("msg_"),
+            model: model.to_string(),
+            started: false,
+            open: None,
+            index: 0,
+            used_tools: false,
+            finish_reason: None,
+            blocked: false,
+            usage: Usage::default(),
+            failed: false,
+        }
+    }
+
+    fn start(&mut self, out: &mut String) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        let message = json!({
+            "id": self.id,
+            "type": "message",
+            "role": "assistant",
+            "model": self.model,
+            "content": [],
+            "stop_reason": null,
+            "stop_sequence": null,
+            "usage": usage(Usage { output: 0, ..self.usage }),
+        });
+        emit(out, "message_start", json!({"message": message}));
+    }
+
+    fn close(&mut self, out: &mut String) {
+        if self.open.take().is_some() {
+            self.stop_block(out);
+        }
+    }
+
+    fn start_block(&mut self, out: &mut String, block: Value) {
+        self.close(out);
+        emit(
+            out,
+            "content_block_start",
+            json!({"index": self.index, "content_block": block}),
+        );
+    }
+
+    fn stop_block(&mut self, out: &mut String) {
+        emit(out, "content_block_stop", json!({"index": self.index}));
+        self.index += 1;
+    }
+
+    fn delta(&mut self, out: &mut String, block: Block, delta: Value) {
+        if self.open.as_ref() != Some(&block) {
+            let start = match block {
+                Block::Text => json!({"type": "text", "text": ""}),
+                Block::Thinking => json!({"type": "thinking", "thinking": ""}),
+            };
+            self.start_block(out, start);
+            self.open = Some(block);
+        }
+        emit(
+            out,
+            "content_block_delta",
+            json!({"index": self.index, "delta": delta}),
+        );
+    }
+}
+
+impl Translator for Stream {
+    fn event(&mut self, event: &Value) -> String {
+        let mut out = String::new();
+        if self.failed {
+            return out;
+        }
+        if let Some(failure) = event.get("error") {
+            self.failed = true;
+            let status = failure.get("code").and_then(Value::as_u64).unwrap_or(500) as u16;
+            let message = failure
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("Upstream error");
+            emit(&mut out, "error", error(status, message));
+            return out;
+        }
+
+        if let Some(usage) = Usage::of(event) {
+            self.usage = usage;
+        }
+        self.start(&mut out);
+
+        for part in parts(event) {
+            let text = part.get("text").and_then(Value::as_str);
+            let signature = part.get("thoughtSignature").and_then(Value::as_str);
+            if part.get("thought").and_then(Value::as_bool) == Some(true) {
+                if let Some(text) = text.filter(|text| !text.is_empty()) {
+                    let delta = json!({"type": "thinking_delta", "thinking": text});
+                    self.delta(&mut out, Block::Thinking, delta);
+                }
+                if let Some(signature) = signature {
+                    let delta = json!({"type": "signature_delta", "signature": signature});
+                    self.delta(&mut out, Block::Thinking, delta);
+                }
+            } else if let Some(call) = part.get("functionCall") {
+                if let Some(signature) = signature {
+                    let delta = json!({"type": "signature_delta", "signature": signature});
+                    self.delta(&mut out, Block::Thinking, delta);
+                }
+                let mut block = tool_use(call);
+                let input = std::mem::replace(&mut block["input"], json!({}));
+                self.start_block(&mut out, block);
+                let delta = json!({"type": "input_json_delta", "partial_json": input.to_string()});
+                emit(
+                    &mut out,
+                    "content_block_delta",
+                    json!({"index": self.index, "delta": delta}),
+                );
+                self.stop_block(&mut out);
+                self.used_tools = true;
+            } else if let Some(text) = text.filter(|text| !text.is_empty()) {
+                let delta = json!({"type": "text_delta", "text": text});
+                self.delta(&mut out, Block::Text, delta);
+            }
+        }
+
+        if let Some(reason) = finish_reason(event) {
+            self.finish_reason = Some(reason.to_string());
+        }
+        self.blocked |= blocked(event);
+        out
+    }
+
+    fn end(&mut self) -> String {
+        let mut out = String::new();
+        if self.failed {
+            return out;
+        }
+        self.start(&mut out);
+        self.close(&mut out);
+
+        let stop_reason = match self.blocked {
+            true => "refusal",
+            false => stop_reason(self.finish_reason.as_deref(), self.used_tools),
+        };
+        emit(
+            &mut out,
+            "message_delta",
+            json!({
+                "delta": {"stop_reason": stop_reason, "stop_sequence": null},
+                "usage": usage(self.usage),
+            }),
+        );
+        emit(&mut out, "message_stop", json!({}));
+        out
+    }
+}
+
+/// Appends an SSE event named `kind`, whose data carries its kind as `type`
+/// like every Messages stream event.
+fn emit(out: &mut String, kind: &str, mut data: Value) {
+    data["type"] = kind.into();
+    out.push_str(&format!("event: {kind}\ndata: {data}\n\n"));
+}
@@ -0,0 +1,942 @@
+//! The OpenAI Chat Completions API, for models whose chat completions are
+//! served through native Gemini calls rather than Gemini's own
+//! OpenAI-compatible endpoint.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value, json};
+
+use super::{
+    Translator, Usage, blocked, filtered, finish_reason, parts, push, random_id, thinking_budget,
+    url_part,
+};
+use crate::routes::dialect::Dialect;
+
+/// An `error` body as the OpenAI API sends it.
+pub fn error(status: u16, message: &str) -> Value {
+    Dialect::OpenAi.error(status, message)
+}
+
+/// Translates a chat completion request into a `generateContent` body.
+pub fn request(body: &Value) -> Result<Value, String> {
+    let messages = body
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("messages: field required")?;
+    if body.get("n").and_then(Value::as_u64).unwrap_or(1) > 1 {
+        return Err("n: only one choice is supported".into());
+    }
+
+    // Tool messages only carry the id of the call they answer, while Gemini
+    // wants the function's name too.
+    let mut tool_names = HashMap::new();
+    let mut contents = Vec::new();
+    let mut system = Vec::new();
+    for message in messages {
+        let content = message.get("content");
+        match message.get("role").and_then(Value::as_str) {
+            Some("system" | "developer") => system.extend(content_parts(content)?),
+            Some("user") => {
+                for part in content_parts(content)? {
+                    push(&mut contents, "user", part);
+                }
+            }
+            Some("assistant") => {
+                for part in content_parts(content)? {
+                    push(&mut contents, "model", part);
+                }
+                let calls = message.get("tool_calls").and_then(Value::as_array);
+                for call in calls.into_iter().flatten() {
+                    let part = function_call(call)?;
+                    if let (Some(id), Some(name)) = (
+                        call.get("id").and_then(Value::as_str),
+                        call.pointer("/function/name").and_then(Value::as_str),
+                    ) {
+                        tool_names.insert(id.to_string(), name.to_string());
+                    }
+                    push(&mut contents, "model", part);
+                }
+            }
+            Some("tool") => {
+                let id = message
+                    .get("tool_call_id")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let name = tool_names
+                    .get(id)
+                    .ok_or_else(|| format!("messages: no tool call with id {id:?}"))?;
+                let output = content_parts(content)?
+                    .iter()
+                    .filter_map(|part| part.get("text").and_then(Value::as_str))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                push(
+                    &mut contents,
+                    "user",
+                    json!({"functionResponse": {
+                        "id": id,
+                        "name": name,
+                        "response": {"content": output},
+                    }}),
+                );
+            }
+            role => return Err(format!("messages: unexpected role {role:?}")),
+        }
+    }
+    if contents.is_empty() {
+        return Err("messages: at least one user or assistant message is required".into());
+    }
+
+    let mut gemini = Map::new();
+    gemini.insert("contents".into(), contents.into());
+    if !system.is_empty() {
+        gemini.insert("systemInstruction".into(), json!({"parts": system}));
+    }
+    let mut tools = match body.get("tools").and_then(Value::as_array) {
+        Some(tools) => self::tools(tools)?,
+        None => Vec::new(),
+    };
+    if body
+        .get("web_search_options")
+        .is_some_and(|options| !options.is_null())
+    {
+        tools.push(json!({"googleSearch": {}}));
+    }
+    if !tools.is_empty() {
+        gemini.insert("tools".into(), tools.into());
+    }
+    if let Some(choice) = body.get("tool_choice") {
+        let (mode, only) = match choice {
+            Value::String(mode) => match mode.as_str() {
+                "none" => ("NONE", None),
+                "required" => ("ANY", None),
+                _ => ("AUTO", None),
+            },
+            _ => match choice.get("type").and_then(Value::as_str) {
+                Some("function") => ("ANY", choice.pointer("/function/name")),
+                _ => ("AUTO", None),
+            },
+        };
+        let mut config = json!({"mode": mode});
+        if let Some(name) = only {
+            config["allowedFunctionNames"] = json!([name]);
+        }
+        gemini.insert(
+            "toolConfig".into(),
+            json!({"functionCallingConfig": config}),
+        );
+    }
+
+    let mut config = Map::new();
+    for (from, to) in [
+        ("max_tokens", "maxOutputTokens"),
+        ("max_completion_tokens", "maxOutputTokens"),
+        ("temperature", "temperature"),
+        ("top_p", "topP"),
+        ("presence_penalty", "presencePenalty"),
+        ("frequency_penalty", "frequencyPenalty"),
+        ("seed", "seed"),
+    ] {
+        if let Some(value) = body.get(from).filter(|value| !value.is_null()) {
+            config.insert(to.into(), value.clone());
+        }
+    }
+    match body.get("stop") {
+        Some(Value::String(stop)) => {
+            config.insert("stopSequences".into(), json!([stop]));
+        }
+        Some(stops @ Value::Array(_)) => {
+            config.insert("stopSequences".into(), stops.clone());
+        }
+        _ => {}
+    }
+    match body
+        .pointer("/response_format/type")
+        .and_then(Value::as_str)
+    {
+        Some("json_schema") => {
+            config.insert("responseMimeType".into(), "application/json".into());
+            if let Some(schema) = body.pointer("/response_format/json_schema/schema") {
+                config.insert("responseJsonSchema".into(), schema.clone());
+            }
+        }
+        Some("json_object") => {
+            config.insert("responseMimeType".into(), "application/json".into());
+        }
+        _ => {}
+    }
+    if let Some(budget) = body
+        .get("reasoning_effort")
+        .and_then(Value::as_str)
+        .and_then(thinking_budget)
+    {
+        config.insert("thinkingConfig".into(), json!({"thinkingBudget": budget}));
+    }
+
+    // Gemini's own OpenAI-compatible endpoint takes native settings under
+    // `extra_body.google`, which OpenAI's SDKs send as a top-level `google`.
+    let google = body
+        .pointer("/extra_body/google")
+        .or_else(|| body.get("google"));
+    if let Some(google) = google {
+        if let Some(thinking) = google.get("thinking_config") {
+            config.insert("thinkingConfig".into(), camel_case(thinking));
+        }
+        if let Some(settings) = google.get("safety_settings") {
+            gemini.insert("safetySettings".into(), camel_case(settings));
+        }
+        if let Some(cached) = google.get("cached_content") {
+            gemini.insert("cachedContent".into(), cached.clone());
+        }
+    }
+    if !config.is_empty() {
+        gemini.insert("generationConfig".into(), config.into());
+    }
+
+    Ok(gemini.into())
+}
+
+/// The parts of a message's `content`, a string or a list of parts.
+fn content_parts(content: Option<&Value>) -> Result<Vec<Value>, String> {
+    let items = match content {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::String(text)) if text.is_empty() => return Ok(Vec::new()),
+        Some(Value::String(text)) => return Ok(vec![json!({"text": text})]),
+        Some(Value::Array(items)) => items,
+        _ => return Err("messages: content must be a string or a list of parts".into()),
+    };
+    items
+        .iter()
+        .map(|item| {
+            let field = |pointer| item.pointer(pointer).and_then(Value::as_str);
+            match item.get("type").and_then(Value::as_str) {
+                Some("text") => Ok(json!({"text": item.get("text")})),
+                Some("refusal") => Ok(json!({"text": item.get("refusal")})),
+                Some("image_url") => field("/image_url/url")
+                    .or(field("/image_url"))
+                    .map(url_part)
+                    .ok_or_else(|| "messages: image_url without a url".to_string()),
+                Some("input_audio") => {
+                    match (field("/input_audio/data"), field("/input_audio/format")) {
+                        (Some(data), Some(format)) => Ok(json!({"inlineData": {
+                            "mimeType": format!("audio/{format}"),
+                            "data": data,
+                        }})),
+                        _ => Err("messages: input_audio needs data and a format".to_string()),
+                    }
+                }
+                Some("file") => field("/file/file_data").map(url_part).ok_or_else(|| {
+                    "messages: only files sent as file_data are supported".to_string()
+                }),
+                kind => Err(format!("messages: unsupported content part type {kind:?}")),
+            }
+        })
+        .collect()
+}
+
+/// An assistant message's tool call as a Gemini function call part, with
+/// the thought signature Gemini gave it, kept where Gemini's own
+/// OpenAI-compatible endpoint keeps it.
+fn function_call(call: &Value) -> Result<Value, String> {
+    let arguments = call
+        .pointer("/function/arguments")
+        .and_then(Value::as_str)
+        .filter(|arguments| !arguments.trim().is_empty())
+        .unwrap_or("{}");
+    let args: Value = serde_json::from_str(arguments)
+        .map_err(|e| format!("messages: tool call arguments must be JSON: {e}"))?;
+    let mut part = json!({"functionCall": {
+        "id": call.get("id"),
+        "name": call.pointer("/function/name"),
+        "args": args,
+    }});
+    if let Some(signature) = call.pointer("/extra_content/google/thought_signature") {
+        part["thoughtSignature"] = signature.clone();
+    }
+    Ok(part)
+}
+
+fn tools(tools: &[Value]) -> Result<Vec<Value>, String> {
+    let mut declarations = Vec::new();
+    for tool in tools {
+        match tool.get("type").and_then(Value::as_str) {
+            Some("function") => {
+                let function = &tool["function"];
+                declarations.push(json!({
+                    "name": function.get("name"),
+                    "description": function.get("description").cloned().unwrap_or_default(),
+                    "parametersJsonSchema": function.get("parameters").cloned().unwrap_or_else(|| json!({"type": "object"})),
+                }));
+            }
+            kind => return Err(format!("tools: unsupported tool type {kind:?}")),
+        }
+    }
+    Ok(match declarations.is_empty() {
+        true => Vec::new(),
+        false => vec![json!({"functionDeclarations": declarations})],
+    })
+}
+
+/// `value` with the keys of every object in it in camelCase, as Gemini names
+/// the fields the OpenAI-compatible endpoint takes in snake_case.
+fn camel_case(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| {
+                let mut words = key.split('_');
+                let mut camel = words.next().unwrap_or_default().to_string();
+                for word in words {
+                    let mut chars = word.chars();
+                    if let Some(first) = chars.next() {
+                        camel.extend(first.to_uppercase());
+                        camel.push_str(chars.as_str());
+                    }
+                }
+                (camel, camel_case(value))
+            })
+            .collect::<Map<_, _>>()
+            .into(),
+        Value::Array(items) => items.iter().map(camel_case).collect(),
+        value => value.clone(),
+    }
+}
+
+fn finish(finish_reason: Option<&str>, used_tools: bool) -> &'static str {
+    match finish_reason {
+        _ if used_tools => "tool_calls",
+        Some("MAX_TOKENS") => "length",
+        Some(reason) if filtered(reason) => "content_filter",
+        _ => "stop",
+    }
+}
+
+fn usage(usage: Usage) -> Value {
+    json!({
+        "prompt_tokens": usage.prompt,
+        "completion_tokens": usage.output,
+        "total_tokens": usage.prompt + usage.output,
+        "prompt_tokens_details": {"cached_tokens": usage.cached},
+        "completion_tokens_details": {"reasoning_tokens": usage.thoughts},
+    })
+}
+
+/// A Gemini function call as a chat completion tool call.
+fn tool_call(call: &Value, signature: Option<&str>) -> Value {
+    let mut tool_call = json!({
+        "id": call
+            .get("id")
+            .and_then(Value::as_str)
+            .map_or_else(|| random_id("call_"), str::to_string),
+        "type": "function",
+        "function": {
+            "name": call.get("name"),
+            "arguments": call.get("args").cloned().unwrap_or_else(|| json!({})).to_string(),
+        },
+    });
+    if let Some(signature) = signature {
+        tool_call["extra_content"] = json!({"google": {"thought_signature": signature}});
+    }
+    tool_call
+}
+
+/// What one answer, or one event of a streamed one, says in chat completion
+/// terms.
+#[derive(Default)]
+struct Delta {
+    content: String,
+    /// Thought summaries, which Gemini only sends when asked to.
+    reasoning: String,
+    tool_calls: Vec<Value>,
+}
+
+impl Delta {
+    fn of(gemini: &Value) -> Self {
+        let mut delta = Self::default();
+        for part in parts(gemini) {
+            let text = part.get("text").and_then(Value::as_str).unwrap_or_default();
+            if part.get("thought").and_then(Value::as_bool) == Some(true) {
+                delta.reasoning.push_str(text);
+            } else if let Some(call) = part.get("functionCall") {
+                let signature = part.get("thoughtSignature").and_then(Value::as_str);
+                delta.tool_calls.push(tool_call(call, signature));
+            } else {
+                delta.content.push_str(text);
+            }
+        }
+        delta
+    }
+}
+
+/// Translates a `generateContent` answer into a chat completion for
+/// `model`, the model the client asked for.
+pub fn response(gemini: &Value, model: &str) -> Value {
+    let delta = Delta::of(gemini);
+    let finish_reason = match blocked(gemini) {
+        true => "content_filter",
+        false => finish(finish_reason(gemini), !delta.tool_calls.is_empty()),
+    };
+
+    let mut message = json!({
+        "role": "assistant",
+        "content": (!delta.content.is_empty()).then_some(delta.content),
+    });
+    if !delta.reasoning.is_empty() {
+        message["reasoning_content"] = delta.reasoning.into();
+    }
+    if !delta.tool_calls.is_empty() {
+        message["tool_calls"] = delta.tool_calls.into();
+    }
+    json!({
+        "id": random_id("chatcmpl-"),
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": finish_reason,
+            "logprobs": null,
+        }],
+        "usage": usage(Usage::of(gemini).unwrap_or_default()),
+    })
+}
+
+/// Translates a `streamGenerateContent` stream into chat completion chunks.
+pub struct Stream {
+    id: String,
+    created: i64,
+    model: String,
+    /// Whether the client asked for a last chunk with the usage.
+    include_usage: bool,
+    started: bool,
+    /// Tool calls streamed so far, which number the next one.
+    tool_calls: usize,
+    usage: Option<Usage>,
+    /// Set once an error event ended the stream.
+    failed: bool,
+}
+
+impl Stream {
+    /// For `body`, a request for `model`, the model the client asked for.
+    pub fn new(body: &Value, model: &str) -> Self {
+        Self {
+            id: random_id("chatcmpl-"),
+            created: chrono::Utc::now().timestamp(),
+            model: model.to_string(),
+            include_usage: body
+                .pointer("/stream_options/include_usage")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            started: false,
+            tool_calls: 0,
+            usage: None,
+            failed: false,
+        }
+    }
+
+    fn chunk(&self, choices: Value) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": choices,
+        })
+    }
+}
+
+impl Translator for Stream {
+    fn event(&mut self, event: &Value) -> String {
+        if self.failed {
+            return String::new();
+        }
+        if let Some(failure) = event.get("error") {
+            self.failed = true;
+            let status = failure.get("code").and_then(Value::as_u64).unwrap_or(500) as u16;
+            let message = failure
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("Upstream error");
+            return format!("data: {}\n\n", error(status, message));
+        }
+
+        if let Some(usage) = Usage::of(event) {
+            self.usage = Some(usage);
+        }
+        let Delta {
+            content,
+            reasoning,
+            tool_calls,
+        } = Delta::of(event);
+
+        let mut delta = Map::new();
+        if !self.started {
+            self.started = true;
+            delta.insert("role".into(), "assistant".into());
+        }
+        if !content.is_empty() {
+            delta.insert("content".into(), content.into());
+        }
+        if !reasoning.is_empty() {
+            delta.insert("reasoning_content".into(), reasoning.into());
+        }
+        if !tool_calls.is_empty() {
+            let tool_calls: Vec<Value> = tool_calls
+                .into_iter()
+                .map(|mut call| {
+                    call["index"] = self.tool_calls.into();
+                    self.tool_calls += 1;
+                    call
+                })
+                .collect();
+            delta.insert("tool_calls".into(), tool_calls.into());
+        }
+
+        let finish_reason = match blocked(event) {
+            true => Some("content_filter"),
+            false => finish_reason(event).map(|reason| finish(Some(reason), self.tool_calls > 0)),
+        };
+        if delta.is_empty() && finish_reason.is_none() {
+            return String::new();
+        }
+        let chunk = self.chunk(json!([{
+            "index": 0,
+            "delta": delta,
+            "finish_reason": finish_reason,
+            "logprobs": null,
+        }]));
+        format!("data: {chunk}\n\n")
+    }
+
+    fn end(&mut self) -> String {
+        if self.failed {
+            return String::new();
+        }
+        let mut out = String::new();
+        if self.include_usage {
+            let mut chunk = self.chunk(json!([]));
+            chunk["usage"] = usage(self.usage.unwrap_or_default());
+            out.push_str(&format!("data: {chunk}\n\n"));
+        }
+        out.push_str("data: [DONE]\n\n");
+        out
+    }
+}
+
+/// Translates a `generateContent` body into a chat completion request for
+/// `model` on an OpenAI-compatible provider.
+pub fn provider_request(gemini: &Value, model: &str, stream: bool) -> Result<Value, String> {
+    let mut messages = Vec::new();
+    let system: Vec<&str> = gemini
+        .pointer("/systemInstruction/parts")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part.get("text").and_then(Value::as_str))
+        .collect();
+    if !system.is_empty() {
+        messages.push(json!({"role": "system", "content": system.join("\n")}));
+    }
+
+    // Gemini doesn't always give calls an id, and matches responses to them
+    // by name, while chat completions match them by id.
+    let mut calls = 0;
+    let mut pending: Vec<(String, String)> = Vec::new();
+    let contents = gemini
+        .get("contents")
+        .and_then(Value::as_array)
+        .ok_or("contents: field required")?;
+    for content in contents {
+        let role = match content.get("role").and_then(Value::as_str) {
+            Some("model") => "assistant",
+            _ => "user",
+        };
+        let mut parts = Vec::new();
+        let mut tool_calls = Vec::new();
+        let items = content.get("parts").and_then(Value::as_array);
+        for part in items.into_iter().flatten() {
+            if part.get("thought").and_then(Value::as_bool) == Some(true) {
+                continue;
+            }
+            if let Some(text) = part.get("text").and_then(Value::as_str) {
+                parts.push(json!({"type": "text", "text": text}));
+            } else if let Some(call) = part.get("functionCall") {
+                let name = call.get("name").and_then(Value::as_str).unwrap_or_default();
+                let id = call
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map_or_else(|| format!("call_{calls}"), str::to_string);
+                calls += 1;
+                pending.push((name.to_string(), id.clone()));
+                tool_calls.push(json!({
+                    "id": id,
+                    "type": "function",
+                    "function": {
+                        "name": name,
+                        "arguments": call.get("args").cloned().unwrap_or_else(|| json!({})).to_string(),
+                    },
+                }));
+            } else if let Some(response) = part.get("functionResponse") {
+                let name = response
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let id = match response.get("id").and_then(Value::as_str) {
+                    Some(id) => {
+                        pending.retain(|(_, pending)| pending != id);
+                        id.to_string()
+                    }
+                    None => pending
+                        .iter()
+                        .position(|(pending, _)| pending == name)
+                        .map(|at| pending.remove(at).1)
+                        .ok_or_else(|| format!("contents: no call to {name:?} to respond to"))?,
+                };
+                let output = &response["response"];
+                let output = match output.get("content").or(output.get("output")) {
+                    Some(Value::String(text)) => text.clone(),
+                    _ => output.to_string(),
+                };
+                messages.push(json!({"role": "tool", "tool_call_id": id, "content": output}));
+            } else if let Some(data) = part.get("inlineData") {
+                let mime_type = data
+                    .get("mimeType")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let data = data.get("data").and_then(Value::as_str).unwrap_or_default();
+                parts.push(match mime_type.split_once('/') {
+                    Some(("image", _)) => json!({"type": "image_url", "image_url": {
+                        "url": format!("data:{mime_type};base64,{data}"),
+                    }}),
+                    Some(("audio", format)) => json!({"type": "input_audio", "input_audio": {
+                        "data": data,
+                        "format": format,
+                    }}),
+                    _ => return Err(format!("contents: unsupported inline data {mime_type:?}")),
+                });
+            } else if let Some(uri) = part.pointer("/fileData/fileUri").and_then(Value::as_str) {
+                parts.push(json!({"type": "image_url", "image_url": {"url": uri}}));
+            }
+        }
+
+        if parts.is_empty() && tool_calls.is_empty() {
+            continue;
+        }
+        // Plain text goes as a string, which every server takes.
+        let content = match parts.iter().all(|part| part["type"] == "text") {
+            true => parts
+                .iter()
+                .filter_map(|part| part["text"].as_str())
+                .collect::<String>()
+                .into(),
+            false => Value::from(parts),
+        };
+        let mut message = json!({"role": role, "content": content});
+        if !tool_calls.is_empty() {
+            message["tool_calls"] = tool_calls.into();
+        }
+        messages.push(message);
+    }
+
+    let mut chat = Map::new();
+    chat.insert("model".into(), model.into());
+    chat.insert("messages".into(), messages.into());
+    if stream {
+        chat.insert("stream".into(), true.into());
+        chat.insert("stream_options".into(), json!({"include_usage": true}));
+    }
+
+    let mut functions = Vec::new();
+    let tools = gemini.get("tools").and_then(Value::as_array);
+    for tool in tools.into_iter().flatten() {
+        let declarations = tool
+            .get("functionDeclarations")
+            .and_then(Value::as_array)
+            .ok_or("tools: only function declarations are supported")?;
+        for declaration in declarations {
+            let parameters = declaration
+                .get("parametersJsonSchema")
+                .cloned()
+                .or_else(|| declaration.get("parameters").map(json_schema))
+                .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+            functions.push(json!({"type": "function", "function": {
+                "name": declaration.get("name"),
+                "description": declaration.get("description").cloned().unwrap_or_default(),
+                "parameters": parameters,
+            }}));
+        }
+    }
+    if !functions.is_empty() {
+        chat.insert("tools".into(), functions.into());
+    }
+    if let Some(calling) = gemini.pointer("/toolConfig/functionCallingConfig") {
+        let only = calling
+            .get("allowedFunctionNames")
+            .and_then(Value::as_array)
+            .filter(|names| names.len() == 1)
+            .map(|names| &names[0]);
+        let choice = match (calling.get("mode").and_then(Value::as_str), only) {
+            (Some("NONE"), _) => json!("none"),
+            (Some("ANY"), Some(name)) => json!({"type": "function", "function": {"name": name}}),
+            (Some("ANY"), None) => json!("required"),
+            _ => json!("auto"),
+        };
+        chat.insert("tool_choice".into(), choice);
+    }
+
+    let config = gemini.get("generationConfig");
+    for (from, to) in [
+        ("maxOutputTokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("topP", "top_p"),
+        ("topK", "top_k"),
+        ("presencePenalty", "presence_penalty"),
+        ("frequencyPenalty", "frequency_penalty"),
+        ("seed", "seed"),
+        ("candidateCount", "n"),
+        ("stopSequences", "stop"),
+    ] {
+        if let Some(value) = config.and_then(|config| config.get(from)) {
+            chat.insert(to.into(), value.clone());
+        }
+    }
+    if config.and_then(|config| config.get("responseMimeType")) == Some(&json!("application/json"))
+    {
+        let schema = config.and_then(|config| {
+            config
+                .get("responseJsonSchema")
+                .cloned()
+                .or_else(|| config.get("responseSchema").map(json_schema))
+        });
+        let format = match schema {
+            Some(schema) => json!({"type": "json_schema", "json_schema": {
+                "name": "response",
+                "schema": schema,
+            }}),
+            None => json!({"type": "json_object"}),
+        };
+        chat.insert("response_format".into(), format);
+    }
+
+    Ok(chat.into())
+}
+
+/// A Gemini schema, whose types are named in upper case, as a JSON schema.
+fn json_schema(schema: &Value) -> Value {
+    match schema {
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| {
+                let value = match (key.as_str(), value) {
+                    ("type", Value::String(kind)) => kind.to_lowercase().into(),
+                    _ => json_schema(value),
+                };
+                (key.clone(), value)
+            })
+            .collect::<Map<_, _>>()
+            .into(),
+        Value::Array(items) => items.iter().map(json_schema).collect(),
+        value => value.clone(),
+    }
+}
+
+/// Gemini's name for why a provider's choice stopped.
+fn gemini_finish_reason(finish_reason: &str) -> &'static str {
+    match finish_reason {
+        "length" => "MAX_TOKENS",
+        "content_filter" => "SAFETY",
+        _ => "STOP",
+    }
+}
+
+/// A chat completion `usage` as Gemini's `usageMetadata`, which counts
+/// thoughts apart from the answer.
+fn usage_metadata(usage: &Value) -> Value {
+    let count = |pointer| usage.pointer(pointer).and_then(Value::as_u64).unwrap_or(0);
+    let thoughts = count("/completion_tokens_details/reasoning_tokens");
+    let mut metadata = json!({
+        "promptTokenCount": count("/prompt_tokens"),
+        "candidatesTokenCount": count("/completion_tokens").saturating_sub(thoughts),
+        "totalTokenCount": count("/total_tokens"),
+    });
+    if thoughts > 0 {
+        metadata["thoughtsTokenCount"] = thoughts.into();
+    }
+    let cached = count("/prompt_tokens_details/cached_tokens");
+    if cached > 0 {
+        metadata["cachedContentTokenCount"] = cached.into();
+    }
+    metadata
+}
+
+/// The parts a provider's message, or a delta of one, stands for. Servers
+/// name reasoning `reasoning_content` or `reasoning`.
+fn provider_parts(message: &Value) -> Vec<Value> {
+    let text = |field| {
+        message
+            .get(field)
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+    };
+    let mut parts = Vec::new();
+    if let Some(reasoning) = text("reasoning_content").or(text("reasoning")) {
+        parts.push(json!({"text": reasoning, "thought": true}));
+    }
+    if let Some(content) = text("content") {
+        parts.push(json!({"text": content}));
+    }
+    parts
+}
+
+/// A provider's tool call, complete, as a Gemini function call part.
+fn provider_call(call: &Value) -> Value {
+    let arguments = call
+        .pointer("/function/arguments")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    json!({"functionCall": {
+        "id": call.get("id"),
+        "name": call.pointer("/function/name"),
+        "args": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+    }})
+}
+
+/// Translates a provider's chat completion into a `generateContent` answer.
+pub fn provider_response(completion: &Value) -> Value {
+    let choices = completion.get("choices").and_then(Value::as_array);
+    let candidates: Vec<Value> = choices
+        .into_iter()
+        .flatten()
+        .map(|choice| {
+            let message = &choice["message"];
+            let mut parts = provider_parts(message);
+            let calls = message.get("tool_calls").and_then(Value::as_array);
+            parts.extend(calls.into_iter().flatten().map(provider_call));
+            json!({
+                "index": choice.get("index").cloned().unwrap_or_else(|| json!(0)),
+                "content": {"role": "model", "parts": parts},
+                "finishReason": gemini_finish_reason(
+                    choice.get("finish_reason").and_then(Value::as_str).unwrap_or_default(),
+                ),
+            })
+        })
+        .collect();
+
+    let mut gemini = json!({
+        "candidates": candidates,
+        "modelVersion": completion.get("model"),
+        "responseId": completion.get("id"),
+    });
+    if let Some(usage) = completion.get("usage").filter(|usage| !usage.is_null()) {
+        gemini["usageMetadata"] = usage_metadata(usage);
+    }
+    gemini
+}
+
+/// Translates a provider's chat completion chunks into
+/// `streamGenerateContent` events. Tool calls stream in pieces, so they're
+/// held back until their choice finishes, and the last event waits for the
+/// usage that follows it.
+#[derive(Default)]
+pub struct ProviderStream {
+    /// Tool calls so far, by their index, arguments pieced together.
+    calls: Vec<Value>,
+    /// The event that finished the answer, not sent yet.
+    last: Option<Value>,
+    failed: bool,
+}
+
+impl ProviderStream {
+    fn send(event: &Value) -> String {
+        format!("data: {event}\r\n\r\n")
+    }
+
+    fn collect_call(&mut self, delta: &Value) {
+        let index = delta.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+        if self.calls.len() <= index {
+            self.calls
+                .resize(index + 1, json!({"function": {"arguments": ""}}));
+        }
+        let call = &mut self.calls[index];
+        for field in ["id", "type"] {
+            if let Some(value) = delta.get(field) {
+                call[field] = value.clone();
+            }
+        }
+        if let Some(name) = delta.pointer("/function/name") {
+            call["function"]["name"] = name.clone();
+        }
+        if let Some(arguments) = delta.pointer("/function/arguments").and_then(Value::as_str) {
+            let joined = format!(
+                "{}{arguments}",
+                call["function"]["arguments"].as_str().unwrap_or_default()
+            );
+            call["function"]["arguments"] = joined.into();
+        }
+    }
+}
+
+impl Translator for ProviderStream {
+    fn event(&mut self, event: &Value) -> String {
+        if self.failed {
+            return String::new();
+        }
+        if let Some(failure) = event.get("error") {
+            self.failed = true;
+            let status = failure.get("code").and_then(Value::as_u64).unwrap_or(500) as u16;
+            let message = failure
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("Upstream error");
+            return Self::send(&Dialect::Gemini.error(status, message));
+        }
+
+        let mut out = String::new();
+        if let Some(usage) = event.get("usage").filter(|usage| !usage.is_null()) {
+            let last = self.last.get_or_insert_with(|| json!({"candidates": []}));
+            last["usageMetadata"] = usage_metadata(usage);
+        }
+        let Some(choice) = event.pointer("/choices/0") else {
+            return out;
+        };
+        let delta = &choice["delta"];
+        let calls = delta.get("tool_calls").and_then(Value::as_array);
+        for call in calls.into_iter().flatten() {
+            self.collect_call(call);
+        }
+
+        let mut parts = provider_parts(delta);
+        let finish_reason = choice.get("finish_reason").and_then(Value::as_str);
+        if finish_reason.is_some() {
+            parts.extend(self.calls.drain(..).map(|call| provider_call(&call)));
+        }
+        if parts.is_empty() && finish_reason.is_none() {
+            return out;
+        }
+        let mut candidate = json!({
+            "index": 0,
+            "content": {"role": "model", "parts": parts},
+        });
+        match finish_reason {
+            Some(reason) => {
+                candidate["finishReason"] = gemini_finish_reason(reason).into();
+                let last = self.last.get_or_insert_with(|| json!({}));
+                last["candidates"] = json!([candidate]);
+                last["modelVersion"] = event.get("model").cloned().unwrap_or_default();
+            }
+            None => {
+                let event = json!({"candidates": [candidate], "modelVersion": event.get("model")});
+                out.push_str(&Self::send(&event));
+            }
+        }
+        out
+    }
+
+    fn end(&mut self) -> String {
+        match self.last.take() {
+            Some(last) if !self.failed => Self::send(&last),
+            _ => String::new(),
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! APIs served by translating their requests into native Gemini ones and the
+//! answers back. Requests go through the juggler like any other, and failures
+//! come back in Gemini's error format until each API reshapes them.
+
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_web::{HttpResponse, body::to_bytes, error::ErrorBadGateway, web::Bytes};
+use futures_util::{Stream, StreamExt, stream::LocalBoxStream};
+use rand::Rng;
+use serde_json::{Value, json};
+
+use super::dialect::Dialect;
+use super::failover::{error_message, relay};
+use super::juggle::{InFlight, Juggled, admit, admit_requested, juggle, read_body};
+use crate::{
+    AppState,
+    utils::{
+        Admission, Client, Event, Requested, SseDecoder, UsageTap, config::Provider, total_tokens,
+    },
+};
+
+pub mod anthropic;
+pub mod chat;
+pub mod ollama;
+pub mod responses;
+
+/// What a native Gemini request gave.
+pub enum Generated {
+    Complete(Value),
+    /// `streamGenerateContent` events, as SSE, with failover.
+    Streaming(LocalBoxStream<'static, Result<Bytes, Infallible>>),
+}
+
+/// Runs a native Gemini request on behalf of a client of a translated API,
+/// checked against the client's policy and limits first. Failures come back
+/// as responses in Gemini's error format.
+pub async fn generate(
+    data: Arc<AppState>,
+    client: &Arc<Client>,
+    model: String,
+    mut body: Value,
+    stream: bool,
+) -> Result<Generated, HttpResponse> {
+    let admission = admit(Dialect::Gemini, client, &model, &mut body)?;
+    if let Some(provider) = data.config.provider(&model).cloned() {
+        return provide(&data, admission, &provider, &model, &body, stream).await;
+    }
+
+    let juggled = juggle(&data, &model, |key| {
+        let (data, model, body) = (&data, &model, &body);
+        async move {
+            data.requester
+                .forward_gemini(&key, model, body, stream)
+                .await
+        }
+    })
+    .await
+    .map_err(|e| e.error_response())?;
+
+    match juggled {
+        Juggled::Done(resp) => Err(resp),
+        Juggled::Forward { key, resp } if stream => Ok(Generated::Streaming(relay(
+            data,
+            admission,
+            Dialect::Gemini,
+            model,
+            body,
+            key,
+            resp,
+        ))),
+        Juggled::Forward { key, mut resp } => {
+            let body = read_body(&data, client, &key, &model, &mut resp)
+                .await
+                .map_err(|e| e.error_response())?;
+            serde_json::from_slice(&body)
+                .map(Generated::Complete)
+                .map_err(|e| {
+                    actix_web::error::ErrorBadGateway(format!("Invalid upstream response: {}", e))
+                        .error_response()
+                })
+        }
+    }
+}
+
+/// Runs an admitted native Gemini request on `provider`, the
+/// OpenAI-compatible server `model` is routed to, as a chat completion. The
+/// answer comes back as Gemini's would, and failures as responses in
+/// Gemini's error format.
+pub async fn provide(
+    data: &AppState,
+    admission: Admission,
+    provider: &Provider,
+    model: &str,
+    body: &Value,
+    stream: bool,
+) -> Result<Generated, HttpResponse> {
+    let request = chat::provider_request(body, model, stream)
+        .map_err(|message| HttpResponse::BadRequest().json(gemini_error(400, &message)))?;
+
+    let mut resp = match data.requester.forward_chat(provider, &request).await {
+        Ok(Event::Forward(resp)) => resp,
+        Ok(Event::Ok(resp)) => return Err(reshape_error(resp, gemini_error).await),
+        Ok(Event::Retry { retry_after, .. }) => {
+            let mut response = HttpResponse::TooManyRequests();
+            if let Some(retry_after) = retry_after {
+                response.insert_header(("Retry-After", retry_after.as_secs().max(1).to_string()));
+            }
+            let message = format!("Provider {} is ratelimited", provider.name);
+            return Err(response.json(gemini_error(429, &message)));
+        }
+        Ok(Event::BadKey) => {
+            let message = format!("Provider {} rejected its key", provider.name);
+            return Err(HttpResponse::BadGateway().json(gemini_error(502, &message)));
+        }
+        Ok(Event::Fail(e)) | Err(e) => return Err(e.error_response()),
+    };
+
+    let client = admission.client().clone();
+    if stream {
+        // A broken connection ends the stream with an error event.
+        let chunks = resp.map(|chunk| {
+            Ok::<_, Infallible>(chunk.unwrap_or_else(|e| {
+                let message = format!("Error reading response: {e}");
+                Bytes::from(format!("\n\ndata: {}\n\n", gemini_error(502, &message)))
+            }))
+        });
+        let events = Translated::new(chunks, chat::ProviderStream::default());
+        let events = UsageTap::new(events, move |tokens| client.record_tokens(tokens));
+        return Ok(Generated::Streaming(
+            InFlight::new(events, admission).boxed_local(),
+        ));
+    }
+
+    let body = resp
+        .body()
+        .await
+        .map_err(|e| ErrorBadGateway(format!("Error reading response: {}", e)).error_response())?;
+    if let Some(tokens) = total_tokens(&body) {
+        client.record_tokens(tokens);
+    }
+    serde_json::from_slice(&body)
+        .map(|completion| Generated::Complete(chat::provider_response(&completion)))
+        .map_err(|e| ErrorBadGateway(format!("Invalid upstream response: {}", e)).error_response())
+}
+
+fn gemini_error(status: u16, message: &str) -> Value {
+    Dialect::Gemini.error(status, message)
+}
+
+/// Runs a native Gemini `method` other than generation, such as
+/// `countTokens`, on behalf of a client of a translated API, juggled under
+/// `quota`. Failures come back as responses in Gemini's error format.
+pub async fn call_method(
+    data: &AppState,
+    client: &Arc<Client>,
+    model: &str,
+    method: &str,
+    quota: &str,
+    body: Value,
+) -> Result<Value, HttpResponse> {
+    let _admission = admit_requested(Dialect::Gemini, client, &Requested::model(model))?;
+
+    let juggled = juggle(data, quota, |key| {
+        let body = &body;
+        async move {
+            data.requester
+                .forward_method(&key, model, method, body)
+                .await
+        }
+    })
+    .await
+    .map_err(|e| e.error_response())?;
+
+    match juggled {
+        Juggled::Done(resp) => Err(resp),
+        Juggled::Forward { key, mut resp } => {
+            let body = read_body(data, client, &key, quota, &mut resp)
+                .await
+                .map_err(|e| e.error_response())?;
+            serde_json::from_slice(&body).map_err(|e| {
+                actix_web::error::ErrorBadGateway(format!("Invalid upstream response: {}", e))
+                    .error_response()
+            })
+        }
+    }
+}
+
+/// `resp`, a failure in any of the formats the juggler and the upstream
+/// answer with, as an `error` body of the client's API, keeping its status
+/// and `Retry-After`.
+pub async fn reshape_error(resp: HttpResponse, error: fn(u16, &str) -> Value) -> HttpResponse {
+    let status = resp.status();
+    let retry_after = resp.headers().get("Retry-After").cloned();
+    let body = to_bytes(resp.into_body()).await.unwrap_or_default();
+    let message =
+        error_message(&body).unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned());
+
+    let mut response = HttpResponse::build(status);
+    if let Some(retry_after) = retry_after {
+        response.insert_header(("Retry-After", retry_after));
+    }
+    response.json(error(status.as_u16(), &message))
+}
+
+/// An id for something the upstream doesn't name, like `msg_1f0c...`.
+pub fn random_id(prefix: &str) -> String {
+    let mut rng = rand::rng();
+    let suffix: String = (0..24)
+        .map(|_| char::from_digit(rng.random_range(0..16), 16).unwrap())
+        .collect();
+    format!("{prefix}{suffix}")
+}
+
+/// Turns the events of an upstream stream, usually `streamGenerateContent`,
+/// into another API's streaming format, framing included.
+pub trait Translator {
+    /// What to send for one upstream event, possibly nothing.
+    fn event(&mut self, event: &Value) -> String;
+    /// What to send once the upstream stream is over.
+    fn end(&mut self) -> String;
+}
+
+/// An upstream SSE stream, passed through a [`Translator`] as events arrive.
+pub struct Translated<S, T> {
+    inner: S,
+    decoder: SseDecoder,
+    translator: T,
+    done: bool,
+}
+
+impl<S, T> Translated<S, T> {
+    pub fn new(inner: S, translator: T) -> Self {
+        Self {
+            inner,
+            decoder: SseDecoder::default(),
+            translator,
+            done: false,
+        }
+    }
+}
+
+impl<S, T> Translated<S, T>
+where
+    T: Translator,
+{
+    fn translate(&mut self, events: impl IntoIterator<Item = String>) -> String {
+        events
+            .into_iter()
+            .filter_map(|event| serde_json::from_str::<Value>(&event).ok())
+            .map(|event| self.translator.event(&event))
+            .collect()
+    }
+}
+
+impl<S, T, E> Stream for Translated<S, T>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    T: Translator + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            match self.inner.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let events = self.decoder.feed(&chunk);
+                    let out = self.translate(events);
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(Bytes::from(out))));
+                    }
+                }
+                Poll::Ready(None) => {
+                    self.done = true;
+                    let rest = self.decoder.finish();
+                    let mut out = self.translate(rest);
+                    out.push_str(&self.translator.end());
+                    return Poll::Ready((!out.is_empty()).then(|| Ok(Bytes::from(out))));
+                }
+                polled => return polled,
+            }
+        }
+    }
+}
+
+/// The error body for a request to `path`, in the format of the API served
+/// there.
+pub fn error_body(path: &str, status: u16, message: &str) -> Value {
+    if path.starts_with("/v1/messages") {
+        anthropic::error(status, message)
+    } else if path.starts_with("/v1/responses") {
+        responses::error(status, message)
+    } else if path.starts_with("/api/") {
+        ollama::error(status, message)
+    } else {
+        Dialect::of(path).error(status, message)
+    }
+}
+
+/// A Gemini answer's first candidate.
+pub fn candidate(response: &Value) -> Option<&Value> {
+    response.pointer("/candidates/0")
+}
+
+/// Parts of a Gemini answer's first candidate.
+pub fn parts(response: &Value) -> &[Value] {
+    response
+        .pointer("/candidates/0/content/parts")
+        .and_then(Value::as_array)
+        .map_or(&[], Vec::as_slice)
+}
+
+/// Why a Gemini answer's first candidate stopped, if it did.
+pub fn finish_reason(response: &Value) -> Option<&str> {
+    candidate(response)?.get("finishReason")?.as_str()
+}
+
+/// Whether Gemini refused the prompt outright.
+pub fn blocked(response: &Value) -> bool {
+    response.pointer("/promptFeedback/blockReason").is_some()
+}
+
+/// Whether a finish reason means the answer was withheld for its content.
+pub fn filtered(finish_reason: &str) -> bool {
+    matches!(
+        finish_reason,
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY"
+    )
+}
+
+/// The thinking budget for a reasoning effort, as Gemini's own
+/// OpenAI-compatible endpoint maps `reasoning_effort`.
+pub fn thinking_budget(effort: &str) -> Option<u64> {
+    match effort {
+        "none" | "minimal" => Some(0),
+        "low" => Some(1024),
+        "medium" => Some(8192),
+        "high" => Some(24576),
+        _ => None,
+    }
+}
+
+/// Adds `part` to the last content if it has `role`, or as a new content.
+pub fn push(contents: &mut Vec<Value>, role: &str, part: Value) {
+    match contents.last_mut() {
+        Some(last) if last["role"] == role => {
+            if let Some(parts) = last["parts"].as_array_mut() {
+                parts.push(part);
+            }
+        }
+        _ => contents.push(json!({"role": role, "parts": [part]})),
+    }
+}
+
+/// A data URL as inline data, or any other URL as a file reference.
+pub fn url_part(url: &str) -> Value {
+    match url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        Some((mime_type, data)) => json!({"inlineData": {"mimeType": mime_type, "data": data}}),
+        None => json!({"fileData": {"fileUri": url}}),
+    }
+}
+
+/// Token counts a Gemini response reports.
+#[derive(Default, Clone, Copy)]
+pub struct Usage {
+    pub prompt: u64,
+    /// Prompt tokens served from cached content.
+    pub cached: u64,
+    /// Answer tokens, thoughts included.
+    pub output: u64,
+    pub thoughts: u64,
+}
+
+impl Usage {
+    pub fn of(response: &Value) -> Option<Self> {
+        let usage = response.get("usageMetadata")?;
+        let count = |field| usage.get(field).and_then(Value::as_u64).unwrap_or(0);
+        Some(Self {
+            prompt: count("promptTokenCount"),
+            cached: count("cachedContentTokenCount"),
+            output: count("candidatesTokenCount") + count("thoughtsTokenCount"),
+            thoughts: count("thoughtsTokenCount"),
+        })
+    }
+}
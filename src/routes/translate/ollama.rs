@@ -0,0 +1,433 @@
+//! The Ollama API.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value, json};
+
+use super::{Translator, Usage, finish_reason, parts, thinking_budget};
+use crate::utils::model_name;
+
+/// An error body as Ollama sends it.
+pub fn error(_status: u16, message: &str) -> Value {
+    json!({"error": message})
+}
+
+/// The model an Ollama request names, without the `:latest` tag Ollama
+/// tools add to names that have none.
+pub fn model(body: &Value) -> &str {
+    let model = body
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    model.strip_suffix(":latest").unwrap_or(model)
+}
+
+/// Translates a `/api/chat` request into a `generateContent` body.
+pub fn chat_request(body: &Value) -> Result<Value, String> {
+    let messages = body
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("messages: field required")?;
+
+    // Tool messages don't always say which function they answer, in which
+    // case they answer the calls before them in order.
+    let mut unanswered = VecDeque::new();
+    let mut system = Vec::new();
+    let mut contents: Vec<Value> = Vec::new();
+    for message in messages {
+        let content = message.get("content").and_then(Value::as_str);
+        let text = content
+            .filter(|text| !text.is_empty())
+            .map(|text| json!({"text": text}));
+        let (role, parts) = match message.get("role").and_then(Value::as_str) {
+            Some("system") => {
+                system.extend(text);
+                continue;
+            }
+            Some("user") => ("user", text.into_iter().chain(images(message)).collect()),
+            Some("assistant") => {
+                let mut parts: Vec<Value> = text.into_iter().collect();
+                for call in message
+                    .get("tool_calls")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|call| call.get("function"))
+                {
+                    let name = call.get("name").and_then(Value::as_str).unwrap_or_default();
+                    unanswered.push_back(name.to_string());
+                    let args = match call.get("arguments") {
+                        Some(Value::String(args)) => serde_json::from_str(args).unwrap_or_default(),
+                        Some(args) => args.clone(),
+                        None => json!({}),
+                    };
+                    parts.push(json!({"functionCall": {"name": name, "args": args}}));
+                }
+                ("model", parts)
+            }
+            Some("tool") => {
+                let name = match message.get("tool_name").and_then(Value::as_str) {
+                    Some(name) => {
+                        if let Some(at) = unanswered.iter().position(|call| call == name) {
+                            unanswered.remove(at);
+                        }
+                        name.to_string()
+                    }
+                    None => unanswered.pop_front().unwrap_or_default(),
+                };
+                let response = json!({"functionResponse": {
+                    "name": name,
+                    "response": {"content": content.unwrap_or_default()},
+                }});
+                ("user", vec![response])
+            }
+            role => return Err(format!("messages: unexpected role {role:?}")),
+        };
+        if parts.is_empty() {
+            continue;
+        }
+        // Gemini wants turns to alternate, while tool results come one
+        // message each.
+        match contents.last_mut() {
+            Some(last) if last["role"] == role => {
+                if let Some(last) = last["parts"].as_array_mut() {
+                    last.extend(parts);
+                }
+            }
+            _ => contents.push(json!({"role": role, "parts": parts})),
+        }
+    }
+
+    let mut gemini = Map::new();
+    gemini.insert("contents".into(), contents.into());
+    if !system.is_empty() {
+        gemini.insert("systemInstruction".into(), json!({"parts": system}));
+    }
+    if let Some(tools) = body.get("tools").and_then(Value::as_array) {
+        gemini.insert("tools".into(), self::tools(tools)?.into());
+    }
+    insert_config(&mut gemini, body);
+    Ok(gemini.into())
+}
+
+/// Translates a `/api/generate` request into a `generateContent` body.
+pub fn generate_request(body: &Value) -> Result<Value, String> {
+    let prompt = body
+        .get("prompt")
+        .and_then(Value::as_str)
+        .ok_or("prompt: field required")?;
+
+    let parts: Vec<Value> = std::iter::once(json!({"text": prompt}))
+        .chain(images(body))
+        .collect();
+    let mut gemini = Map::new();
+    gemini.insert("contents".into(), json!([{"role": "user", "parts": parts}]));
+    if let Some(system) = body.get("system").and_then(Value::as_str) {
+        gemini.insert(
+            "systemInstruction".into(),
+            json!({"parts": [{"text": system}]}),
+        );
+    }
+    insert_config(&mut gemini, body);
+    Ok(gemini.into())
+}
+
+/// Base64 images of a message or prompt, which Ollama sends without their
+/// type, as inline data.
+fn images(message: &Value) -> Vec<Value> {
+    message
+        .get("images")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(|data| {
+            // The base64 of each format's magic bytes.
+            let mime_type = match data {
+                _ if data.starts_with("iVBOR") => "image/png",
+                _ if data.starts_with("R0lGOD") => "image/gif",
+                _ if data.starts_with("UklGR") => "image/webp",
+                _ => "image/jpeg",
+            };
+            json!({"inlineData": {"mimeType": mime_type, "data": data}})
+        })
+        .collect()
+}
+
+fn tools(tools: &[Value]) -> Result<Vec<Value>, String> {
+    let declarations = tools
+        .iter()
+        .map(|tool| match tool.get("type").and_then(Value::as_str) {
+            None | Some("function") => {
+                let function = tool.get("function").unwrap_or(tool);
+                Ok(json!({
+                    "name": function.get("name"),
+                    "description": function.get("description").cloned().unwrap_or_default(),
+                    "parametersJsonSchema": function.get("parameters").cloned().unwrap_or_else(|| json!({"type": "object"})),
+                }))
+            }
+            Some(kind) => Err(format!("tools: unsupported tool type {kind}")),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(match declarations.is_empty() {
+        true => Vec::new(),
+        false => vec![json!({"functionDeclarations": declarations})],
+    })
+}
+
+/// Adds a `generationConfig` from the `options`, `format` and `think` both
+/// endpoints take.
+fn insert_config(gemini: &mut Map<String, Value>, body: &Value) {
+    let mut config = Map::new();
+    if let Some(options) = body.get("options") {
+        for (from, to) in [
+            ("temperature", "temperature"),
+            ("top_p", "topP"),
+            ("top_k", "topK"),
+            ("stop", "stopSequences"),
+            ("seed", "seed"),
+            ("presence_penalty", "presencePenalty"),
+            ("frequency_penalty", "frequencyPenalty"),
+        ] {
+            if let Some(value) = options.get(from) {
+                config.insert(to.into(), value.clone());
+            }
+        }
+        // Ollama takes -1 and -2 to mean no limit.
+        if let Some(limit) = options
+            .get("num_predict")
+            .and_then(Value::as_u64)
+            .filter(|&limit| limit > 0)
+        {
+            config.insert("maxOutputTokens".into(), limit.into());
+        }
+    }
+    match body.get("format") {
+        Some(Value::String(format)) if format == "json" => {
+            config.insert("responseMimeType".into(), "application/json".into());
+        }
+        Some(schema @ Value::Object(_)) => {
+            config.insert("responseMimeType".into(), "application/json".into());
+            config.insert("responseJsonSchema".into(), schema.clone());
+        }
+        _ => {}
+    }
+    match body.get("think") {
+        Some(Value::Bool(true)) => {
+            config.insert("thinkingConfig".into(), json!({"includeThoughts": true}));
+        }
+        Some(Value::String(effort)) => {
+            let mut thinking = json!({"includeThoughts": true});
+            if let Some(budget) = thinking_budget(effort) {
+                thinking["thinkingBudget"] = budget.into();
+            }
+            config.insert("thinkingConfig".into(), thinking);
+        }
+        _ => {}
+    }
+    if !config.is_empty() {
+        gemini.insert("generationConfig".into(), config.into());
+    }
+}
+
+/// A model of a Gemini listing as `/api/tags` lists it. Nothing about it is
+/// local, so sizes, digests and dates are left blank.
+pub fn tag(entry: &Value) -> Option<Value> {
+    let name = model_name(entry.get("name")?.as_str()?);
+    Some(json!({
+        "name": name,
+        "model": name,
+        "modified_at": "1970-01-01T00:00:00Z",
+        "size": 0,
+        "digest": "",
+        "details": {
+            "format": "gemini",
+            "family": "gemini",
+            "families": ["gemini"],
+            "parameter_size": "",
+            "quantization_level": "",
+        },
+    }))
+}
+
+/// Which endpoint an answer is for, as they shape it differently.
+#[derive(Clone, Copy)]
+pub enum Endpoint {
+    Chat,
+    Generate,
+}
+
+/// Translates a `streamGenerateContent` stream into Ollama's NDJSON stream,
+/// one object per upstream event and a last one with the counts.
+pub struct Stream {
+    endpoint: Endpoint,
+    model: String,
+    started: Instant,
+    finish_reason: Option<String>,
+    usage: Usage,
+    /// Set once an error line ended the stream.
+    failed: bool,
+}
+
+impl Stream {
+    /// For a request to `endpoint` for `model`, the model the client asked
+    /// for.
+    pub fn new(endpoint: Endpoint, model: &str) -> Self {
+        Self {
+            endpoint,
+            model: model.to_string(),
+            started: Instant::now(),
+            finish_reason: None,
+            usage: Usage::default(),
+            failed: false,
+        }
+    }
+
+    /// The answer for `answer`, a whole `generateContent` answer.
+    pub fn complete(mut self, answer: &Value) -> Value {
+        self.observe(answer);
+        self.done(self.chunk(parts(answer)))
+    }
+
+    fn observe(&mut self, event: &Value) {
+        if let Some(usage) = Usage::of(event) {
+            self.usage = usage;
+        }
+        if let Some(reason) = finish_reason(event) {
+            self.finish_reason = Some(reason.to_string());
+        }
+    }
+
+    /// What `parts` of the answer add, in the endpoint's shape.
+    fn chunk(&self, parts: &[Value]) -> Value {
+        let (mut content, mut thinking, mut tool_calls) = (String::new(), String::new(), vec![]);
+        for part in parts {
+            let text = part.get("text").and_then(Value::as_str);
+            if let Some(call) = part.get("functionCall") {
+                tool_calls.push(json!({"function": {
+                    "name": call.get("name"),
+                    "arguments": call.get("args").cloned().unwrap_or_else(|| json!({})),
+                }}));
+            } else if part.get("thought").and_then(Value::as_bool) == Some(true) {
+                thinking.push_str(text.unwrap_or_default());
+            } else {
+                content.push_str(text.unwrap_or_default());
+            }
+        }
+
+        let created_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+        let mut chunk = json!({"model": self.model, "created_at": created_at});
+        match self.endpoint {
+            Endpoint::Chat => {
+                let mut message = json!({"role": "assistant", "content": content});
+                if !thinking.is_empty() {
+                    message["thinking"] = thinking.into();
+                }
+                if !tool_calls.is_empty() {
+                    message["tool_calls"] = tool_calls.into();
+                }
+                chunk["message"] = message;
+            }
+            Endpoint::Generate => {
+                chunk["response"] = content.into();
+                if !thinking.is_empty() {
+                    chunk["thinking"] = thinking.into();
+                }
+            }
+        }
+        chunk
+    }
+
+    /// `chunk` as the last of the answer, with its counts.
+    fn done(&self, mut chunk: Value) -> Value {
+        let elapsed = self.started.elapsed().as_nanos() as u64;
+        chunk["done"] = true.into();
+        chunk["done_reason"] = match self.finish_reason.as_deref() {
+            Some("MAX_TOKENS") => "length",
+            _ => "stop",
+        }
+        .into();
+        if let Endpoint::Generate = self.endpoint {
+            chunk["context"] = json!([]);
+        }
+        chunk["total_duration"] = elapsed.into();
+        chunk["prompt_eval_count"] = self.usage.prompt.into();
+        chunk["eval_count"] = self.usage.output.into();
+        chunk["eval_duration"] = elapsed.into();
+        chunk
+    }
+}
+
+impl Translator for Stream {
+    fn event(&mut self, event: &Value) -> String {
+        if self.failed {
+            return String::new();
+        }
+        if let Some(failure) = event.get("error") {
+            self.failed = true;
+            let message = failure
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("Upstream error");
+            return format!("{}\n", error(500, message));
+        }
+
+        self.observe(event);
+        let parts = parts(event);
+        if parts.is_empty() {
+            return String::new();
+        }
+        let mut chunk = self.chunk(parts);
+        chunk["done"] = false.into();
+        format!("{chunk}\n")
+    }
+
+    fn end(&mut self) -> String {
+        if self.failed {
+            return String::new();
+        }
+        format!("{}\n", self.done(self.chunk(&[])))
+    }
+}
+
+/// Translates an `/api/embed` request into a `batchEmbedContents` body for
+/// `model`.
+pub fn embed_request(body: &Value, model: &str) -> Result<Value, String> {
+    let inputs = match body.get("input") {
+        Some(Value::String(input)) => vec![input.as_str()],
+        Some(Value::Array(inputs)) => inputs.iter().filter_map(Value::as_str).collect(),
+        _ => return Err("input: field required".into()),
+    };
+    let requests: Vec<Value> = inputs
+        .into_iter()
+        .map(|input| {
+            let mut request = json!({
+                "model": format!("models/{model}"),
+                "content": {"parts": [{"text": input}]},
+            });
+            if let Some(dimensions) = body.get("dimensions") {
+                request["outputDimensionality"] = dimensions.clone();
+            }
+            request
+        })
+        .collect();
+    Ok(json!({"requests": requests}))
+}
+
+/// Translates a `batchEmbedContents` answer into an `/api/embed` one.
+pub fn embed_response(gemini: &Value, model: &str, started: Instant) -> Value {
+    let embeddings: Vec<&Value> = gemini
+        .get("embeddings")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|embedding| embedding.get("values"))
+        .collect();
+    json!({
+        "model": model,
+        "embeddings": embeddings,
+        "total_duration": started.elapsed().as_nanos() as u64,
+    })
+}
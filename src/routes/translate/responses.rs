@@ -0,0 +1,700 @@
+//! The OpenAI Responses API.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::{Map, Value, json};
+
+use super::{
+    Translator, Usage, blocked, filtered, finish_reason, parts, push, random_id, thinking_budget,
+    url_part,
+};
+use crate::routes::dialect::Dialect;
+use crate::utils::{Conversation, ConversationStore};
+
+/// An `error` body as the Responses API sends it, the same as the rest of
+/// the OpenAI API.
+pub fn error(status: u16, message: &str) -> Value {
+    Dialect::OpenAi.error(status, message)
+}
+
+/// Translates a Responses request into a `generateContent` body, following
+/// `history`, the contents of the response it continues from.
+pub fn request(body: &Value, history: &[Value]) -> Result<Value, String> {
+    // Call outputs only carry the id of the call they answer, while Gemini
+    // wants the function's name too.
+    let mut tool_names = HashMap::new();
+    for call in history
+        .iter()
+        .filter_map(|content| content.get("parts").and_then(Value::as_array))
+        .flatten()
+        .filter_map(|part| part.get("functionCall"))
+    {
+        if let (Some(id), Some(name)) = (
+            call.get("id").and_then(Value::as_str),
+            call.get("name").and_then(Value::as_str),
+        ) {
+            tool_names.insert(id.to_string(), name.to_string());
+        }
+    }
+
+    let mut contents = history.to_vec();
+    let mut system = Vec::new();
+    if let Some(instructions) = body.get("instructions").and_then(Value::as_str) {
+        system.push(json!({"text": instructions}));
+    }
+    match body.get("input") {
+        Some(Value::String(text)) => push(&mut contents, "user", json!({"text": text})),
+        Some(Value::Array(items)) => {
+            input_items(items, &mut contents, &mut system, &mut tool_names)?
+        }
+        None => {}
+        Some(_) => return Err("input: must be a string or a list of items".into()),
+    }
+    if contents.is_empty() {
+        return Err("input: field required".into());
+    }
+
+    let mut gemini = Map::new();
+    gemini.insert("contents".into(), contents.into());
+    if !system.is_empty() {
+        gemini.insert("systemInstruction".into(), json!({"parts": system}));
+    }
+    if let Some(tools) = body.get("tools").and_then(Value::as_array) {
+        gemini.insert("tools".into(), self::tools(tools)?.into());
+    }
+    if let Some(choice) = body.get("tool_choice") {
+        let (mode, only) = match choice {
+            Value::String(mode) => match mode.as_str() {
+                "none" => ("NONE", None),
+                "required" => ("ANY", None),
+                _ => ("AUTO", None),
+            },
+            _ => match choice.get("type").and_then(Value::as_str) {
+                Some("function") => ("ANY", choice.get("name")),
+                _ => ("AUTO", None),
+            },
+        };
+        let mut config = json!({"mode": mode});
+        if let Some(name) = only {
+            config["allowedFunctionNames"] = json!([name]);
+        }
+        gemini.insert(
+            "toolConfig".into(),
+            json!({"functionCallingConfig": config}),
+        );
+    }
+
+    let mut config = Map::new();
+    for (from, to) in [
+        ("max_output_tokens", "maxOutputTokens"),
+        ("temperature", "temperature"),
+        ("top_p", "topP"),
+    ] {
+        if let Some(value) = body.get(from).filter(|value| !value.is_null()) {
+            config.insert(to.into(), value.clone());
+        }
+    }
+    match body.pointer("/text/format/type").and_then(Value::as_str) {
+        Some("json_schema") => {
+            config.insert("responseMimeType".into(), "application/json".into());
+            if let Some(schema) = body.pointer("/text/format/schema") {
+                config.insert("responseJsonSchema".into(), schema.clone());
+            }
+        }
+        Some("json_object") => {
+            config.insert("responseMimeType".into(), "application/json".into());
+        }
+        _ => {}
+    }
+    if let Some(reasoning) = body.get("reasoning") {
+        let mut thinking = Map::new();
+        if let Some(budget) = reasoning
+            .get("effort")
+            .and_then(Value::as_str)
+            .and_then(thinking_budget)
+        {
+            thinking.insert("thinkingBudget".into(), budget.into());
+        }
+        if reasoning
+            .get("summary")
+            .is_some_and(|summary| !summary.is_null())
+        {
+            thinking.insert("includeThoughts".into(), true.into());
+        }
+        if !thinking.is_empty() {
+            config.insert("thinkingConfig".into(), thinking.into());
+        }
+    }
+    if !config.is_empty() {
+        gemini.insert("generationConfig".into(), config.into());
+    }
+
+    Ok(gemini.into())
+}
+
+fn input_items(
+    items: &[Value],
+    contents: &mut Vec<Value>,
+    system: &mut Vec<Value>,
+    tool_names: &mut HashMap<String, String>,
+) -> Result<(), String> {
+    // Thought signatures come back on reasoning items, and belong to the part
+    // that follows them.
+    let mut signature = None;
+    for item in items {
+        let field = |name| item.get(name).and_then(Value::as_str).unwrap_or_default();
+        match item
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("message")
+        {
+            "message" => {
+                let role = match item.get("role").and_then(Value::as_str) {
+                    Some("user") => "user",
+                    Some("assistant") => "model",
+                    Some("system" | "developer") => {
+                        system.extend(content_parts(item.get("content"))?);
+                        continue;
+                    }
+                    role => return Err(format!("input: unexpected role {role:?}")),
+                };
+                for mut part in content_parts(item.get("content"))? {
+                    if role == "model"
+                        && let Some(signature) = signature.take()
+                    {
+                        part["thoughtSignature"] = signature;
+                    }
+                    push(contents, role, part);
+                }
+            }
+            "function_call" => {
+                let (id, name) = (field("call_id"), field("name"));
+                tool_names.insert(id.to_string(), name.to_string());
+                let args =
+                    serde_json::from_str::<Value>(field("arguments")).unwrap_or_else(|_| json!({}));
+                let mut part = json!({"functionCall": {"id": id, "name": name, "args": args}});
+                if let Some(signature) = signature.take() {
+                    part["thoughtSignature"] = signature;
+                }
+                push(contents, "model", part);
+            }
+            "function_call_output" => {
+                let id = field("call_id");
+                let (output, media) = call_output(item.get("output"));
+                let part = json!({"functionResponse": {
+                    "id": id,
+                    "name": tool_names.get(id).cloned().unwrap_or_default(),
+                    "response": {"output": output},
+                }});
+                push(contents, "user", part);
+                for part in media {
+                    push(contents, "user", part);
+                }
+            }
+            "reasoning" => {
+                signature = item
+                    .get("encrypted_content")
+                    .filter(|signature| signature.as_str().is_some_and(|s| !s.is_empty()))
+                    .cloned();
+            }
+            kind => return Err(format!("input: unsupported item type {kind}")),
+        }
+    }
+    Ok(())
+}
+
+fn content_parts(content: Option<&Value>) -> Result<Vec<Value>, String> {
+    let items = match content {
+        Some(Value::String(text)) => return Ok(vec![json!({"text": text})]),
+        Some(Value::Array(items)) => items,
+        _ => return Err("input: content must be a string or a list of parts".into()),
+    };
+    Ok(items.iter().filter_map(content_part).collect())
+}
+
+fn content_part(item: &Value) -> Option<Value> {
+    let url = |name| item.get(name).and_then(Value::as_str);
+    match item.get("type").and_then(Value::as_str)? {
+        "input_text" | "output_text" => Some(json!({"text": item.get("text")?})),
+        "refusal" => Some(json!({"text": item.get("refusal")?})),
+        "input_image" => Some(url_part(url("image_url")?)),
+        "input_file" => Some(url_part(url("file_data").or(url("file_url"))?)),
+        _ => None,
+    }
+}
+
+/// The text of a function call's output, and any images or files in it,
+/// which Gemini takes as parts of their own.
+fn call_output(output: Option<&Value>) -> (String, Vec<Value>) {
+    match output {
+        Some(Value::String(text)) => (text.clone(), Vec::new()),
+        Some(Value::Array(items)) => {
+            let text = items
+                .iter()
+                .filter(|item| item.get("type").and_then(Value::as_str) == Some("input_text"))
+                .filter_map(|item| item.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let media = items
+                .iter()
+                .filter(|item| item.get("type").and_then(Value::as_str) != Some("input_text"))
+                .filter_map(content_part)
+                .collect();
+            (text, media)
+        }
+        _ => (String::new(), Vec::new()),
+    }
+}
+
+fn tools(tools: &[Value]) -> Result<Vec<Value>, String> {
+    let mut declarations = Vec::new();
+    let mut gemini = Vec::new();
+    for tool in tools {
+        match tool.get("type").and_then(Value::as_str) {
+            Some("function") => declarations.push(json!({
+                "name": tool.get("name"),
+                "description": tool.get("description").cloned().unwrap_or_default(),
+                "parametersJsonSchema": tool.get("parameters").cloned().unwrap_or_else(|| json!({"type": "object"})),
+            })),
+            Some(kind) if kind.starts_with("web_search") => {
+                gemini.push(json!({"googleSearch": {}}));
+            }
+            kind => return Err(format!("tools: unsupported tool type {kind:?}")),
+        }
+    }
+    if !declarations.is_empty() {
+        gemini.push(json!({"functionDeclarations": declarations}));
+    }
+    Ok(gemini)
+}
+
+fn usage(usage: Usage) -> Value {
+    json!({
+        "input_tokens": usage.prompt,
+        "input_tokens_details": {"cached_tokens": usage.cached},
+        "output_tokens": usage.output,
+        "output_tokens_details": {"reasoning_tokens": usage.thoughts},
+        "total_tokens": usage.prompt + usage.output,
+    })
+}
+
+fn output_text(text: &str) -> Value {
+    json!({"type": "output_text", "text": text, "annotations": []})
+}
+
+fn summary_text(text: &str) -> Value {
+    json!({"type": "summary_text", "text": text})
+}
+
+/// Where a response goes once answered, for later requests to continue from.
+pub struct Keep {
+    pub store: Arc<ConversationStore>,
+    /// Name of the client asking.
+    pub client: String,
+    /// The contents the response answers.
+    pub contents: Vec<Value>,
+}
+
+/// The output item being streamed, while it can take more deltas.
+enum Open {
+    Message {
+        id: String,
+        text: String,
+    },
+    Reasoning {
+        id: String,
+        summary: Option<String>,
+        signature: Option<String>,
+    },
+}
+
+/// Translates a `streamGenerateContent` stream into Responses stream events,
+/// and builds the response they add up to.
+pub struct Stream {
+    /// The response, as the stream started it.
+    response: Value,
+    sequence: u64,
+    started: bool,
+    /// Output items done streaming.
+    output: Vec<Value>,
+    open: Option<Open>,
+    finish_reason: Option<String>,
+    blocked: bool,
+    usage: Option<Usage>,
+    /// Set once an error event ended the stream.
+    failed: bool,
+    /// The answer's parts, as Gemini sent them, for the conversation store.
+    parts: Vec<Value>,
+    keep: Option<Keep>,
+}
+
+impl Stream {
+    /// For `body`, a request for `model`, the model the client asked for.
+    pub fn new(body: &Value, model: &str, keep: Option<Keep>) -> Self {
+        let field = |name, default| body.get(name).cloned().unwrap_or(default);
+        let response = json!({
+            "id": random_id("resp_"),
+            "object": "response",
+            "created_at": chrono::Utc::now().timestamp(),
+            "status": "in_progress",
+            "model": model,
+            "output": [],
+            "error": null,
+            "incomplete_details": null,
+            "instructions": field("instructions", Value::Null),
+            "previous_response_id": field("previous_response_id", Value::Null),
+            "max_output_tokens": field("max_output_tokens", Value::Null),
+            "temperature": field("temperature", Value::Null),
+            "top_p": field("top_p", Value::Null),
+            "tools": field("tools", json!([])),
+            "tool_choice": field("tool_choice", json!("auto")),
+            "parallel_tool_calls": field("parallel_tool_calls", json!(true)),
+            "text": field("text", json!({"format": {"type": "text"}})),
+            "reasoning": field("reasoning", json!({"effort": null, "summary": null})),
+            "store": keep.is_some(),
+            "metadata": field("metadata", json!({})),
+            "usage": null,
+        });
+        Self {
+            response,
+            sequence: 0,
+            started: false,
+            output: Vec::new(),
+            open: None,
+            finish_reason: None,
+            blocked: false,
+            usage: None,
+            failed: false,
+            parts: Vec::new(),
+            keep,
+        }
+    }
+
+    /// The response for `answer`, a whole `generateContent` answer, built as
+    /// if it had been streamed.
+    pub fn complete(mut self, answer: &Value) -> Value {
+        self.event(answer);
+        self.finish(&mut String::new())
+    }
+
+    /// Appends an SSE event named `kind`, whose data carries its kind as
+    /// `type` and its place in the stream, like every Responses stream event.
+    fn emit(&mut self, out: &mut String, kind: &str, mut data: Value) {
+        data["type"] = kind.into();
+        data["sequence_number"] = self.sequence.into();
+        self.sequence += 1;
+        out.push_str(&format!("event: {kind}\ndata: {data}\n\n"));
+    }
+
+    fn start(&mut self, out: &mut String) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        let response = json!({"response": self.response});
+        self.emit(out, "response.created", response.clone());
+        self.emit(out, "response.in_progress", response);
+    }
+
+    fn add_item(&mut self, out: &mut String, item: &Value) {
+        let index = self.output.len();
+        self.emit(
+            out,
+            "response.output_item.added",
+            json!({"output_index": index, "item": item}),
+        );
+    }
+
+    fn done_item(&mut self, out: &mut String, item: Value) {
+        let index = self.output.len();
+        self.emit(
+            out,
+            "response.output_item.done",
+            json!({"output_index": index, "item": item}),
+        );
+        self.output.push(item);
+    }
+
+    fn close(&mut self, out: &mut String) {
+        let index = self.output.len();
+        match self.open.take() {
+            None => {}
+            Some(Open::Message { id, text }) => {
+                let at = json!({"item_id": id, "output_index": index, "content_index": 0});
+                let mut done = at.clone();
+                done["text"] = text.clone().into();
+                done["logprobs"] = json!([]);
+                self.emit(out, "response.output_text.done", done);
+                let mut done = at;
+                done["part"] = output_text(&text);
+                self.emit(out, "response.content_part.done", done);
+                let item = json!({
+                    "id": id,
+                    "type": "message",
+                    "status": "completed",
+                    "role": "assistant",
+                    "content": [output_text(&text)],
+                });
+                self.done_item(out, item);
+            }
+            Some(Open::Reasoning {
+                id,
+                summary,
+                signature,
+            }) => {
+                let mut item = json!({"id": id, "type": "reasoning", "summary": []});
+                if let Some(text) = summary {
+                    let at = json!({"item_id": id, "output_index": index, "summary_index": 0});
+                    let mut done = at.clone();
+                    done["text"] = text.clone().into();
+                    self.emit(out, "response.reasoning_summary_text.done", done);
+                    let mut done = at;
+                    done["part"] = summary_text(&text);
+                    self.emit(out, "response.reasoning_summary_part.done", done);
+                    item["summary"] = json!([summary_text(&text)]);
+                }
+                if let Some(signature) = signature {
+                    item["encrypted_content"] = signature.into();
+                }
+                self.done_item(out, item);
+            }
+        }
+    }
+
+    fn text(&mut self, out: &mut String, delta: &str) {
+        let index = self.output.len();
+        if !matches!(self.open, Some(Open::Message { .. })) {
+            self.close(out);
+            let id = random_id("msg_");
+            let item = json!({
+                "id": id,
+                "type": "message",
+                "status": "in_progress",
+                "role": "assistant",
+                "content": [],
+            });
+            self.add_item(out, &item);
+            self.emit(
+                out,
+                "response.content_part.added",
+                json!({"item_id": id, "output_index": index, "content_index": 0, "part": output_text("")}),
+            );
+            self.open = Some(Open::Message {
+                id,
+                text: String::new(),
+            });
+        }
+        let Some(Open::Message { id, text }) = &mut self.open else {
+            return;
+        };
+        text.push_str(delta);
+        let id = id.clone();
+        self.emit(
+            out,
+            "response.output_text.delta",
+            json!({"item_id": id, "output_index": index, "content_index": 0, "delta": delta, "logprobs": []}),
+        );
+    }
+
+    fn reasoning(&mut self, out: &mut String) {
+        if matches!(self.open, Some(Open::Reasoning { .. })) {
+            return;
+        }
+        self.close(out);
+        let id = random_id("rs_");
+        self.add_item(out, &json!({"id": id, "type": "reasoning", "summary": []}));
+        self.open = Some(Open::Reasoning {
+            id,
+            summary: None,
+            signature: None,
+        });
+    }
+
+    fn thought(&mut self, out: &mut String, delta: &str) {
+        self.reasoning(out);
+        let index = self.output.len();
+        let Some(Open::Reasoning { id, summary, .. }) = &mut self.open else {
+            return;
+        };
+        let id = id.clone();
+        let started = summary.is_some();
+        summary.get_or_insert_default().push_str(delta);
+        let at = json!({"item_id": id, "output_index": index, "summary_index": 0});
+        if !started {
+            let mut added = at.clone();
+            added["part"] = summary_text("");
+            self.emit(out, "response.reasoning_summary_part.added", added);
+        }
+        let mut delta_event = at;
+        delta_event["delta"] = delta.into();
+        self.emit(out, "response.reasoning_summary_text.delta", delta_event);
+    }
+
+    fn signature(&mut self, out: &mut String, signature: &str) {
+        self.reasoning(out);
+        if let Some(Open::Reasoning {
+            signature: kept, ..
+        }) = &mut self.open
+        {
+            *kept = Some(signature.to_string());
+        }
+    }
+
+    /// Streams a function call as one item, and gives the id it goes by.
+    fn function_call(&mut self, out: &mut String, call: &Value) -> String {
+        self.close(out);
+        let index = self.output.len();
+        let call_id = call
+            .get("id")
+            .and_then(Value::as_str)
+            .map_or_else(|| random_id("call_"), str::to_string);
+        let arguments = call
+            .get("args")
+            .cloned()
+            .unwrap_or_else(|| json!({}))
+            .to_string();
+        let id = random_id("fc_");
+        let mut item = json!({
+            "id": id,
+            "type": "function_call",
+            "status": "in_progress",
+            "call_id": call_id,
+            "name": call.get("name"),
+            "arguments": "",
+        });
+        self.add_item(out, &item);
+        self.emit(
+            out,
+            "response.function_call_arguments.delta",
+            json!({"item_id": id, "output_index": index, "delta": arguments}),
+        );
+        self.emit(
+            out,
+            "response.function_call_arguments.done",
+            json!({"item_id": id, "output_index": index, "arguments": arguments}),
+        );
+        item["status"] = "completed".into();
+        item["arguments"] = arguments.into();
+        self.done_item(out, item);
+        call_id
+    }
+
+    /// Closes the answer, stores it if asked to, and gives the response it
+    /// adds up to.
+    fn finish(&mut self, out: &mut String) -> Value {
+        self.close(out);
+        let incomplete = match self.finish_reason.as_deref() {
+            _ if self.blocked => Some("content_filter"),
+            Some("MAX_TOKENS") => Some("max_output_tokens"),
+            Some(reason) if filtered(reason) => Some("content_filter"),
+            _ => None,
+        };
+
+        let mut response = self.response.clone();
+        response["status"] = match incomplete {
+            Some(_) => "incomplete",
+            None => "completed",
+        }
+        .into();
+        if let Some(reason) = incomplete {
+            response["incomplete_details"] = json!({"reason": reason});
+        }
+        response["output"] = self.output.clone().into();
+        if let Some(counted) = self.usage {
+            response["usage"] = usage(counted);
+        }
+
+        if let Some(keep) = self.keep.take() {
+            let mut contents = keep.contents;
+            if !self.parts.is_empty() {
+                let parts = std::mem::take(&mut self.parts);
+                contents.push(json!({"role": "model", "parts": parts}));
+            }
+            let id = response["id"].as_str().unwrap_or_default().to_string();
+            keep.store.put(
+                &id,
+                Conversation {
+                    client: keep.client,
+                    contents,
+                    response: response.clone(),
+                },
+            );
+        }
+        response
+    }
+}
+
+impl Translator for Stream {
+    fn event(&mut self, event: &Value) -> String {
+        let mut out = String::new();
+        if self.failed {
+            return out;
+        }
+        if let Some(failure) = event.get("error") {
+            self.failed = true;
+            let message = failure
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("Upstream error");
+            self.start(&mut out);
+            let mut response = self.response.clone();
+            response["status"] = "failed".into();
+            response["output"] = self.output.clone().into();
+            response["error"] = json!({"code": "server_error", "message": message});
+            self.emit(&mut out, "response.failed", json!({"response": response}));
+            return out;
+        }
+
+        if let Some(usage) = Usage::of(event) {
+            self.usage = Some(usage);
+        }
+        self.start(&mut out);
+
+        for part in parts(event) {
+            let mut kept = part.clone();
+            let text = part.get("text").and_then(Value::as_str);
+            let signature = part.get("thoughtSignature").and_then(Value::as_str);
+            if part.get("thought").and_then(Value::as_bool) == Some(true) {
+                if let Some(text) = text.filter(|text| !text.is_empty()) {
+                    self.thought(&mut out, text);
+                }
+                if let Some(signature) = signature {
+                    self.signature(&mut out, signature);
+                }
+            } else if let Some(call) = part.get("functionCall") {
+                if let Some(signature) = signature {
+                    self.signature(&mut out, signature);
+                }
+                // Calls Gemini doesn't name are stored under the id the
+                // client will answer them with.
+                let call_id = self.function_call(&mut out, call);
+                kept["functionCall"]["id"] = call_id.into();
+            } else if let Some(text) = text.filter(|text| !text.is_empty()) {
+                self.text(&mut out, text);
+            }
+            self.parts.push(kept);
+        }
+
+        if let Some(reason) = finish_reason(event) {
+            self.finish_reason = Some(reason.to_string());
+        }
+        self.blocked |= blocked(event);
+        out
+    }
+
+    fn end(&mut self) -> String {
+        let mut out = String::new();
+        if self.failed {
+            return out;
+        }
+        self.start(&mut out);
+        let response = self.finish(&mut out);
+        let kind = match response["status"].as_str() {
+            Some("incomplete") => "response.incomplete",
+            _ => "response.completed",
+        };
+        self.emit(&mut out, kind, json!({"response": response}));
+        out
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use colored::Colorize;
+use log::{info, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::config::{ClientConfig, ClientLimits, ClientPolicy, ConfigInner, Secret};
+use super::juggler::next_daily_reset;
+
+const MINUTE: chrono::Duration = chrono::Duration::minutes(1);
+/// Most unknown keys remembered at once.
+const MAX_REJECTED: usize = 1024;
+
+#[derive(Default)]
+struct Usage {
+    requests: u64,
+    tokens: u64,
+    /// When the requests of the last minute were admitted.
+    recent: VecDeque<DateTime<Utc>>,
+    requests_today: u64,
+    tokens_today: u64,
+    today_resets_at: Option<DateTime<Utc>>,
+    in_flight: u64,
+}
+
+impl Usage {
+    fn catch_up(&mut self, now: DateTime<Utc>) {
+        while self.recent.front().is_some_and(|at| *at + MINUTE <= now) {
+            self.recent.pop_front();
+        }
+        if self.today_resets_at.is_some_and(|at| at <= now) {
+            self.requests_today = 0;
+            self.tokens_today = 0;
+            self.today_resets_at = None;
+        }
+    }
+}
+
+/// A caller of the proxy, and what it has used so far.
+pub struct Client {
+    pub name: String,
+    secret: Secret,
+    revoked: bool,
+    limits: ClientLimits,
+    pub policy: ClientPolicy,
+    daily_reset_timezone: Tz,
+    usage: Mutex<Usage>,
+}
+
+/// Why a client's request was turned away.
+pub struct OverLimit {
+    pub limit: &'static str,
+    /// When the client is back under the limit, if known.
+    pub retry_after: Option<DateTime<Utc>>,
+}
+
+/// A request a client was allowed to make, in flight until dropped.
+pub struct Admission {
+    client: Arc<Client>,
+}
+
+impl Admission {
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.client.usage.lock().unwrap().in_flight -= 1;
+    }
+}
+
+impl Client {
+    fn new(config: ClientConfig, daily_reset_timezone: Tz) -> Self {
+        Self {
+            name: config.name,
+            secret: config.secret,
+            revoked: config.revoked,
+            limits: config.limits,
+            policy: config.policy,
+            daily_reset_timezone,
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+
+    fn matches(&self, presented: &str) -> bool {
+        match &self.secret {
+            // Digests are compared rather than the keys themselves, so the
+            // time it takes says nothing about how close a guess was.
+            Secret::Key(key) => Sha256::digest(presented) == Sha256::digest(key),
+            Secret::KeySha256(hash) => hex(&Sha256::digest(presented)).eq_ignore_ascii_case(hash),
+            Secret::KeyArgon2(hash) => PasswordHash::new(hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(presented.as_bytes(), &hash)
+                    .is_ok()
+            }),
+        }
+    }
+
+    /// Counts a request against the client's limits, unless it would go over
+    /// one of them.
+    pub fn admit(self: &Arc<Self>) -> Result<Admission, OverLimit> {
+        let now = Utc::now();
+        let mut usage = self.usage.lock().unwrap();
+        usage.catch_up(now);
+
+        let limits = &self.limits;
+        let over = |limit, retry_after| Err(OverLimit { limit, retry_after });
+        if let Some(max) = limits.max_concurrent
+            && usage.in_flight >= max
+        {
+            return over("concurrent requests", None);
+        }
+        if let Some(rpm) = limits.rpm.map(|rpm| rpm.max(1) as usize)
+            && usage.recent.len() >= rpm
+        {
+            let idx = usage.recent.len() - rpm;
+            return over("requests per minute", Some(usage.recent[idx] + MINUTE));
+        }
+        if let Some(rpd) = limits.rpd
+            && usage.requests_today >= rpd
+        {
+            return over("requests per day", usage.today_resets_at);
+        }
+        if let Some(tpd) = limits.tpd
+            && usage.tokens_today >= tpd
+        {
+            return over("tokens per day", usage.today_resets_at);
+        }
+
+        usage.requests += 1;
+        usage.requests_today += 1;
+        usage.recent.push_back(now);
+        usage.in_flight += 1;
+        usage
+            .today_resets_at
+            .get_or_insert_with(|| next_daily_reset(now, self.daily_reset_timezone));
+
+        Ok(Admission {
+            client: self.clone(),
+        })
+    }
+
+    pub fn record_tokens(&self, tokens: u64) {
+        let now = Utc::now();
+        let mut usage = self.usage.lock().unwrap();
+        usage.catch_up(now);
+        usage.tokens += tokens;
+        usage.tokens_today += tokens;
+        usage
+            .today_resets_at
+            .get_or_insert_with(|| next_daily_reset(now, self.daily_reset_timezone));
+    }
+}
+
+#[derive(Serialize)]
+pub struct ClientStatus {
+    pub name: String,
+    pub revoked: bool,
+    pub num_requests: u64,
+    pub num_tokens: u64,
+    pub requests_today: u64,
+    pub tokens_today: u64,
+    pub in_flight: u64,
+}
+
+pub struct Clients {
+    clients: Vec<Arc<Client>>,
+    /// Which client a key digest belongs to, once verified, so argon2 hashes
+    /// are only checked once per key.
+    verified: Mutex<HashMap<Vec<u8>, usize>>,
+    /// Digests of recent keys that belong to no client, so a wrong key sent
+    /// over and over isn't checked against every argon2 hash each time.
+    /// The oldest are forgotten first.
+    rejected: Mutex<VecDeque<Vec<u8>>>,
+}
+
+impl Clients {
+    pub fn new(config: &ConfigInner) -> Self {
+        let legacy = config.api_key.iter().map(|key| ClientConfig {
+            name: "default".to_string(),
+            secret: Secret::Key(key.clone()),
+            revoked: false,
+            limits: ClientLimits::default(),
+            policy: ClientPolicy::default(),
+        });
+        let timezone = config.cooldowns.daily_reset_timezone;
+        let clients: Vec<Arc<Client>> = legacy
+            .chain(config.clients.iter().cloned())
+            .map(|client| Arc::new(Client::new(client, timezone)))
+            .collect();
+
+        let active = clients.iter().filter(|client| !client.revoked).count();
+        info!(
+            "accepting {} {}",
+            active.to_string().cyan().bold(),
+            if active == 1 { "client" } else { "clients" }
+        );
+
+        Self {
+            clients,
+            verified: Mutex::new(HashMap::new()),
+            rejected: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The client `presented` belongs to, unless it's unknown or revoked.
+    pub fn authenticate(&self, presented: &str) -> Option<Arc<Client>> {
+        let digest = Sha256::digest(presented).to_vec();
+        let cached = self.verified.lock().unwrap().get(&digest).copied();
+        let idx = match cached {
+            Some(idx) => idx,
+            None => {
+                if self.rejected.lock().unwrap().contains(&digest) {
+                    return None;
+                }
+                let Some(idx) = self
+                    .clients
+                    .iter()
+                    .position(|client| client.matches(presented))
+                else {
+                    let mut rejected = self.rejected.lock().unwrap();
+                    if rejected.len() == MAX_REJECTED {
+                        rejected.pop_front();
+                    }
+                    rejected.push_back(digest);
+                    return None;
+                };
+                self.verified.lock().unwrap().insert(digest, idx);
+                idx
+            }
+        };
+
+        let client = &self.clients[idx];
+        if client.revoked {
+            warn!("turned away revoked client {}", client.name.cyan());
+            return None;
+        }
+        Some(client.clone())
+    }
+
+    /// The client called `name`, unless it's revoked.
+    pub fn named(&self, name: &str) -> Option<Arc<Client>> {
+        self.clients
+            .iter()
+            .find(|client| client.name == name && !client.revoked)
+            .cloned()
+    }
+
+    pub fn get_status(&self) -> Vec<ClientStatus> {
+        let now = Utc::now();
+        self.clients
+            .iter()
+            .map(|client| {
+                let mut usage = client.usage.lock().unwrap();
+                usage.catch_up(now);
+                ClientStatus {
+                    name: client.name.clone(),
+                    revoked: client.revoked,
+                    num_requests: usage.requests,
+                    num_tokens: usage.tokens,
+                    requests_today: usage.requests_today,
+                    tokens_today: usage.tokens_today,
+                    in_flight: usage.in_flight,
+                }
+            })
+            .collect()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -1,9 +1,13 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use easy_config_store::ConfigStore;
 use eyre::Result;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, de};
+
+use super::policy::glob_match;
+use super::strategy::Strategy;
 
 pub type Config = Arc<ConfigStore<ConfigInner>>;
 
@@ -13,10 +17,351 @@ pub fn config(path: PathBuf) -> Result<Config> {
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ConfigInner {
-    pub api_key: String,
-    pub keys: Vec<String>,
+    /// Shorthand for a single client named `default`.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub clients: Vec<ClientConfig>,
+    pub keys: Vec<KeyEntry>,
     pub host: String,
     pub port: u16,
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+    #[serde(default = "default_upstream_url")]
+    pub upstream_url: String,
+    #[serde(default)]
+    pub upstream_overrides: UpstreamOverrides,
+    #[serde(default)]
+    pub cooldowns: Cooldowns,
+    #[serde(default)]
+    pub limits: HashMap<String, Limits>,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub streaming: Streaming,
+    #[serde(default)]
+    pub queue: Queue,
+    #[serde(default)]
+    pub models: Models,
+    #[serde(default)]
+    pub responses: Responses,
+    #[serde(default)]
+    pub ollama: Ollama,
+    #[serde(default)]
+    pub openai: OpenAi,
+    #[serde(default)]
+    pub providers: Vec<Provider>,
+}
+
+impl ConfigInner {
+    /// The model `name` stands for, following `models.aliases`.
+    pub fn resolve_model<'a>(&'a self, name: &'a str) -> &'a str {
+        self.models.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    /// The provider requests for `model` go to instead of Gemini, the first
+    /// one whose `models` match it.
+    pub fn provider(&self, model: &str) -> Option<&Provider> {
+        self.providers.iter().find(|provider| {
+            provider
+                .models
+                .iter()
+                .any(|pattern| glob_match(pattern, model))
+        })
+    }
+
+    /// Whether chat completions for `model` are translated into native
+    /// Gemini requests, following `openai`.
+    pub fn native_openai(&self, model: &str) -> bool {
+        self.openai.native
+            || self
+                .openai
+                .native_models
+                .iter()
+                .any(|pattern| glob_match(pattern, model))
+    }
+}
+
+/// How long answers of the Responses API are kept around for later turns to
+/// continue from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Responses {
+    /// How long a stored response can be continued from or retrieved.
+    pub ttl: dur::Duration,
+    /// Most responses kept at once. The oldest go first.
+    pub max_stored: usize,
+}
+
+impl Default for Responses {
+    fn default() -> Self {
+        Self {
+            ttl: dur::Duration::from_secs(60 * 60),
+            max_stored: 1000,
+        }
+    }
+}
+
+/// How the Ollama-compatible endpoints are served.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Ollama {
+    /// Name of the client requests that send no key are served as, since
+    /// Ollama tools rarely send one. Unset, they're turned away like on
+    /// any other endpoint.
+    pub client: Option<String>,
+}
+
+/// How OpenAI-compatible chat completions reach Gemini. By default they're
+/// forwarded to Gemini's own OpenAI-compatible endpoint; translated, they go
+/// through native `generateContent` instead, which takes every Gemini
+/// feature but answers the same way.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct OpenAi {
+    /// Translate chat completions for every model.
+    pub native: bool,
+    /// Models to translate chat completions for, as glob patterns such as
+    /// `gemini-2.5-*`.
+    pub native_models: Vec<String>,
+}
+
+/// An OpenAI-compatible server, such as vLLM or llama.cpp, that generation
+/// requests for some models go to as chat completions instead of going to
+/// Gemini. Its answers are translated back, so clients can't tell.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Provider {
+    /// What logs call it.
+    pub name: String,
+    /// Base URL of its API, which serves `{url}/chat/completions`, e.g.
+    /// `http://localhost:8000/v1`.
+    pub url: String,
+    /// Sent as a bearer token, for servers that want one.
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Models routed to it, as glob patterns such as `llama-*`.
+    pub models: Vec<String>,
+}
+
+/// How model listings are served, and other names models go by.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Models {
+    /// How long a listing fetched from the upstream is served before it's
+    /// fetched again.
+    pub cache_ttl: dur::Duration,
+    /// Only list the models the calling client's policy allows.
+    pub filter_by_policy: bool,
+    /// Extra model names and the model each stands for, listed along the
+    /// upstream's and accepted anywhere a model is named.
+    pub aliases: HashMap<String, String>,
+}
+
+impl Default for Models {
+    fn default() -> Self {
+        Self {
+            cache_ttl: dur::Duration::from_secs(10 * 60),
+            filter_by_policy: false,
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+/// Requests that find every key ratelimited wait for one to come back,
+/// first come first served, instead of failing right away, as long as one is
+/// expected within `max_wait` and fewer than `max_depth` requests are already
+/// waiting for the same model. A `max_wait` of zero turns waiting off.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Queue {
+    pub max_wait: dur::Duration,
+    pub max_depth: usize,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self {
+            max_wait: dur::Duration::from_secs(0),
+            max_depth: 100,
+        }
+    }
+}
+
+/// What happens when an upstream stream fails after it started. Streams that
+/// fail before sending anything always move on to another key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Streaming {
+    /// Continue a stream that already sent part of its answer on another key,
+    /// asking it to pick up where the last one stopped.
+    pub resume: bool,
+    /// How many times a single stream moves on to another key.
+    pub max_retries: u32,
+}
+
+impl Default for Streaming {
+    fn default() -> Self {
+        Self {
+            resume: false,
+            max_retries: 2,
+        }
+    }
+}
+
+/// A caller of the proxy, identified by its own key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClientConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub secret: Secret,
+    /// Revoked clients are turned away as if their key was unknown.
+    #[serde(default)]
+    pub revoked: bool,
+    #[serde(default)]
+    pub limits: ClientLimits,
+    #[serde(default)]
+    pub policy: ClientPolicy,
+}
+
+/// What a client may ask for. Requests outside it are turned away before a
+/// key is spent on them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ClientPolicy {
+    /// Models the client may call, as glob patterns such as `gemini-*-flash*`.
+    /// Empty allows every model.
+    pub models: Vec<String>,
+    /// Most output tokens a request may ask for. Requests that don't say are
+    /// capped to it.
+    pub max_output_tokens: Option<u64>,
+    /// Whether requests may declare tools such as functions or code execution.
+    pub tools: bool,
+    /// Whether requests may ground answers with search or URL context.
+    pub grounding: bool,
+}
+
+impl Default for ClientPolicy {
+    fn default() -> Self {
+        Self {
+            models: Vec::new(),
+            max_output_tokens: None,
+            tools: true,
+            grounding: true,
+        }
+    }
+}
+
+/// Budgets a client can't go over, however many keys are left, checked
+/// before a key is spent on its request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ClientLimits {
+    /// Requests per minute.
+    pub rpm: Option<u64>,
+    /// Requests per day, counted until the daily quota reset.
+    pub rpd: Option<u64>,
+    /// Tokens per day, counted until the daily quota reset.
+    pub tpd: Option<u64>,
+    /// Requests in flight at once, streams included until they end.
+    pub max_concurrent: Option<u64>,
+}
+
+/// How a client key is stored in the config: as is, or hashed so the config
+/// never holds it in plain text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Secret {
+    Key(String),
+    /// Hex encoded SHA-256 of the key.
+    KeySha256(String),
+    /// Argon2 hash of the key, in PHC string format (`$argon2id$...`).
+    KeyArgon2(String),
+}
+
+/// A Gemini API key, either on its own or with a weight for the
+/// `weighted-random` strategy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum KeyEntry {
+    Plain(String),
+    Weighted { key: String, weight: u32 },
+}
+
+impl KeyEntry {
+    pub fn key(&self) -> &str {
+        match self {
+            KeyEntry::Plain(key) | KeyEntry::Weighted { key, .. } => key,
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        match self {
+            KeyEntry::Plain(_) => 1,
+            KeyEntry::Weighted { weight, .. } => *weight,
+        }
+    }
+}
+
+/// Known per-key quotas for a model. Keys that would go over one of them are
+/// skipped instead of finding out through a 429.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Limits {
+    /// Requests per minute.
+    #[serde(default, deserialize_with = "nonzero_limit")]
+    pub rpm: Option<u64>,
+    /// Tokens per minute.
+    #[serde(default, deserialize_with = "nonzero_limit")]
+    pub tpm: Option<u64>,
+    /// Requests per day, counted until the daily quota reset.
+    #[serde(default, deserialize_with = "nonzero_limit")]
+    pub rpd: Option<u64>,
+}
+
+/// Refuses a limit of `0`, which would take the key out of rotation for the
+/// model rather than limit it. Leaving a limit out is how to have none.
+fn nonzero_limit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    match Option::<u64>::deserialize(deserializer)? {
+        Some(0) => Err(de::Error::custom(
+            "limits must be at least 1, leave a limit out to have none",
+        )),
+        limit => Ok(limit),
+    }
+}
+
+/// How long a ratelimited key sits out when the upstream doesn't say,
+/// depending on which quota it ran out of. Daily quotas always come back at
+/// the next midnight in `daily_reset_timezone`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Cooldowns {
+    pub per_minute: dur::Duration,
+    pub unknown: dur::Duration,
+    pub daily_reset_timezone: chrono_tz::Tz,
+}
+
+impl Default for Cooldowns {
+    fn default() -> Self {
+        Self {
+            per_minute: dur::Duration::from_secs(60),
+            unknown: dur::Duration::from_secs(5 * 60),
+            daily_reset_timezone: chrono_tz::America::Los_Angeles,
+        }
+    }
+}
+
+/// Per-route replacements for `upstream_url`, for when only some endpoints
+/// should go somewhere else (e.g. a gateway that only speaks the OpenAI
+/// dialect).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct UpstreamOverrides {
+    /// Also used for the model's other native methods, such as
+    /// `countTokens` and `embedContent`, and the native model listing.
+    pub generate_content: Option<String>,
+    pub stream_generate_content: Option<String>,
+    pub openai: Option<String>,
+}
+
+fn default_upstream_url() -> String {
+    "https://generativelanguage.googleapis.com".to_string()
 }
 
 impl Default for ConfigInner {
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use super::config::ConfigInner;
+
+/// A Responses API answer, kept so later requests can continue from it.
+pub struct Conversation {
+    /// Name of the client it was made for, the only one that may use it.
+    pub client: String,
+    /// The `generateContent` contents that led to it, its answer included.
+    pub contents: Vec<Value>,
+    /// The response as the client got it.
+    pub response: Value,
+}
+
+/// Stored responses by id, kept until they're `ttl` old or until
+/// `max_stored` newer ones push them out.
+pub struct ConversationStore {
+    ttl: Duration,
+    max_stored: usize,
+    stored: Mutex<HashMap<String, (Instant, Arc<Conversation>)>>,
+}
+
+impl ConversationStore {
+    pub fn new(config: &ConfigInner) -> Self {
+        Self {
+            ttl: config.responses.ttl.to_std(),
+            max_stored: config.responses.max_stored,
+            stored: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The response stored as `id`, if `client` made it.
+    pub fn get(&self, id: &str, client: &str) -> Option<Arc<Conversation>> {
+        let stored = self.stored.lock().unwrap();
+        let (stored_at, conversation) = stored.get(id)?;
+        (stored_at.elapsed() < self.ttl && conversation.client == client)
+            .then(|| conversation.clone())
+    }
+
+    pub fn put(&self, id: &str, conversation: Conversation) {
+        if self.max_stored == 0 {
+            return;
+        }
+        let mut stored = self.stored.lock().unwrap();
+        stored.retain(|_, (stored_at, _)| stored_at.elapsed() < self.ttl);
+        while stored.len() >= self.max_stored {
+            let Some(oldest) = stored
+                .iter()
+                .min_by_key(|(_, (stored_at, _))| *stored_at)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            stored.remove(&oldest);
+        }
+        stored.insert(id.to_string(), (Instant::now(), Arc::new(conversation)));
+    }
+
+    /// Forgets the response stored as `id`, if `client` made it.
+    pub fn remove(&self, id: &str, client: &str) -> bool {
+        let mut stored = self.stored.lock().unwrap();
+        match stored.get(id) {
+            Some((_, conversation)) if conversation.client == client => {
+                stored.remove(id);
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use std::future::{Ready, ready};
+use std::sync::Arc;
 use std::time::Instant;
 
-use actix_web::Error;
 use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::{Error, HttpMessage};
 use colored::Colorize;
 use dur::Duration;
 use futures_util::future::LocalBoxFuture;
 use log::{error, info};
 
+use super::Client;
+
 pub struct HttpLogger;
 
 impl<S, B> Transform<S, ServiceRequest> for HttpLogger
@@ -57,19 +60,27 @@ where
         Box::pin(async move {
             let res = fut.await?;
             let status = res.status().as_u16();
+            // Set by the routes once they know who's calling.
+            let client = res
+                .request()
+                .extensions()
+                .get::<Arc<Client>>()
+                .map_or_else(|| "-".to_string(), |client| client.name.clone());
             let elapsed = start.elapsed();
             let duration = format!("{:.2}", Duration::from(elapsed));
             let status_colored = colorize_status(status);
             let path_display = truncate_path(&path, 50);
 
             let log_msg = format!(
-                " {} {} {:>8} {} {:<15} {} {:<7}  {:<50}",
+                " {} {} {:>8} {} {:<15} {} {:<12} {} {:<7}  {:<50}",
                 status_colored,
                 "|".white(),
                 duration.white(),
                 "|".white(),
                 peer_addr.white(),
                 "|".white(),
+                client.cyan(),
+                "|".white(),
                 method.cyan(),
                 path_display.white(),
             );
@@ -1,24 +1,151 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt::Display;
 use std::ops::Deref;
+use std::time::Duration;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use colored::Colorize;
-use log::{debug, info};
+use log::{debug, error, info};
 use rand::seq::SliceRandom;
 use serde::Serialize;
 
+use super::config::{ConfigInner, Cooldowns, KeyEntry, Limits};
+use super::state::{KeyState, ModelState, Snapshot, StateStore, fingerprint};
+use super::strategy::{Candidate, SelectionStrategy};
+use super::upstream_error::Quota;
+
+const MINUTE: chrono::Duration = chrono::Duration::minutes(1);
+
+/// How much the latest response time weighs in a key's latency average.
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// Requests and tokens a key spent on a model over the last minute.
+#[derive(Default)]
+struct Window {
+    requests: VecDeque<DateTime<Utc>>,
+    tokens: VecDeque<(DateTime<Utc>, u64)>,
+}
+
+impl Window {
+    fn prune(&mut self, now: DateTime<Utc>) {
+        while self.requests.front().is_some_and(|at| *at + MINUTE <= now) {
+            self.requests.pop_front();
+        }
+        while self
+            .tokens
+            .front()
+            .is_some_and(|(at, _)| *at + MINUTE <= now)
+        {
+            self.tokens.pop_front();
+        }
+    }
+
+    /// When enough requests leave the window to make room for another one.
+    fn requests_clear_at(&self, rpm: u64) -> Option<DateTime<Utc>> {
+        let rpm = rpm as usize;
+        (self.requests.len() >= rpm).then(|| self.requests[self.requests.len() - rpm] + MINUTE)
+    }
+
+    /// When enough tokens leave the window to get back under `tpm`.
+    fn tokens_clear_at(&self, tpm: u64) -> Option<DateTime<Utc>> {
+        let mut used: u64 = self.tokens.iter().map(|(_, tokens)| tokens).sum();
+        for (at, tokens) in &self.tokens {
+            if used < tpm {
+                return None;
+            }
+            used -= tokens;
+            if used < tpm {
+                return Some(*at + MINUTE);
+            }
+        }
+        None
+    }
+}
+
 pub struct Key {
     pub key: String,
-    pub ratelimited_at: Option<DateTime<Utc>>,
     pub num_requests: u64,
+    /// Quotas are tracked per model, a key out of quota for one model is
+    /// still good for the others.
+    pub models: HashMap<String, ModelState>,
+    windows: HashMap<String, Window>,
+    /// Index in the configured key list.
+    position: usize,
+    weight: u32,
+    /// Moving average of upstream response times per model.
+    latency: HashMap<String, Duration>,
 }
 
-impl From<String> for Key {
-    fn from(key: String) -> Self {
+impl Key {
+    fn new(position: usize, entry: &KeyEntry) -> Self {
         Self {
-            key,
-            ratelimited_at: None,
+            key: entry.key().to_string(),
             num_requests: 0,
+            models: HashMap::new(),
+            windows: HashMap::new(),
+            position,
+            weight: entry.weight(),
+            latency: HashMap::new(),
+        }
+    }
+
+    /// When the key can take another request for `model`, `None` meaning
+    /// right away. Accounts for upstream cooldowns as well as `limits`, and
+    /// forgets cooldowns and daily counts that have run out.
+    fn available_at(
+        &mut self,
+        model: &str,
+        limits: Option<&Limits>,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let mut blocked_until: Option<DateTime<Utc>> = None;
+
+        if let Some(state) = self.models.get_mut(model) {
+            match state.cooldown_until {
+                Some(until) if until > now => blocked_until = blocked_until.max(Some(until)),
+                Some(_) => state.cooldown_until = None,
+                None => {}
+            }
+
+            if state.today_resets_at.is_some_and(|at| at <= now) {
+                state.requests_today = 0;
+                state.today_resets_at = None;
+            }
+            if let Some(rpd) = limits.and_then(|limits| limits.rpd)
+                && state.requests_today >= rpd
+            {
+                blocked_until = blocked_until.max(state.today_resets_at);
+            }
+        }
+
+        if let Some(limits) = limits
+            && let Some(window) = self.windows.get_mut(model)
+        {
+            window.prune(now);
+            if let Some(rpm) = limits.rpm {
+                blocked_until = blocked_until.max(window.requests_clear_at(rpm));
+            }
+            if let Some(tpm) = limits.tpm {
+                blocked_until = blocked_until.max(window.tokens_clear_at(tpm));
+            }
+        }
+
+        blocked_until
+    }
+
+    fn model_requests(&self, model: &str) -> u64 {
+        self.models.get(model).map_or(0, |state| state.num_requests)
+    }
+
+    fn masked(&self) -> String {
+        let len = self.key.len();
+        match (
+            self.key.get(..6),
+            len.checked_sub(4).and_then(|i| self.key.get(i..)),
+        ) {
+            (Some(head), Some(tail)) if len > 10 => format!("{head}...{tail}"),
+            _ => "...".to_string(),
         }
     }
 }
@@ -42,102 +169,343 @@ pub struct KeyStatus {
     pub key_masked: String,
     pub num_requests: u64,
     pub is_ratelimited: bool,
-    pub seconds_remaining: Option<i64>,
+    /// Seconds until the key is usable again for each model it's ratelimited
+    /// or out of budget for.
+    pub ratelimited_models: BTreeMap<String, i64>,
+}
+
+#[derive(Serialize, Default)]
+pub struct ModelStatus {
+    pub active_keys: usize,
+    pub ratelimited_keys: usize,
+    /// Seconds until the first key becomes usable again, if none are now.
+    pub seconds_until_available: Option<i64>,
 }
 
 pub struct KeyJuggler {
     keys: Vec<Key>,
+    strategy: Box<dyn SelectionStrategy>,
+    cooldowns: Cooldowns,
+    limits: HashMap<String, Limits>,
+    removed: Vec<String>,
+    store: Option<StateStore>,
+    /// Snapshots taken so far, which orders their writes.
+    generation: u64,
+    dirty: bool,
 }
 
 impl KeyJuggler {
-    pub fn new(keys: Vec<String>) -> Self {
+    pub fn new(config: &ConfigInner) -> Self {
+        let keys = &config.keys;
         info!(
-            "initializing key juggler with {} {}",
+            "initializing key juggler with {} {} ({:?} strategy)",
             keys.len().to_string().cyan().bold(),
-            if keys.len() == 1 { "key" } else { "keys" }
+            if keys.len() == 1 { "key" } else { "keys" },
+            config.strategy
         );
-        let mut keys: Vec<Key> = keys.into_iter().map(Key::from).collect();
+        let mut keys: Vec<Key> = keys
+            .iter()
+            .enumerate()
+            .map(|(position, entry)| Key::new(position, entry))
+            .collect();
         keys.shuffle(&mut rand::rng());
-        Self { keys }
+        Self {
+            keys,
+            strategy: config.strategy.build(),
+            cooldowns: config.cooldowns.clone(),
+            limits: config.limits.clone(),
+            removed: Vec::new(),
+            store: None,
+            generation: 0,
+            dirty: false,
+        }
+    }
+
+    /// Restores the state saved by a previous run and keeps `store` up to date
+    /// from now on.
+    pub fn attach(&mut self, store: StateStore) -> eyre::Result<()> {
+        let snapshot = store.load()?;
+
+        self.keys.retain(|key| {
+            let removed = snapshot.removed.contains(&fingerprint(key));
+            if removed {
+                log::warn!(
+                    "key {} was removed from rotation in a previous run, skipping",
+                    fingerprint(key).cyan()
+                );
+            }
+            !removed
+        });
+
+        let mut restored = 0;
+        for key in self.keys.iter_mut() {
+            if let Some(state) = snapshot.keys.get(&fingerprint(key)) {
+                key.num_requests = state.num_requests;
+                key.models = state.models.clone();
+                restored += 1;
+            }
+        }
+
+        info!(
+            "restored state for {} {}",
+            restored.to_string().cyan().bold(),
+            if restored == 1 { "key" } else { "keys" }
+        );
+
+        self.removed = snapshot.removed;
+        self.store = Some(store);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            keys: self
+                .keys
+                .iter()
+                .map(|key| {
+                    (
+                        fingerprint(key),
+                        KeyState {
+                            num_requests: key.num_requests,
+                            models: key.models.clone(),
+                        },
+                    )
+                })
+                .collect(),
+            removed: self.removed.clone(),
+        }
+    }
+
+    /// Takes a snapshot of the state and writes it in the background, so
+    /// the file system is never waited on while the juggler is locked.
+    pub fn persist(&mut self) {
+        let Some((store, generation, snapshot)) = self.take_snapshot() else {
+            return;
+        };
+
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = store.save(generation, &snapshot) {
+                error!("failed to persist juggler state: {}", e);
+            }
+        });
+    }
+
+    /// Like [`KeyJuggler::persist`], waiting for the write to finish, for
+    /// when the server shuts down.
+    pub fn persist_now(&mut self) {
+        let Some((store, generation, snapshot)) = self.take_snapshot() else {
+            return;
+        };
+
+        if let Err(e) = store.save(generation, &snapshot) {
+            error!("failed to persist juggler state: {}", e);
+        }
+    }
+
+    fn take_snapshot(&mut self) -> Option<(StateStore, u64, Snapshot)> {
+        let store = self.store.clone()?;
+        self.generation += 1;
+        self.dirty = false;
+        Some((store, self.generation, self.snapshot()))
     }
 
-    pub fn select(&mut self) -> Option<&Key> {
-        let best_idx = self.find_best_key()?;
-        self.keys[best_idx].num_requests += 1;
+    /// Persists only if something changed since the last snapshot, meant to be
+    /// called periodically to catch request counter updates.
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.persist();
+        }
+    }
+
+    /// Picks a key that isn't ratelimited for `model` and isn't in `exclude`
+    /// according to the configured strategy and counts a request against it.
+    /// Returns an owned copy so callers can release the lock before talking
+    /// to the upstream.
+    pub fn select(&mut self, model: &str, exclude: &[String]) -> Option<String> {
+        let best_idx = self.find_best_key(model, exclude)?;
+        let now = Utc::now();
+        let daily_reset_timezone = self.cooldowns.daily_reset_timezone;
+        let key = &mut self.keys[best_idx];
+        key.num_requests += 1;
+        let state = key.models.entry(model.to_string()).or_default();
+        state.num_requests += 1;
+        state.requests_today += 1;
+        state
+            .today_resets_at
+            .get_or_insert_with(|| next_daily_reset(now, daily_reset_timezone));
+        key.windows
+            .entry(model.to_string())
+            .or_default()
+            .requests
+            .push_back(now);
+        self.dirty = true;
         debug!(
-            "selected key {} (index {}, {} total {})",
-            self.keys[best_idx].key.cyan(),
+            "selected key {} for {} (index {}, {} total {})",
+            key.key.cyan(),
+            model.cyan(),
             best_idx.to_string().cyan(),
-            self.keys[best_idx].num_requests.to_string().cyan(),
-            if self.keys[best_idx].num_requests == 1 {
+            key.num_requests.to_string().cyan(),
+            if key.num_requests == 1 {
                 "request"
             } else {
                 "requests"
             }
         );
-        Some(&self.keys[best_idx])
+        Some(key.key.clone())
     }
 
-    fn find_best_key(&mut self) -> Option<usize> {
+    fn find_best_key(&mut self, model: &str, exclude: &[String]) -> Option<usize> {
         let current_time = Utc::now();
+        let limits = self.limits.get(model);
 
-        let mut best_idx: Option<usize> = None;
-        let mut best_score: Option<i64> = None;
-
-        for (idx, key) in self.keys.iter_mut().enumerate() {
-            let is_expired = match key.ratelimited_at {
-                None => false,
-                Some(ratelimited_at) => {
-                    if (current_time - ratelimited_at) > chrono::Duration::days(1) {
-                        key.ratelimited_at = None;
-                        false
-                    } else {
-                        true
-                    }
+        let mut available: Vec<usize> = self
+            .keys
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, key)| !exclude.contains(&key.key))
+            .filter_map(|(idx, key)| {
+                key.available_at(model, limits, current_time)
+                    .is_none()
+                    .then_some(idx)
+            })
+            .collect();
+        if available.is_empty() {
+            return None;
+        }
+
+        // Keys are shuffled at startup, strategies see them in configuration
+        // order instead.
+        available.sort_by_key(|&idx| self.keys[idx].position);
+        let candidates: Vec<Candidate> = available
+            .iter()
+            .map(|&idx| {
+                let key = &self.keys[idx];
+                Candidate {
+                    position: key.position,
+                    requests: key.model_requests(model),
+                    weight: key.weight,
+                    latency: key.latency.get(model).copied(),
                 }
-            };
+            })
+            .collect();
+
+        let picked = self.strategy.pick(&candidates);
+        available.get(picked).copied()
+    }
 
-            let score: i64 = match is_expired {
-                true => i64::MAX / 2,
-                false => key.num_requests as i64,
-            };
+    /// When the first key that isn't `exclude`d becomes usable for `model`
+    /// again, if none are now.
+    pub fn next_available(&mut self, model: &str, exclude: &[String]) -> Option<DateTime<Utc>> {
+        let current_time = Utc::now();
+        let limits = self.limits.get(model);
+        let mut earliest: Option<DateTime<Utc>> = None;
 
-            match best_score {
-                None => {
-                    best_idx = Some(idx);
-                    best_score = Some(score);
-                }
-                Some(current_best) if score < current_best => {
-                    best_idx = Some(idx);
-                    best_score = Some(score);
-                }
-                _ => {}
-            }
+        for key in self
+            .keys
+            .iter_mut()
+            .filter(|key| !exclude.contains(&key.key))
+        {
+            let until = key.available_at(model, limits, current_time)?;
+            earliest = Some(earliest.map_or(until, |earliest| earliest.min(until)));
         }
 
-        best_idx
+        earliest
+    }
+
+    /// Counts the tokens a response used towards the key's `tpm` budget.
+    pub fn record_usage(&mut self, key: &str, model: &str, tokens: u64) {
+        let Some(key) = self.keys.iter_mut().find(|k| k.key == key) else {
+            return;
+        };
+
+        debug!(
+            "key {} used {} tokens on {}",
+            key.key.cyan(),
+            tokens.to_string().cyan(),
+            model.cyan()
+        );
+        key.windows
+            .entry(model.to_string())
+            .or_default()
+            .tokens
+            .push_back((Utc::now(), tokens));
+    }
+
+    /// Folds how long the upstream took to answer into the key's latency
+    /// average for `model`.
+    pub fn record_latency(&mut self, key: &str, model: &str, elapsed: Duration) {
+        let Some(key) = self.keys.iter_mut().find(|k| k.key == key) else {
+            return;
+        };
+
+        key.latency
+            .entry(model.to_string())
+            .and_modify(|average| {
+                *average =
+                    average.mul_f64(1.0 - LATENCY_SMOOTHING) + elapsed.mul_f64(LATENCY_SMOOTHING)
+            })
+            .or_insert(elapsed);
+    }
+
+    fn cooldown_until(
+        &self,
+        quota: Quota,
+        retry_after: Option<std::time::Duration>,
+        now: DateTime<Utc>,
+    ) -> DateTime<Utc> {
+        let after = |duration: std::time::Duration| {
+            chrono::Duration::from_std(duration)
+                .ok()
+                .and_then(|duration| now.checked_add_signed(duration))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        };
+
+        match (quota, retry_after) {
+            // The retry delay sent along a daily quota violation only covers
+            // the current minute window, the quota itself won't be back until
+            // it resets.
+            (Quota::PerDay, _) => next_daily_reset(now, self.cooldowns.daily_reset_timezone),
+            (_, Some(retry_after)) => after(retry_after),
+            (Quota::PerMinute, None) => after(self.cooldowns.per_minute.to_std()),
+            (Quota::Unknown, None) => after(self.cooldowns.unknown.to_std()),
+        }
     }
 
-    pub fn ratelimit(&mut self, key: &str) -> Option<&Key> {
+    pub fn ratelimit(
+        &mut self,
+        key: &str,
+        model: &str,
+        quota: Quota,
+        retry_after: Option<std::time::Duration>,
+    ) {
         if let Some(idx) = self.keys.iter().position(|k| k.key == key) {
-            let request_count = self.keys[idx].num_requests;
+            let request_count = self.keys[idx].model_requests(model);
+            let now = Utc::now();
+            let until = self.cooldown_until(quota, retry_after, now);
+            let cooldown = (until - now).to_std().unwrap_or_default();
             log::warn!(
-                "ratelimited key {} at index {} (handled {} {})",
+                "ratelimited key {} at index {} on {} for {} (handled {} {}, {:?} quota)",
                 self.keys[idx].key.cyan(),
                 idx.to_string().cyan(),
+                model.cyan(),
+                dur::Duration::from_std(cooldown).to_string().cyan(),
                 request_count.to_string().cyan(),
                 if request_count == 1 {
                     "request"
                 } else {
                     "requests"
-                }
+                },
+                quota
             );
-            self.keys[idx].ratelimited_at = Some(Utc::now());
-            self.keys[idx].num_requests = 0;
-            self.select()
+            let state = self.keys[idx].models.entry(model.to_string()).or_default();
+            state.cooldown_until = Some(until);
+            state.num_requests = 0;
+            self.persist();
         } else {
-            log::warn!("key not found for ratelimit");
-            None
+            log::debug!(
+                "key {} already left rotation, ignoring ratelimit",
+                key.cyan()
+            );
         }
     }
 
@@ -149,50 +517,100 @@ impl KeyJuggler {
                 idx.to_string().cyan()
             );
             self.keys.remove(idx);
+            self.removed.push(fingerprint(key));
+            self.persist();
         }
     }
 
-    pub fn current(&mut self) -> &Key {
-        let idx = self.find_best_key().unwrap_or(0);
-        self.keys[idx].num_requests += 1;
-        &self.keys[idx]
-    }
-
     pub fn get_status(&mut self) -> Vec<KeyStatus> {
         let current_time = Utc::now();
+        let limits = &self.limits;
 
         self.keys
             .iter_mut()
             .enumerate()
             .map(|(idx, key)| {
-                let is_expired = match key.ratelimited_at {
-                    None => false,
-                    Some(ratelimited_at) => {
-                        if (current_time - ratelimited_at) > chrono::Duration::days(1) {
-                            key.ratelimited_at = None;
-                            false
-                        } else {
-                            true
-                        }
-                    }
-                };
-
-                let seconds_remaining = if is_expired {
-                    let remaining =
-                        chrono::Duration::days(1) - (current_time - key.ratelimited_at.unwrap());
-                    Some(remaining.num_seconds())
-                } else {
-                    None
-                };
+                let models: Vec<String> = key.models.keys().cloned().collect();
+                let ratelimited_models: BTreeMap<String, i64> = models
+                    .into_iter()
+                    .filter_map(|model| {
+                        let until = key.available_at(&model, limits.get(&model), current_time)?;
+                        Some((model, (until - current_time).num_seconds()))
+                    })
+                    .collect();
 
                 KeyStatus {
                     index: idx,
-                    key_masked: format!("{}...{}", &key.key[..6], &key.key[key.key.len() - 4..]),
+                    key_masked: key.masked(),
                     num_requests: key.num_requests,
-                    is_ratelimited: is_expired,
-                    seconds_remaining,
+                    is_ratelimited: !ratelimited_models.is_empty(),
+                    ratelimited_models,
+                }
+            })
+            .collect()
+    }
+
+    /// Availability of every model that has been requested so far.
+    pub fn get_model_status(&mut self) -> BTreeMap<String, ModelStatus> {
+        let current_time = Utc::now();
+        let models: BTreeSet<String> = self
+            .keys
+            .iter()
+            .flat_map(|key| key.models.keys().cloned())
+            .collect();
+
+        models
+            .into_iter()
+            .map(|model| {
+                let limits = self.limits.get(&model);
+                let mut status = ModelStatus::default();
+                let mut earliest: Option<DateTime<Utc>> = None;
+
+                for key in self.keys.iter_mut() {
+                    match key.available_at(&model, limits, current_time) {
+                        Some(until) => {
+                            status.ratelimited_keys += 1;
+                            earliest = Some(earliest.map_or(until, |e| e.min(until)));
+                        }
+                        None => status.active_keys += 1,
+                    }
                 }
+
+                if status.active_keys == 0 {
+                    status.seconds_until_available =
+                        earliest.map(|until| (until - current_time).num_seconds());
+                }
+
+                (model, status)
             })
             .collect()
     }
 }
+
+/// Normalizes a model name as given by clients, which may or may not carry
+/// the `models/` resource prefix.
+pub fn model_name(model: &str) -> &str {
+    model.strip_prefix("models/").unwrap_or(model)
+}
+
+/// Next midnight in `timezone`, when Gemini's daily quotas reset (midnight
+/// Pacific time for the public API).
+pub fn next_daily_reset(now: DateTime<Utc>, timezone: Tz) -> DateTime<Utc> {
+    let tomorrow = now
+        .with_timezone(&timezone)
+        .date_naive()
+        .succ_opt()
+        .unwrap_or(NaiveDate::MAX)
+        .and_time(NaiveTime::MIN);
+
+    // Midnight may be skipped by a DST change in some zones, in which case
+    // the day starts at the first instant that does exist.
+    (0..24)
+        .find_map(|hour| {
+            timezone
+                .from_local_datetime(&(tomorrow + chrono::Duration::hours(hour)))
+                .earliest()
+        })
+        .map(|reset| reset.with_timezone(&Utc))
+        .unwrap_or(now + chrono::Duration::days(1))
+}
@@ -1,12 +1,31 @@
 pub mod cli;
+mod clients;
 pub mod config;
+mod conversations;
 mod http_logger;
 mod juggler;
 mod log;
+mod models;
+mod policy;
+mod queue;
 mod requester;
+mod sse;
+mod state;
+mod strategy;
+mod upstream_error;
+mod usage;
 
+pub use clients::{Admission, Client, Clients, OverLimit};
 pub use config::Config;
+pub use conversations::{Conversation, ConversationStore};
 pub use http_logger::HttpLogger;
-pub use juggler::KeyJuggler;
+pub use juggler::{KeyJuggler, model_name};
 pub use log::Logger;
-pub use requester::{Event, Requester};
+pub use models::ModelCache;
+pub use policy::Requested;
+pub use queue::{Turn, WaitQueue};
+pub use requester::{Event, Requester, Response};
+pub use sse::{JsonArray, SseDecoder};
+pub use state::StateStore;
+pub use upstream_error::{UpstreamError, classify, in_band_error};
+pub use usage::{UsageTap, total_tokens};
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use super::config::ConfigInner;
+
+/// Model listings fetched from the upstream, by the path they were fetched
+/// from, served until they're `ttl` old.
+pub struct ModelCache {
+    ttl: Duration,
+    listings: Mutex<HashMap<String, (Instant, Value)>>,
+}
+
+impl ModelCache {
+    pub fn new(config: &ConfigInner) -> Self {
+        Self {
+            ttl: config.models.cache_ttl.to_std(),
+            listings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<Value> {
+        let listings = self.listings.lock().unwrap();
+        let (fetched_at, listing) = listings.get(path)?;
+        (fetched_at.elapsed() < self.ttl).then(|| listing.clone())
+    }
+
+    pub fn put(&self, path: &str, listing: Value) {
+        self.listings
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), (Instant::now(), listing));
+    }
+}
@@ -0,0 +1,81 @@
+use super::config::ClientPolicy;
+
+/// What a request asks for, as far as client policies go.
+pub struct Requested<'a> {
+    pub model: &'a str,
+    pub max_output_tokens: Option<u64>,
+    pub tools: bool,
+    pub grounding: bool,
+}
+
+impl<'a> Requested<'a> {
+    /// A request for `model` that asks for nothing else.
+    pub fn model(model: &'a str) -> Self {
+        Self {
+            model,
+            max_output_tokens: None,
+            tools: false,
+            grounding: false,
+        }
+    }
+}
+
+impl ClientPolicy {
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.models.is_empty() || self.models.iter().any(|pattern| glob_match(pattern, model))
+    }
+
+    /// Why the policy doesn't allow `requested`, if it doesn't.
+    pub fn check(&self, requested: &Requested) -> Result<(), String> {
+        if !self.allows_model(requested.model) {
+            return Err(format!("Model {} is not allowed", requested.model));
+        }
+        if let (Some(max), Some(asked)) = (self.max_output_tokens, requested.max_output_tokens)
+            && asked > max
+        {
+            return Err(format!(
+                "{asked} output tokens were requested, but at most {max} are allowed"
+            ));
+        }
+        if requested.tools && !self.tools {
+            return Err("Tools are not allowed".to_string());
+        }
+        if requested.grounding && !self.grounding {
+            return Err("Grounding is not allowed".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of
+/// characters and `?` for any single one.
+pub(super) fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and where in `name` it started matching, to
+    // backtrack to when the rest of the pattern doesn't match.
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
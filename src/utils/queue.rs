@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::OwnedMutexGuard;
+
+use super::config::ConfigInner;
+
+/// Requests waiting for a key to come back for one model. The gate is a fair
+/// mutex, so they get to pick a key in the order they arrived.
+#[derive(Default)]
+struct Line {
+    gate: Arc<tokio::sync::Mutex<()>>,
+    depth: AtomicUsize,
+}
+
+/// Lines of requests waiting for a key, one per model.
+pub struct WaitQueue {
+    pub max_wait: Duration,
+    max_depth: usize,
+    lines: Mutex<HashMap<String, Arc<Line>>>,
+}
+
+impl WaitQueue {
+    pub fn new(config: &ConfigInner) -> Self {
+        Self {
+            max_wait: config.queue.max_wait.to_std(),
+            max_depth: config.queue.max_depth,
+            lines: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.max_wait.is_zero() && self.max_depth > 0
+    }
+
+    fn line(&self, model: &str) -> Arc<Line> {
+        self.lines
+            .lock()
+            .unwrap()
+            .entry(model.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Whether requests are already waiting for `model`, in which case new
+    /// ones have to get in line behind them.
+    pub fn is_waiting(&self, model: &str) -> bool {
+        self.lines
+            .lock()
+            .unwrap()
+            .get(model)
+            .is_some_and(|line| line.depth.load(Ordering::SeqCst) > 0)
+    }
+
+    /// Gets in line for `model`, unless the line is full.
+    pub fn join(&self, model: &str) -> Option<Place> {
+        let line = self.line(model);
+        line.depth
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |depth| {
+                (depth < self.max_depth).then_some(depth + 1)
+            })
+            .ok()?;
+        Some(Place { line })
+    }
+}
+
+/// A request's place in line, given up when dropped.
+pub struct Place {
+    line: Arc<Line>,
+}
+
+impl Place {
+    /// Waits until every request ahead is done waiting.
+    pub async fn front(self) -> Turn {
+        let guard = self.line.gate.clone().lock_owned().await;
+        Turn {
+            _guard: guard,
+            _place: self,
+        }
+    }
+}
+
+impl Drop for Place {
+    fn drop(&mut self) {
+        self.line.depth.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The front of the line: the next key that comes back goes to this request.
+pub struct Turn {
+    _guard: OwnedMutexGuard<()>,
+    _place: Place,
+}
@@ -1,37 +1,56 @@
 use std::pin::Pin;
+use std::time::Duration;
 
-use actix_web::{Error, HttpResponse, dev::Decompress, error::ErrorBadGateway};
-use awc::{Client, ClientResponse, error::PayloadError, http::StatusCode};
+use actix_web::{Error, HttpResponse, dev::Decompress, error::ErrorBadGateway, web::Bytes};
+use awc::{Client, ClientResponse, error::PayloadError};
 use colored::Colorize;
 use log::error;
 use serde_json::Value;
 
-type Response = ClientResponse<
+use super::config::{ConfigInner, Provider, UpstreamOverrides};
+use super::upstream_error::{Quota, UpstreamError, classify};
+
+pub type Response = ClientResponse<
     Decompress<
         actix_web::dev::Payload<
             Pin<Box<dyn futures_util::Stream<Item = Result<actix_web::web::Bytes, PayloadError>>>>,
         >,
     >,
 >;
+
 pub enum Event {
     Ok(HttpResponse),
     Forward(Response),
-    Retry,
+    Retry {
+        quota: Quota,
+        retry_after: Option<Duration>,
+    },
     BadKey, // maybe rename this
     Fail(Error),
 }
 
 pub struct Requester {
     client: Client,
+    upstream_url: String,
+    overrides: UpstreamOverrides,
 }
 
 impl Requester {
-    pub fn new() -> Self {
+    pub fn new(config: &ConfigInner) -> Self {
         Self {
             client: Client::builder().disable_timeout().finish(),
+            upstream_url: config.upstream_url.clone(),
+            overrides: config.upstream_overrides.clone(),
         }
     }
 
+    fn base_url<'a>(&'a self, route: &'a Option<String>) -> &'a str {
+        route
+            .as_deref()
+            .unwrap_or(&self.upstream_url)
+            .trim_end_matches('/')
+    }
+
     pub async fn forward_gemini(
         &self,
         key: &str,
@@ -41,11 +60,13 @@ impl Requester {
     ) -> Result<Event, Error> {
         let url = match stream {
             true => format!(
-                "https://generativelanguage.googleapis.com/v1beta/models/{model}:streamGenerateContent?key={}&alt=sse",
+                "{}/v1beta/models/{model}:streamGenerateContent?key={}&alt=sse",
+                self.base_url(&self.overrides.stream_generate_content),
                 key
             ),
             false => format!(
-                "https://generativelanguage.googleapis.com/v1beta/models/{model}:generateContent?key={}",
+                "{}/v1beta/models/{model}:generateContent?key={}",
+                self.base_url(&self.overrides.generate_content),
                 key
             ),
         };
@@ -56,15 +77,63 @@ impl Requester {
             key.cyan()
         );
 
-        let resp = self
-            .client
-            .post(&url)
-            .no_decompress()
-            .send_json(body)
-            .await
-            .map_err(|e| {
-                actix_web::error::ErrorBadGateway(format!("Error forwarding request: {}", e))
-            })?;
+        let resp = self.client.post(&url).send_json(body).await.map_err(|e| {
+            actix_web::error::ErrorBadGateway(format!("Error forwarding request: {}", e))
+        })?;
+
+        Ok(Self::handle_status(resp).await)
+    }
+
+    /// Forwards a call to one of `model`'s other methods, such as
+    /// `countTokens` or `embedContent`, which all answer in one piece.
+    pub async fn forward_method(
+        &self,
+        key: &str,
+        model: &str,
+        method: &str,
+        body: &Value,
+    ) -> Result<Event, Error> {
+        let url = format!(
+            "{}/v1beta/models/{model}:{method}?key={}",
+            self.base_url(&self.overrides.generate_content),
+            key
+        );
+
+        log::debug!(
+            "forwarding {} request to {}, using key {}",
+            method.cyan(),
+            "gemini".cyan(),
+            key.cyan()
+        );
+
+        let resp = self.client.post(&url).send_json(body).await.map_err(|e| {
+            actix_web::error::ErrorBadGateway(format!("Error forwarding request: {}", e))
+        })?;
+
+        Ok(Self::handle_status(resp).await)
+    }
+
+    /// Fetches the upstream's model listing, natively or in the OpenAI
+    /// dialect.
+    pub async fn list_models(&self, key: &str, openai: bool) -> Result<Event, Error> {
+        let request = match openai {
+            true => self
+                .client
+                .get(format!(
+                    "{}/v1beta/openai/models",
+                    self.base_url(&self.overrides.openai)
+                ))
+                .insert_header(("Authorization", format!("Bearer {}", key))),
+            false => self.client.get(format!(
+                "{}/v1beta/models?key={}&pageSize=1000",
+                self.base_url(&self.overrides.generate_content),
+                key
+            )),
+        };
+
+        let resp = request.send().await.map_err(|e| {
+            actix_web::error::ErrorBadGateway(format!("Error forwarding request: {}", e))
+        })?;
 
         Ok(Self::handle_status(resp).await)
     }
@@ -72,10 +141,12 @@ impl Requester {
     pub async fn forward_openai(&self, key: &str, body: &Value) -> Result<Event, Error> {
         let resp = self
             .client
-            .post("https://generativelanguage.googleapis.com/v1beta/openai/chat/completions")
+            .post(format!(
+                "{}/v1beta/openai/chat/completions",
+                self.base_url(&self.overrides.openai)
+            ))
             .insert_header(("Authorization", format!("Bearer {}", key)))
             .insert_header(("Content-Type", "application/json"))
-            .no_decompress()
             .send_json(body)
             .await
             .map_err(|e| {
@@ -85,49 +156,98 @@ impl Requester {
         Ok(Self::handle_status(resp).await)
     }
 
+    /// Sends a chat completion to one of the OpenAI-compatible providers
+    /// configured next to Gemini.
+    pub async fn forward_chat(&self, provider: &Provider, body: &Value) -> Result<Event, Error> {
+        log::debug!("forwarding request to {}", provider.name.cyan());
+
+        let mut request = self.client.post(format!(
+            "{}/chat/completions",
+            provider.url.trim_end_matches('/')
+        ));
+        if let Some(key) = &provider.key {
+            request = request.insert_header(("Authorization", format!("Bearer {}", key)));
+        }
+
+        let resp = request.send_json(body).await.map_err(|e| {
+            actix_web::error::ErrorBadGateway(format!("Error forwarding request: {}", e))
+        })?;
+
+        Ok(Self::handle_status(resp).await)
+    }
+
+    /// Forwards a request to another OpenAI-compatible endpoint as is, body
+    /// and content type included, so uploads such as audio files go through
+    /// untouched.
+    pub async fn forward_openai_path(
+        &self,
+        key: &str,
+        path: &str,
+        content_type: Option<&str>,
+        body: Bytes,
+    ) -> Result<Event, Error> {
+        let mut request = self
+            .client
+            .post(format!(
+                "{}/v1beta/openai/{path}",
+                self.base_url(&self.overrides.openai)
+            ))
+            .insert_header(("Authorization", format!("Bearer {}", key)));
+        if let Some(content_type) = content_type {
+            request = request.insert_header(("Content-Type", content_type));
+        }
+
+        let resp = request.send_body(body).await.map_err(|e| {
+            actix_web::error::ErrorBadGateway(format!("Error forwarding request: {}", e))
+        })?;
+
+        Ok(Self::handle_status(resp).await)
+    }
+
     async fn handle_status(mut resp: Response) -> Event {
-        match resp.status() {
-            StatusCode::TOO_MANY_REQUESTS => {
-                let body_bytes = match resp.body().await {
-                    Ok(bytes) => bytes,
-                    Err(e) => {
-                        return Event::Fail(ErrorBadGateway(format!(
-                            "Error reading response: {}",
-                            e
-                        )));
-                    }
-                };
-
-                if String::from_utf8_lossy(&body_bytes)
-                    .to_lowercase()
-                    .contains("day")
-                {
-                    return Event::Retry; // ratelimited
-                }
-
-                if body_bytes.len() == 344 {
-                    error!(
-                        "received 344 byte body, indicating a broken key, removing from rotation and retrying..."
-                    );
-
-                    return Event::BadKey;
-                }
-
-                Event::Ok(HttpResponse::build(StatusCode::TOO_MANY_REQUESTS).body(body_bytes))
+        let status = resp.status();
+        if status.is_success() {
+            return Event::Forward(resp);
+        }
+
+        let retry_after_header = resp
+            .headers()
+            .get("Retry-After")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+
+        let body_bytes = match resp.body().await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return Event::Fail(ErrorBadGateway(format!("Error reading response: {}", e)));
             }
-            status if status.is_success() => Event::Forward(resp),
-            status => {
-                let body_bytes = match resp.body().await {
-                    Ok(bytes) => bytes,
-                    Err(e) => {
-                        return Event::Fail(ErrorBadGateway(format!(
-                            "Error reading response: {}",
-                            e
-                        )));
-                    }
-                };
-                Event::Ok(HttpResponse::build(status).body(body_bytes))
+        };
+
+        match classify(status.as_u16(), &body_bytes) {
+            UpstreamError::Ratelimited { quota, retry_after } => Event::Retry {
+                quota,
+                retry_after: retry_after.or(retry_after_header),
+            },
+            UpstreamError::InvalidKey { reason } => {
+                error!(
+                    "upstream rejected key ({}), removing from rotation and retrying...",
+                    reason.cyan()
+                );
+                Event::BadKey
             }
+            _ => Event::Ok(HttpResponse::build(status).body(body_bytes)),
         }
     }
 }
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let at = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
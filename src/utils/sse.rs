@@ -0,0 +1,107 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::web::Bytes;
+use futures_util::{Stream, StreamExt};
+
+/// Splits a `text/event-stream` body into the `data` of each event, however
+/// the body happens to be chunked.
+#[derive(Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    /// Returns the data of every event completed by `chunk`.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer
+            .extend(chunk.iter().copied().filter(|byte| *byte != b'\r'));
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            events.extend(event_data(&event));
+        }
+        events
+    }
+
+    /// Returns the data of an event left unterminated at the end of the body.
+    pub fn finish(&mut self) -> Option<String> {
+        let event = std::mem::take(&mut self.buffer);
+        event_data(&event)
+    }
+}
+
+fn event_data(event: &[u8]) -> Option<String> {
+    let lines: Vec<&str> = std::str::from_utf8(event)
+        .ok()?
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Turns an upstream `alt=sse` stream into the JSON array streaming format
+/// `streamGenerateContent` uses when no `alt` is given, one element per
+/// event, as events arrive.
+pub struct JsonArray<S> {
+    inner: S,
+    decoder: SseDecoder,
+    started: bool,
+    done: bool,
+}
+
+impl<S> JsonArray<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            decoder: SseDecoder::default(),
+            started: false,
+            done: false,
+        }
+    }
+
+    fn elements(&mut self, events: Vec<String>) -> String {
+        let mut out = String::new();
+        for event in events {
+            out.push_str(if self.started { ",\r\n" } else { "[" });
+            out.push_str(&event);
+            self.started = true;
+        }
+        out
+    }
+}
+
+impl<S, E> Stream for JsonArray<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            match self.inner.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let events = self.decoder.feed(&chunk);
+                    if !events.is_empty() {
+                        let out = self.elements(events);
+                        return Poll::Ready(Some(Ok(Bytes::from(out))));
+                    }
+                }
+                Poll::Ready(None) => {
+                    self.done = true;
+                    let rest = self.decoder.finish().into_iter().collect();
+                    let mut out = self.elements(rest);
+                    out.push_str(if self.started { "]" } else { "[]" });
+                    return Poll::Ready(Some(Ok(Bytes::from(out))));
+                }
+                polled => return polled,
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Stable, non-reversible identifier for an API key, safe to write to disk.
+pub fn fingerprint(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().take(8).fold(
+        String::with_capacity(16),
+        |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        },
+    )
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct ModelState {
+    #[serde(default)]
+    pub cooldown_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub num_requests: u64,
+    #[serde(default)]
+    pub requests_today: u64,
+    #[serde(default)]
+    pub today_resets_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct KeyState {
+    #[serde(default)]
+    pub num_requests: u64,
+    #[serde(default)]
+    pub models: HashMap<String, ModelState>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Snapshot {
+    #[serde(default)]
+    pub keys: HashMap<String, KeyState>,
+    #[serde(default)]
+    pub removed: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct StateStore {
+    path: PathBuf,
+    /// Generation of the last snapshot written. Writes happen one at a time
+    /// and skip snapshots older than it, so a write that finishes late never
+    /// replaces a newer state.
+    written: Arc<Mutex<u64>>,
+}
+
+impl StateStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            written: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    pub fn load(&self) -> Result<Snapshot> {
+        if !self.path.exists() {
+            return Ok(Snapshot::default());
+        }
+
+        let contents = std::fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Writes `snapshot`, the `generation`th taken, unless a newer one was
+    /// written already. Writes to a sibling temporary file first so a crash
+    /// mid-write never leaves a truncated state file behind.
+    pub fn save(&self, generation: u64, snapshot: &Snapshot) -> Result<()> {
+        let mut written = self.written.lock().unwrap_or_else(|e| e.into_inner());
+        if generation <= *written {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(snapshot)?)?;
+        std::fs::rename(tmp, &self.path)?;
+        *written = generation;
+        Ok(())
+    }
+}
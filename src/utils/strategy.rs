@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// What a strategy knows about a key that's available for the requested
+/// model.
+pub struct Candidate {
+    /// Position of the key in the configuration, stable across restarts.
+    pub position: usize,
+    /// Requests the key has handled for the model.
+    pub requests: u64,
+    pub weight: u32,
+    /// Moving average of upstream response times, if the key was used yet.
+    pub latency: Option<Duration>,
+}
+
+/// Decides which of the available keys serves the next request.
+pub trait SelectionStrategy: Send + Sync {
+    /// Returns the index into `candidates` of the chosen key. `candidates` is
+    /// never empty and is sorted by `position`.
+    fn pick(&mut self, candidates: &[Candidate]) -> usize;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// The key with the fewest requests for the model.
+    #[default]
+    LeastUsed,
+    /// Every key in turn.
+    RoundRobin,
+    /// A random key, proportionally to its configured weight.
+    WeightedRandom,
+    /// The key with the fastest recent responses, trying unused keys first.
+    LeastLatency,
+    /// The first key in configuration order until it runs out, then the next.
+    FillFirst,
+}
+
+impl Strategy {
+    pub fn build(self) -> Box<dyn SelectionStrategy> {
+        match self {
+            Strategy::LeastUsed => Box::new(LeastUsed),
+            Strategy::RoundRobin => Box::new(RoundRobin::default()),
+            Strategy::WeightedRandom => Box::new(WeightedRandom),
+            Strategy::LeastLatency => Box::new(LeastLatency),
+            Strategy::FillFirst => Box::new(FillFirst),
+        }
+    }
+}
+
+pub struct LeastUsed;
+
+impl SelectionStrategy for LeastUsed {
+    fn pick(&mut self, candidates: &[Candidate]) -> usize {
+        min_index_by_key(candidates, |c| c.requests)
+    }
+}
+
+#[derive(Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl SelectionStrategy for RoundRobin {
+    fn pick(&mut self, candidates: &[Candidate]) -> usize {
+        let idx = candidates
+            .iter()
+            .position(|c| c.position >= self.next)
+            .unwrap_or(0);
+        self.next = candidates[idx].position + 1;
+        idx
+    }
+}
+
+pub struct WeightedRandom;
+
+impl SelectionStrategy for WeightedRandom {
+    fn pick(&mut self, candidates: &[Candidate]) -> usize {
+        let total: u64 = candidates.iter().map(|c| c.weight as u64).sum();
+        if total == 0 {
+            return rand::rng().random_range(0..candidates.len());
+        }
+
+        let mut roll = rand::rng().random_range(0..total);
+        for (idx, candidate) in candidates.iter().enumerate() {
+            match roll.checked_sub(candidate.weight as u64) {
+                Some(rest) => roll = rest,
+                None => return idx,
+            }
+        }
+        candidates.len() - 1
+    }
+}
+
+pub struct LeastLatency;
+
+impl SelectionStrategy for LeastLatency {
+    fn pick(&mut self, candidates: &[Candidate]) -> usize {
+        min_index_by_key(candidates, |c| c.latency.unwrap_or(Duration::ZERO))
+    }
+}
+
+pub struct FillFirst;
+
+impl SelectionStrategy for FillFirst {
+    fn pick(&mut self, _candidates: &[Candidate]) -> usize {
+        0
+    }
+}
+
+fn min_index_by_key<K: Ord>(candidates: &[Candidate], key: impl Fn(&Candidate) -> K) -> usize {
+    candidates
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, c)| key(c))
+        .map_or(0, |(idx, _)| idx)
+}
@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+/// `google.rpc.Status`, as found under the `error` key of Gemini error bodies.
+#[derive(Deserialize, Debug, Default)]
+pub struct Status {
+    #[serde(default)]
+    pub code: u16,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub details: Vec<Detail>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "@type")]
+pub enum Detail {
+    #[serde(rename = "type.googleapis.com/google.rpc.QuotaFailure")]
+    QuotaFailure {
+        #[serde(default)]
+        violations: Vec<QuotaViolation>,
+    },
+    #[serde(rename = "type.googleapis.com/google.rpc.RetryInfo")]
+    RetryInfo {
+        #[serde(rename = "retryDelay")]
+        retry_delay: Option<String>,
+    },
+    #[serde(rename = "type.googleapis.com/google.rpc.ErrorInfo")]
+    ErrorInfo {
+        #[serde(default)]
+        reason: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaViolation {
+    #[serde(default)]
+    pub quota_metric: String,
+    #[serde(default)]
+    pub quota_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quota {
+    PerMinute,
+    PerDay,
+    Unknown,
+}
+
+/// What an upstream error means for the key that received it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpstreamError {
+    /// The key ran out of quota and should sit out for a while.
+    Ratelimited {
+        quota: Quota,
+        retry_after: Option<Duration>,
+    },
+    /// The key itself is unusable and should leave rotation.
+    InvalidKey { reason: String },
+    /// Anything that isn't the key's fault, passed on to the client as is.
+    Other,
+}
+
+/// `ErrorInfo` reasons that mean the key will never work again.
+const INVALID_KEY_REASONS: &[&str] = &[
+    "API_KEY_INVALID",
+    "API_KEY_EXPIRED",
+    "API_KEY_SERVICE_BLOCKED",
+    "CONSUMER_SUSPENDED",
+    "SERVICE_DISABLED",
+];
+
+impl Status {
+    /// Parses the error body of either dialect: native routes return
+    /// `{"error": {...}}`, the OpenAI compatible layer wraps it in a list.
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        let value: Value = serde_json::from_slice(body).ok()?;
+        let error = match &value {
+            Value::Array(items) => items.first()?.get("error")?,
+            other => other.get("error")?,
+        };
+        Status::deserialize(error).ok()
+    }
+
+    pub fn quota(&self) -> Option<Quota> {
+        let violations = self.details.iter().flat_map(|detail| match detail {
+            Detail::QuotaFailure { violations } => violations.as_slice(),
+            _ => &[],
+        });
+
+        let mut quota = None;
+        for violation in violations {
+            let id = format!("{} {}", violation.quota_id, violation.quota_metric).to_lowercase();
+            let this = if id.contains("perday") || id.contains("per_day") {
+                Quota::PerDay
+            } else if id.contains("perminute") || id.contains("per_minute") {
+                Quota::PerMinute
+            } else {
+                Quota::Unknown
+            };
+
+            // The longest quota wins, a key out of its daily quota won't be
+            // saved by its minute window clearing.
+            quota = Some(match (quota, this) {
+                (Some(Quota::PerDay), _) | (_, Quota::PerDay) => Quota::PerDay,
+                (Some(Quota::PerMinute), _) | (_, Quota::PerMinute) => Quota::PerMinute,
+                _ => Quota::Unknown,
+            });
+        }
+
+        quota.or((self.status == "RESOURCE_EXHAUSTED").then_some(Quota::Unknown))
+    }
+
+    pub fn retry_delay(&self) -> Option<Duration> {
+        self.details.iter().find_map(|detail| match detail {
+            Detail::RetryInfo {
+                retry_delay: Some(delay),
+            } => parse_proto_duration(delay),
+            _ => None,
+        })
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.details.iter().find_map(|detail| match detail {
+            Detail::ErrorInfo { reason } => Some(reason.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn classify(&self) -> UpstreamError {
+        if let Some(reason) = self.reason().filter(|r| INVALID_KEY_REASONS.contains(r)) {
+            return UpstreamError::InvalidKey {
+                reason: reason.to_string(),
+            };
+        }
+
+        match self.quota() {
+            Some(quota) if self.code == 429 || self.status == "RESOURCE_EXHAUSTED" => {
+                UpstreamError::Ratelimited {
+                    quota,
+                    retry_after: self.retry_delay(),
+                }
+            }
+            _ => UpstreamError::Other,
+        }
+    }
+}
+
+/// Classifies an upstream error response, falling back on the status code
+/// when the body isn't a `google.rpc.Status`.
+pub fn classify(status: u16, body: &[u8]) -> UpstreamError {
+    match Status::parse(body) {
+        Some(mut parsed) => {
+            if parsed.code == 0 {
+                parsed.code = status;
+            }
+            parsed.classify()
+        }
+        None if status == 429 => UpstreamError::Ratelimited {
+            quota: Quota::Unknown,
+            retry_after: None,
+        },
+        None => UpstreamError::Other,
+    }
+}
+
+/// Recognizes an error sent as an event of an otherwise successful stream,
+/// returning the status code it carries.
+pub fn in_band_error(event: &[u8]) -> Option<u16> {
+    let value: Value = serde_json::from_slice(event).ok()?;
+    let error = match &value {
+        Value::Array(items) => items.first()?.get("error")?,
+        other => other.get("error")?,
+    };
+    Some(
+        error
+            .get("code")
+            .and_then(Value::as_u64)
+            .and_then(|code| u16::try_from(code).ok())
+            .unwrap_or(500),
+    )
+}
+
+/// Parses the JSON form of `google.protobuf.Duration`, e.g. `"20s"` or
+/// `"1.5s"`.
+fn parse_proto_duration(value: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(value.strip_suffix('s')?.parse().ok()?).ok()
+}
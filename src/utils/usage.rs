@@ -0,0 +1,124 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::web::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde_json::Value;
+
+/// Total tokens billed for a complete response body, in either dialect:
+/// `usageMetadata.totalTokenCount` on native routes, `usage.total_tokens` on
+/// the OpenAI compatible ones. Streamed JSON arrays report cumulative usage,
+/// so the last element wins.
+pub fn total_tokens(body: &[u8]) -> Option<u64> {
+    fn of(value: &Value) -> Option<u64> {
+        value
+            .pointer("/usageMetadata/totalTokenCount")
+            .or_else(|| value.pointer("/usage/total_tokens"))
+            .and_then(Value::as_u64)
+    }
+
+    match serde_json::from_slice::<Value>(body).ok()? {
+        Value::Array(items) => items.iter().rev().find_map(of),
+        value => of(&value),
+    }
+}
+
+const USAGE_FIELDS: [&[u8]; 2] = [b"\"totalTokenCount\"", b"\"total_tokens\""];
+
+/// Picks token counts out of a response as it streams by, without caring how
+/// it's framed (SSE events or a JSON array split at arbitrary points).
+#[derive(Default)]
+struct TokenScanner {
+    carry: Vec<u8>,
+    last: Option<u64>,
+}
+
+impl TokenScanner {
+    /// Enough to hold a field name and its value across a chunk boundary.
+    const CARRY: usize = 48;
+
+    fn feed(&mut self, chunk: &[u8]) {
+        let mut window = std::mem::take(&mut self.carry);
+        window.extend_from_slice(chunk);
+
+        for field in USAGE_FIELDS {
+            let mut rest = window.as_slice();
+            while let Some(pos) = find(rest, field) {
+                rest = &rest[pos + field.len()..];
+                if let Some(value) = parse_value(rest) {
+                    self.last = Some(value);
+                }
+            }
+        }
+
+        let keep = window.len().saturating_sub(Self::CARRY);
+        self.carry = window.split_off(keep);
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Parses the number following `: ` after a field name, if it's all there.
+fn parse_value(rest: &[u8]) -> Option<u64> {
+    let rest = rest
+        .trim_ascii_start()
+        .strip_prefix(b":")?
+        .trim_ascii_start();
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    // A number running into the end of the chunk may still continue.
+    if digits == 0 || digits == rest.len() {
+        return None;
+    }
+    std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()
+}
+
+/// Passes a response stream through untouched, reporting the tokens it used
+/// once it ends or is dropped.
+pub struct UsageTap<S> {
+    inner: S,
+    scanner: TokenScanner,
+    on_done: Option<Box<dyn FnOnce(u64)>>,
+}
+
+impl<S> UsageTap<S> {
+    pub fn new(inner: S, on_done: impl FnOnce(u64) + 'static) -> Self {
+        Self {
+            inner,
+            scanner: TokenScanner::default(),
+            on_done: Some(Box::new(on_done)),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let (Some(on_done), Some(tokens)) = (self.on_done.take(), self.scanner.last) {
+            on_done(tokens);
+        }
+    }
+}
+
+impl<S, E> Stream for UsageTap<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = self.inner.poll_next_unpin(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(chunk))) => self.scanner.feed(chunk),
+            Poll::Ready(None) => self.finish(),
+            _ => {}
+        }
+        polled
+    }
+}
+
+impl<S> Drop for UsageTap<S> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
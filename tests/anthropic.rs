@@ -0,0 +1,350 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client, events, raw};
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+
+async fn post(juggler: &Juggler, path: &str, body: &Value) -> (u16, String) {
+    let mut resp = client()
+        .post(juggler.url(path))
+        .insert_header(("x-api-key", API_KEY))
+        .insert_header(("anthropic-version", "2023-06-01"))
+        .send_json(body)
+        .await
+        .unwrap();
+    let body = resp.body().limit(1 << 20).await.unwrap();
+    (
+        resp.status().as_u16(),
+        String::from_utf8_lossy(&body).into_owned(),
+    )
+}
+
+async fn messages(juggler: &Juggler, body: &Value) -> (u16, Value) {
+    let (status, body) = post(juggler, "/v1/messages", body).await;
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+#[actix_web::test]
+async fn translates_text_messages() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = messages(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "max_tokens": 256,
+            "system": "Be brief.",
+            "stop_sequences": ["END"],
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": [{"type": "text", "text": "hello"}]},
+                {"role": "user", "content": [{"type": "text", "text": "again"}]},
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["type"], "message");
+    assert_eq!(body["role"], "assistant");
+    assert_eq!(body["model"], MODEL);
+    assert!(body["id"].as_str().unwrap().starts_with("msg_"));
+    assert_eq!(
+        body["content"],
+        json!([{"type": "text", "text": "hello from key-a"}])
+    );
+    assert_eq!(body["stop_reason"], "end_turn");
+    assert_eq!(body["usage"]["input_tokens"], 4);
+    assert_eq!(body["usage"]["output_tokens"], 3);
+
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["systemInstruction"],
+        json!({"parts": [{"text": "Be brief."}]})
+    );
+    assert_eq!(
+        sent["contents"][0],
+        json!({"role": "user", "parts": [{"text": "hi"}]})
+    );
+    assert_eq!(sent["contents"][1]["role"], "model");
+    assert_eq!(sent["generationConfig"]["maxOutputTokens"], 256);
+    assert_eq!(sent["generationConfig"]["stopSequences"], json!(["END"]));
+}
+
+#[actix_web::test]
+async fn translates_tools_and_images() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        raw(json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "Let me look."},
+                    {"functionCall": {"name": "lookup", "args": {"city": "Lisbon"}}, "thoughtSignature": "c2ln"},
+                ]},
+                "finishReason": "STOP",
+            }],
+            "usageMetadata": {"promptTokenCount": 20, "candidatesTokenCount": 5, "thoughtsTokenCount": 2, "totalTokenCount": 27},
+        })),
+    );
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = messages(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "max_tokens": 256,
+            "tools": [{
+                "name": "lookup",
+                "description": "Looks a city up",
+                "input_schema": {"type": "object", "properties": {"city": {"type": "string"}}},
+            }],
+            "tool_choice": {"type": "tool", "name": "lookup"},
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0K"}},
+                    {"type": "text", "text": "Where is this?"},
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "", "signature": "b2xk"},
+                    {"type": "tool_use", "id": "toolu_1", "name": "lookup", "input": {"city": "Porto"}},
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "Not there"},
+                ]},
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["stop_reason"], "tool_use");
+    let content = body["content"].as_array().unwrap();
+    assert_eq!(content[0], json!({"type": "text", "text": "Let me look."}));
+    assert_eq!(content[1]["type"], "thinking");
+    assert_eq!(content[1]["signature"], "c2ln");
+    assert_eq!(content[2]["type"], "tool_use");
+    assert_eq!(content[2]["name"], "lookup");
+    assert_eq!(content[2]["input"], json!({"city": "Lisbon"}));
+    assert!(content[2]["id"].as_str().unwrap().starts_with("toolu_"));
+    assert_eq!(body["usage"]["output_tokens"], 7);
+
+    let sent = mock.last_body("key-a").unwrap();
+    let declaration = &sent["tools"][0]["functionDeclarations"][0];
+    assert_eq!(declaration["name"], "lookup");
+    assert_eq!(declaration["parametersJsonSchema"]["type"], "object");
+    assert_eq!(
+        sent["toolConfig"]["functionCallingConfig"],
+        json!({"mode": "ANY", "allowedFunctionNames": ["lookup"]})
+    );
+    assert_eq!(
+        sent["contents"][0]["parts"][0],
+        json!({"inlineData": {"mimeType": "image/png", "data": "iVBORw0K"}})
+    );
+    assert_eq!(
+        sent["contents"][1]["parts"][0],
+        json!({
+            "functionCall": {"id": "toolu_1", "name": "lookup", "args": {"city": "Porto"}},
+            "thoughtSignature": "b2xk",
+        })
+    );
+    assert_eq!(
+        sent["contents"][2]["parts"][0],
+        json!({"functionResponse": {
+            "id": "toolu_1",
+            "name": "lookup",
+            "response": {"content": "Not there"},
+        }})
+    );
+}
+
+#[actix_web::test]
+async fn streams_message_events() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = post(
+        &juggler,
+        "/v1/messages",
+        &json!({
+            "model": MODEL,
+            "max_tokens": 256,
+            "stream": true,
+            "messages": [{"role": "user", "content": "hi"}],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let events = events(&body);
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]
+    );
+    assert_eq!(events[0].1["message"]["usage"]["input_tokens"], 4);
+    assert_eq!(
+        events[1].1["content_block"],
+        json!({"type": "text", "text": ""})
+    );
+    assert_eq!(
+        events[3].1["delta"],
+        json!({"type": "text_delta", "text": "chunk 1 "})
+    );
+    assert_eq!(events[6].1["delta"]["stop_reason"], "end_turn");
+    assert_eq!(events[6].1["usage"]["output_tokens"], 3);
+    for (name, data) in &events {
+        assert_eq!(&data["type"], name);
+    }
+}
+
+#[actix_web::test]
+async fn streams_tool_use_blocks() {
+    let mock = MockUpstream::start().await;
+    let chunks = [
+        json!({"candidates": [{"content": {"role": "model", "parts": [{"text": "On it."}]}}]}),
+        json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"functionCall": {"id": "call-1", "name": "lookup", "args": {"city": "Lisbon"}}}]},
+                "finishReason": "STOP",
+            }],
+            "usageMetadata": {"promptTokenCount": 8, "candidatesTokenCount": 6, "totalTokenCount": 14},
+        }),
+    ];
+    let body: String = chunks
+        .iter()
+        .map(|chunk| format!("data: {chunk}\r\n\r\n"))
+        .collect();
+    mock.set("key-a", Behavior::Raw { status: 200, body });
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (_, body) = post(
+        &juggler,
+        "/v1/messages",
+        &json!({
+            "model": MODEL,
+            "max_tokens": 256,
+            "stream": true,
+            "messages": [{"role": "user", "content": "hi"}],
+        }),
+    )
+    .await;
+    let events = events(&body);
+    let tool_start = events
+        .iter()
+        .find(|(name, data)| name == "content_block_start" && data["index"] == 1)
+        .unwrap();
+    assert_eq!(
+        tool_start.1["content_block"],
+        json!({"type": "tool_use", "id": "call-1", "name": "lookup", "input": {}})
+    );
+    let tool_delta = events
+        .iter()
+        .find(|(name, data)| name == "content_block_delta" && data["index"] == 1)
+        .unwrap();
+    assert_eq!(tool_delta.1["delta"]["type"], "input_json_delta");
+    let input: Value =
+        serde_json::from_str(tool_delta.1["delta"]["partial_json"].as_str().unwrap()).unwrap();
+    assert_eq!(input, json!({"city": "Lisbon"}));
+    let (_, delta) = events
+        .iter()
+        .find(|(name, _)| name == "message_delta")
+        .unwrap();
+    assert_eq!(delta["delta"]["stop_reason"], "tool_use");
+    assert_eq!(delta["usage"]["output_tokens"], 6);
+}
+
+#[actix_web::test]
+async fn answers_errors_in_the_messages_format() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(30),
+        },
+    );
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let request =
+        json!({"model": MODEL, "max_tokens": 16, "messages": [{"role": "user", "content": "hi"}]});
+    let (status, body) = messages(&juggler, &request).await;
+    assert_eq!(status, 429);
+    assert_eq!(body["type"], "error");
+    assert_eq!(body["error"]["type"], "rate_limit_error");
+
+    let unauthorized = client()
+        .post(juggler.url("/v1/messages"))
+        .insert_header(("x-api-key", "nope"))
+        .send_json(&request)
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(unauthorized["error"]["type"], "authentication_error");
+
+    let (status, body) = messages(&juggler, &json!({"model": MODEL, "max_tokens": 16})).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+}
+
+#[actix_web::test]
+async fn counts_message_tokens() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = post(
+        &juggler,
+        "/v1/messages/count_tokens",
+        &json!({"model": MODEL, "system": "Be brief.", "messages": [{"role": "user", "content": "hi"}]}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body, json!({"input_tokens": 4}));
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["generateContentRequest"]["model"],
+        format!("models/{MODEL}")
+    );
+
+    // Failures, and answers without a count, come back as Anthropic errors.
+    mock.script(
+        "key-a",
+        [
+            Behavior::Raw {
+                status: 200,
+                body: "{}".to_string(),
+            },
+            Behavior::Raw {
+                status: 200,
+                body: "not json".to_string(),
+            },
+            Behavior::MinuteQuota {
+                retry_delay: Duration::from_secs(30),
+            },
+        ],
+    );
+    let request = json!({"model": MODEL, "messages": [{"role": "user", "content": "hi"}]});
+    for (status, kind) in [
+        (502, "api_error"),
+        (502, "api_error"),
+        (429, "rate_limit_error"),
+    ] {
+        let (got, body) = post(&juggler, "/v1/messages/count_tokens", &request).await;
+        assert_eq!(got, status);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["type"], "error");
+        assert_eq!(body["error"]["type"], kind);
+    }
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use awc::ClientRequest;
+use common::{API_KEY, Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+
+async fn send(req: ClientRequest, body: &Value) -> (u16, Value) {
+    let mut resp = req.send_json(body).await.unwrap();
+    let body = resp.body().await.unwrap();
+    (
+        resp.status().as_u16(),
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+fn generate_request(juggler: &Juggler, query: &str) -> ClientRequest {
+    client().post(juggler.url(&format!("/v1beta/models/{MODEL}:generateContent{query}")))
+}
+
+fn contents() -> Value {
+    json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]})
+}
+
+fn messages() -> Value {
+    json!({"model": MODEL, "messages": [{"role": "user", "content": "hi"}]})
+}
+
+#[actix_web::test]
+async fn accepts_keys_wherever_sdks_send_them() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let by_query = generate_request(&juggler, &format!("?key={API_KEY}"));
+    assert_eq!(send(by_query, &contents()).await.0, 200);
+    let by_header = generate_request(&juggler, "").insert_header(("x-goog-api-key", API_KEY));
+    assert_eq!(send(by_header, &contents()).await.0, 200);
+    let by_bearer = generate_request(&juggler, "")
+        .insert_header(("Authorization", format!("Bearer {API_KEY}")));
+    assert_eq!(send(by_bearer, &contents()).await.0, 200);
+
+    let stream = client()
+        .post(juggler.url(&format!(
+            "/v1beta/models/{MODEL}:streamGenerateContent?alt=sse"
+        )))
+        .insert_header(("x-goog-api-key", API_KEY));
+    assert_eq!(stream.send_json(&contents()).await.unwrap().status(), 200);
+
+    let chat = client()
+        .post(juggler.url("/v1beta/openai/chat/completions"))
+        .insert_header(("x-goog-api-key", API_KEY));
+    assert_eq!(send(chat, &messages()).await.0, 200);
+
+    let status = client()
+        .get(juggler.url(&format!("/status?key={API_KEY}")))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(status.status(), 200);
+}
+
+#[actix_web::test]
+async fn answers_missing_and_invalid_keys_in_the_route_dialect() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = send(generate_request(&juggler, ""), &contents()).await;
+    assert_eq!(status, 401);
+    assert_eq!(body["error"]["status"], "UNAUTHENTICATED");
+    assert!(
+        body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("Missing")
+    );
+
+    let wrong = generate_request(&juggler, "").insert_header(("x-goog-api-key", "nope"));
+    let (status, body) = send(wrong, &contents()).await;
+    assert_eq!(status, 401);
+    assert_eq!(body["error"]["message"], "Invalid API key");
+
+    let chat = client().post(juggler.url("/v1beta/openai/chat/completions"));
+    let (status, body) = send(chat, &messages()).await;
+    assert_eq!(status, 401);
+    assert_eq!(body["error"]["type"], "authentication_error");
+
+    let status = client()
+        .get(juggler.url("/status"))
+        .insert_header(("Authorization", "Bearer nope"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(status.status(), 401);
+    assert_eq!(mock.total_hits(), 0);
+}
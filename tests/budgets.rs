@@ -0,0 +1,104 @@
+mod common;
+
+use common::{Juggler, MockUpstream};
+use serde_json::json;
+
+const MODEL: &str = "gemini-2.5-flash";
+
+fn limits(table: &str) -> String {
+    format!("[config.limits.\"{MODEL}\"]\n{table}")
+}
+
+#[actix_web::test]
+async fn requests_per_minute_spread_over_keys() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a", "key-b"], &limits("rpm = 2"));
+
+    for _ in 0..4 {
+        assert_eq!(juggler.generate(MODEL).await.0, 200);
+    }
+    assert_eq!(mock.hits("key-a"), 2);
+    assert_eq!(mock.hits("key-b"), 2);
+
+    // Both keys are out of budget, the upstream is spared the request.
+    assert_eq!(juggler.generate(MODEL).await.0, 429);
+    assert_eq!(mock.total_hits(), 4);
+
+    // Other models have their own budget.
+    assert_eq!(juggler.generate("gemini-2.5-pro").await.0, 200);
+
+    let status = juggler.status().await;
+    assert_eq!(status["models"][MODEL]["active_keys"], 0);
+    let wait = status["models"][MODEL]["seconds_until_available"]
+        .as_i64()
+        .unwrap();
+    assert!((0..=60).contains(&wait));
+}
+
+#[actix_web::test]
+async fn tokens_per_minute_come_from_response_usage() {
+    let mock = MockUpstream::start().await;
+    // The mock reports 7 tokens per response.
+    let juggler = Juggler::start_with(&mock, &["key-a"], &limits("tpm = 10"));
+
+    assert_eq!(juggler.generate(MODEL).await.0, 200);
+    assert_eq!(juggler.chat(MODEL, false).await.0, 200);
+    assert_eq!(juggler.generate(MODEL).await.0, 429);
+    assert_eq!(mock.hits("key-a"), 2);
+}
+
+#[actix_web::test]
+async fn tokens_per_minute_are_counted_on_streams() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &limits("tpm = 5"));
+
+    let (status, body) = juggler
+        .post(
+            "/v1beta/openai/chat/completions",
+            &json!({
+                "model": MODEL,
+                "stream": true,
+                "stream_options": {"include_usage": true},
+                "messages": [{"role": "user", "content": "hi"}],
+            }),
+        )
+        .await;
+    assert_eq!(status, 200);
+    assert!(body.contains("\"total_tokens\":7"));
+
+    // Usage is recorded once the stream ends, after the response is sent.
+    actix_web::rt::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(juggler.chat(MODEL, true).await.0, 429);
+}
+
+#[actix_web::test]
+async fn requests_per_day_last_until_reset() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &limits("rpd = 1"));
+
+    assert_eq!(juggler.generate(MODEL).await.0, 200);
+    assert_eq!(juggler.generate(MODEL).await.0, 429);
+    assert_eq!(mock.hits("key-a"), 1);
+
+    let status = juggler.status().await;
+    let wait = status["keys"][0]["ratelimited_models"][MODEL]
+        .as_i64()
+        .unwrap();
+    assert!(wait > 60, "a daily budget should outlast the minute window");
+}
+
+#[actix_web::test]
+async fn refuses_zero_limits() {
+    let mock = MockUpstream::start().await;
+    for limit in ["rpm = 0", "tpm = 0", "rpd = 0"] {
+        let output = Juggler::refuse(&mock, &["key-a"], &limits(limit));
+        assert!(
+            output.contains("limits must be at least 1"),
+            "{limit}: {output}"
+        );
+    }
+
+    // Limits that allow something are fine.
+    let juggler = Juggler::start_with(&mock, &["key-a"], &limits("rpm = 1\ntpm = 1\nrpd = 1"));
+    assert_eq!(juggler.generate(MODEL).await.0, 200);
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+
+fn limited(limits: &str) -> String {
+    format!(
+        "[[config.clients]]\n\
+         name = \"limited\"\n\
+         key = \"limited-secret\"\n\
+         limits = {{ {limits} }}\n\
+         [[config.clients]]\n\
+         name = \"other\"\n\
+         key = \"other-secret\"\n"
+    )
+}
+
+async fn generate_as(juggler: &Juggler, key: &str) -> (u16, Option<String>, Value) {
+    let mut resp = client()
+        .post(juggler.url(&format!("/v1beta/models/{MODEL}:generateContent?key={key}")))
+        .send_json(&json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]}))
+        .await
+        .unwrap();
+    let retry_after = resp
+        .headers()
+        .get("Retry-After")
+        .map(|value| value.to_str().unwrap().to_string());
+    let body = resp.body().await.unwrap();
+    (
+        resp.status().as_u16(),
+        retry_after,
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+#[actix_web::test]
+async fn enforces_requests_per_minute() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &limited("rpm = 2"));
+
+    assert_eq!(generate_as(&juggler, "limited-secret").await.0, 200);
+    assert_eq!(generate_as(&juggler, "limited-secret").await.0, 200);
+
+    let (status, retry_after, body) = generate_as(&juggler, "limited-secret").await;
+    assert_eq!(status, 429);
+    let retry_after: i64 = retry_after.unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after));
+    assert_eq!(body["error"]["status"], "RESOURCE_EXHAUSTED");
+    assert_eq!(mock.total_hits(), 2);
+
+    // Other clients have limits of their own.
+    assert_eq!(generate_as(&juggler, "other-secret").await.0, 200);
+}
+
+#[actix_web::test]
+async fn treats_a_zero_rpm_budget_as_one() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &limited("rpm = 0"));
+
+    assert_eq!(generate_as(&juggler, "limited-secret").await.0, 200);
+    let (status, retry_after, _) = generate_as(&juggler, "limited-secret").await;
+    assert_eq!(status, 429);
+    assert!(retry_after.is_some());
+    assert_eq!(mock.total_hits(), 1);
+
+    // Turning requests away leaves the client's usage intact and readable.
+    assert_eq!(generate_as(&juggler, "other-secret").await.0, 200);
+    let status = juggler.status().await;
+    let limited = status["clients"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|client| client["name"] == "limited")
+        .unwrap();
+    assert_eq!(limited["num_requests"], 1);
+}
+
+#[actix_web::test]
+async fn enforces_daily_request_and_token_limits() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &limited("rpd = 1"));
+    assert_eq!(generate_as(&juggler, "limited-secret").await.0, 200);
+    assert_eq!(generate_as(&juggler, "limited-secret").await.0, 429);
+    assert_eq!(mock.total_hits(), 1);
+
+    // Each mock response uses 7 tokens, so the third request is over.
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &limited("tpd = 10"));
+    assert_eq!(generate_as(&juggler, "limited-secret").await.0, 200);
+    assert_eq!(generate_as(&juggler, "limited-secret").await.0, 200);
+    let (status, retry_after, _) = generate_as(&juggler, "limited-secret").await;
+    assert_eq!(status, 429);
+    assert!(retry_after.is_some());
+    assert_eq!(mock.total_hits(), 2);
+}
+
+#[actix_web::test]
+async fn enforces_concurrent_requests() {
+    let mock = MockUpstream::start().await;
+    mock.set_latency(Duration::from_millis(500));
+    let juggler = Juggler::start_with(&mock, &["key-a", "key-b"], &limited("max_concurrent = 1"));
+
+    let first = generate_as(&juggler, "limited-secret");
+    let second = async {
+        actix_web::rt::time::sleep(Duration::from_millis(150)).await;
+        generate_as(&juggler, "limited-secret").await
+    };
+    let ((first, ..), (second, retry_after, _)) = futures_util::join!(first, second);
+    assert_eq!(first, 200);
+    assert_eq!(second, 429);
+    assert!(retry_after.is_none());
+    assert_eq!(mock.total_hits(), 1);
+
+    // The slot is given back once the first request is done.
+    assert_eq!(generate_as(&juggler, "limited-secret").await.0, 200);
+    let status = juggler.status().await;
+    let limited = status["clients"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|client| client["name"] == "limited")
+        .unwrap();
+    assert_eq!(limited["in_flight"], 0);
+}
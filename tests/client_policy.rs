@@ -0,0 +1,184 @@
+mod common;
+
+use common::{Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+const KEY: &str = "policy-secret";
+
+fn policy(policy: &str) -> String {
+    format!(
+        "[[config.clients]]\n\
+         name = \"restricted\"\n\
+         key = \"{KEY}\"\n\
+         policy = {{ {policy} }}\n"
+    )
+}
+
+async fn generate(juggler: &Juggler, model: &str, body: Value) -> (u16, Value) {
+    let mut resp = client()
+        .post(juggler.url(&format!("/v1beta/models/{model}:generateContent?key={KEY}")))
+        .send_json(&body)
+        .await
+        .unwrap();
+    let body = resp.body().await.unwrap();
+    (
+        resp.status().as_u16(),
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+async fn chat(juggler: &Juggler, body: Value) -> (u16, Value) {
+    let mut resp = client()
+        .post(juggler.url("/v1beta/openai/chat/completions"))
+        .insert_header(("Authorization", format!("Bearer {KEY}")))
+        .send_json(&body)
+        .await
+        .unwrap();
+    let body = resp.body().await.unwrap();
+    (
+        resp.status().as_u16(),
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+fn contents() -> Value {
+    json!([{"role": "user", "parts": [{"text": "hi"}]}])
+}
+
+fn messages() -> Value {
+    json!([{"role": "user", "content": "hi"}])
+}
+
+#[actix_web::test]
+async fn allows_only_matching_models() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &policy("models = [\"gemini-*-flash*\"]"));
+
+    let allowed = json!({"contents": contents()});
+    assert_eq!(
+        generate(&juggler, "gemini-2.5-flash", allowed.clone())
+            .await
+            .0,
+        200
+    );
+    assert_eq!(
+        generate(&juggler, "gemini-2.5-flash-lite", allowed.clone())
+            .await
+            .0,
+        200
+    );
+
+    let (status, body) = generate(&juggler, "gemini-2.5-pro", allowed).await;
+    assert_eq!(status, 403);
+    assert_eq!(body["error"]["status"], "PERMISSION_DENIED");
+
+    let (status, body) = chat(
+        &juggler,
+        json!({"model": "gemini-2.5-pro", "messages": messages()}),
+    )
+    .await;
+    assert_eq!(status, 403);
+    assert_eq!(body["error"]["type"], "permission_error");
+    assert_eq!(
+        chat(
+            &juggler,
+            json!({"model": "models/gemini-2.0-flash", "messages": messages()})
+        )
+        .await
+        .0,
+        200
+    );
+
+    // Forwarded requests that name no model have none to refuse.
+    let resp = client()
+        .post(juggler.url("/v1beta/openai/embeddings"))
+        .insert_header(("Authorization", format!("Bearer {KEY}")))
+        .send_json(&json!({"input": "hi"}))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    assert_eq!(mock.total_hits(), 4);
+    let status = juggler.status().await;
+    let restricted = &status["clients"][1];
+    assert_eq!(restricted["name"], "restricted");
+    assert_eq!(restricted["num_requests"], 4);
+}
+
+#[actix_web::test]
+async fn caps_output_tokens() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &policy("max_output_tokens = 256"));
+
+    let over = json!({"contents": contents(), "generationConfig": {"maxOutputTokens": 1024}});
+    assert_eq!(generate(&juggler, "gemini-2.5-flash", over).await.0, 403);
+    let over = json!({"model": "gemini-2.5-flash", "messages": messages(), "max_tokens": 1024});
+    assert_eq!(chat(&juggler, over).await.0, 403);
+    // The proto spelling the API also accepts is held to the same cap.
+    let over = json!({"contents": contents(), "generation_config": {"max_output_tokens": 1024}});
+    assert_eq!(generate(&juggler, "gemini-2.5-flash", over).await.0, 403);
+    let over = json!({
+        "contents": contents(),
+        "generationConfig": {"maxOutputTokens": 128},
+        "generation_config": {"max_output_tokens": 1024},
+    });
+    assert_eq!(generate(&juggler, "gemini-2.5-flash", over).await.0, 403);
+    assert_eq!(mock.total_hits(), 0);
+
+    let within = json!({"contents": contents(), "generationConfig": {"maxOutputTokens": 128}});
+    assert_eq!(generate(&juggler, "gemini-2.5-flash", within).await.0, 200);
+    assert_eq!(
+        mock.last_body("key-a").unwrap()["generationConfig"]["maxOutputTokens"],
+        128
+    );
+
+    // Requests that don't say are capped to the policy.
+    let unset = json!({"contents": contents()});
+    assert_eq!(generate(&juggler, "gemini-2.5-flash", unset).await.0, 200);
+    assert_eq!(
+        mock.last_body("key-a").unwrap()["generationConfig"]["maxOutputTokens"],
+        256
+    );
+    let unset = json!({"contents": contents(), "generation_config": {"temperature": 0.5}});
+    assert_eq!(generate(&juggler, "gemini-2.5-flash", unset).await.0, 200);
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(sent["generation_config"]["max_output_tokens"], 256);
+    assert!(sent.get("generationConfig").is_none());
+    let unset = json!({"model": "gemini-2.5-flash", "messages": messages()});
+    assert_eq!(chat(&juggler, unset).await.0, 200);
+    assert_eq!(mock.last_body("key-a").unwrap()["max_tokens"], 256);
+}
+
+#[actix_web::test]
+async fn refuses_tools_and_grounding() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(
+        &mock,
+        &["key-a"],
+        &policy("tools = false, grounding = false"),
+    );
+
+    let functions = json!({
+        "contents": contents(),
+        "tools": [{"functionDeclarations": [{"name": "lookup"}]}],
+    });
+    let (status, body) = generate(&juggler, "gemini-2.5-flash", functions).await;
+    assert_eq!(status, 403);
+    assert_eq!(body["error"]["message"], "Tools are not allowed");
+
+    let search = json!({"contents": contents(), "tools": [{"googleSearch": {}}]});
+    let (status, body) = generate(&juggler, "gemini-2.5-flash", search).await;
+    assert_eq!(status, 403);
+    assert_eq!(body["error"]["message"], "Grounding is not allowed");
+
+    let functions = json!({
+        "model": "gemini-2.5-flash",
+        "messages": messages(),
+        "tools": [{"type": "function", "function": {"name": "lookup"}}],
+    });
+    assert_eq!(chat(&juggler, functions).await.0, 403);
+    assert_eq!(mock.total_hits(), 0);
+
+    let plain = json!({"contents": contents()});
+    assert_eq!(generate(&juggler, "gemini-2.5-flash", plain).await.0, 200);
+}
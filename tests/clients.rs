@@ -0,0 +1,137 @@
+mod common;
+
+use std::time::Instant;
+
+use argon2::password_hash::{PasswordHasher, SaltString};
+use common::{Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+const MODEL: &str = "gemini-2.5-flash";
+
+fn clients() -> String {
+    let sha256: String = Sha256::digest("team-b-secret")
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let argon2 = argon2::Argon2::default()
+        .hash_password(
+            b"team-c-secret",
+            &SaltString::from_b64("c29tZXNhbHRzb21lc2FsdA").unwrap(),
+        )
+        .unwrap()
+        .to_string();
+
+    format!(
+        "[[config.clients]]\n\
+         name = \"team-a\"\n\
+         key = \"team-a-secret\"\n\
+         [[config.clients]]\n\
+         name = \"team-b\"\n\
+         key_sha256 = \"{sha256}\"\n\
+         [[config.clients]]\n\
+         name = \"team-c\"\n\
+         key_argon2 = \"{argon2}\"\n\
+         [[config.clients]]\n\
+         name = \"team-d\"\n\
+         key = \"team-d-secret\"\n\
+         revoked = true\n"
+    )
+}
+
+async fn generate_as(juggler: &Juggler, key: &str) -> u16 {
+    client()
+        .post(juggler.url(&format!("/v1beta/models/{MODEL}:generateContent?key={key}")))
+        .send_json(&json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]}))
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+async fn chat_as(juggler: &Juggler, key: &str) -> u16 {
+    client()
+        .post(juggler.url("/v1beta/openai/chat/completions"))
+        .insert_header(("Authorization", format!("Bearer {key}")))
+        .send_json(&json!({"model": MODEL, "messages": [{"role": "user", "content": "hi"}]}))
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+fn client_status<'a>(status: &'a Value, name: &str) -> &'a Value {
+    status["clients"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|client| client["name"] == name)
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn accepts_plain_and_hashed_client_keys() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &clients());
+
+    for key in ["team-a-secret", "team-b-secret", "team-c-secret"] {
+        assert_eq!(generate_as(&juggler, key).await, 200);
+        assert_eq!(chat_as(&juggler, key).await, 200);
+    }
+    assert_eq!(generate_as(&juggler, "nobody").await, 401);
+    assert_eq!(chat_as(&juggler, "nobody").await, 401);
+}
+
+#[actix_web::test]
+async fn turns_away_revoked_clients() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &clients());
+
+    assert_eq!(generate_as(&juggler, "team-d-secret").await, 401);
+    assert_eq!(chat_as(&juggler, "team-d-secret").await, 401);
+    assert_eq!(mock.total_hits(), 0);
+}
+
+#[actix_web::test]
+async fn attributes_usage_to_clients() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &clients());
+
+    assert_eq!(generate_as(&juggler, "team-a-secret").await, 200);
+    assert_eq!(chat_as(&juggler, "team-a-secret").await, 200);
+    assert_eq!(generate_as(&juggler, "team-c-secret").await, 200);
+
+    let status = juggler.status().await;
+    let team_a = client_status(&status, "team-a");
+    assert_eq!(team_a["num_requests"], 2);
+    assert_eq!(team_a["num_tokens"], 14);
+    let team_c = client_status(&status, "team-c");
+    assert_eq!(team_c["num_requests"], 1);
+    assert_eq!(team_c["num_tokens"], 7);
+    assert_eq!(client_status(&status, "team-b")["num_requests"], 0);
+    assert_eq!(client_status(&status, "team-d")["revoked"], true);
+}
+
+#[actix_web::test]
+async fn remembers_unknown_keys() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], &clients());
+
+    // The first try checks the key against the argon2 hash, the ones after
+    // are turned away without hashing it again.
+    let started = Instant::now();
+    assert_eq!(generate_as(&juggler, "nobody").await, 401);
+    let first = started.elapsed();
+
+    let started = Instant::now();
+    for _ in 0..10 {
+        assert_eq!(generate_as(&juggler, "nobody").await, 401);
+    }
+    assert!(
+        started.elapsed() < first,
+        "{:?} vs {first:?}",
+        started.elapsed()
+    );
+
+    assert_eq!(generate_as(&juggler, "team-c-secret").await, 200);
+}
@@ -0,0 +1,681 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::{
+    App, HttpRequest, HttpResponse, HttpServer,
+    dev::Service,
+    http::header,
+    middleware::{Compress, Condition},
+    web,
+};
+use futures_util::{StreamExt, stream};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+/// What the mock does when it sees a request made with a given key.
+#[derive(Clone, Debug)]
+pub enum Behavior {
+    Ok,
+    /// Free tier daily quota exhausted.
+    DailyQuota,
+    /// Per-minute quota exhausted, clears after `retry_delay`.
+    MinuteQuota {
+        retry_delay: Duration,
+    },
+    /// `API_KEY_INVALID`, as returned for revoked or mistyped keys.
+    InvalidKey,
+    /// The model is overloaded.
+    ServerError,
+    /// Succeeds, but streams `chunks` pieces `delay` apart.
+    SlowStream {
+        chunks: usize,
+        delay: Duration,
+    },
+    /// Streams `after` chunks, then breaks off, by dropping the connection or,
+    /// with `in_band`, by sending a per-minute quota error as the next event.
+    BrokenStream {
+        after: usize,
+        in_band: bool,
+    },
+    /// Responds with exactly this status and body.
+    Raw {
+        status: u16,
+        body: String,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    defaults: HashMap<String, Behavior>,
+    scripts: HashMap<String, VecDeque<Behavior>>,
+    hits: HashMap<String, usize>,
+    bodies: HashMap<String, Value>,
+    busy: HashMap<String, Arc<tokio::sync::Mutex<()>>>,
+    latency: Duration,
+    key_latency: HashMap<String, Duration>,
+    serial_per_key: bool,
+    compressed: usize,
+}
+
+impl State {
+    fn next(&mut self, key: &str, model: &str, body: &Value) -> Behavior {
+        *self.hits.entry(key.to_string()).or_default() += 1;
+        self.bodies.insert(key.to_string(), body.clone());
+        let per_model = format!("{key}@{model}");
+        for target in [per_model.as_str(), key] {
+            if let Some(behavior) = self.scripts.get_mut(target).and_then(VecDeque::pop_front) {
+                return behavior;
+            }
+        }
+        for target in [per_model.as_str(), key] {
+            if let Some(behavior) = self.defaults.get(target) {
+                return behavior.clone();
+            }
+        }
+        Behavior::Ok
+    }
+}
+
+/// Stand-in for `generativelanguage.googleapis.com`, serving the native
+/// `generateContent`/`streamGenerateContent` routes and the OpenAI compatible
+/// `chat/completions` route with per-key scripted failures.
+#[derive(Clone)]
+pub struct MockUpstream {
+    state: Arc<Mutex<State>>,
+    pub addr: SocketAddr,
+}
+
+impl MockUpstream {
+    pub async fn start() -> Self {
+        Self::start_serving(false).await
+    }
+
+    /// Like [`MockUpstream::start`], compressing responses for clients that
+    /// accept it, like Google's servers do.
+    pub async fn start_compressed() -> Self {
+        Self::start_serving(true).await
+    }
+
+    async fn start_serving(compress: bool) -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let data = web::Data::from(state.clone());
+
+        let server = HttpServer::new(move || {
+            let counted = data.clone();
+            App::new()
+                .wrap(Condition::new(compress, Compress::default()))
+                .wrap_fn(move |req, srv| {
+                    let state = counted.clone();
+                    let resp = srv.call(req);
+                    async move {
+                        let resp = resp.await?;
+                        if resp.headers().contains_key(header::CONTENT_ENCODING) {
+                            state.lock().unwrap().compressed += 1;
+                        }
+                        Ok(resp)
+                    }
+                })
+                .app_data(data.clone())
+                .route(
+                    "/v1beta/models/{model}:generateContent",
+                    web::post().to(generate_content),
+                )
+                .route(
+                    "/v1beta/models/{model}:streamGenerateContent",
+                    web::post().to(stream_generate_content),
+                )
+                .route(
+                    "/v1beta/openai/chat/completions",
+                    web::post().to(chat_completions),
+                )
+                .route(
+                    "/v1beta/models/{model}:{method}",
+                    web::post().to(model_method),
+                )
+                .route("/v1beta/openai/{path:.*}", web::post().to(openai_endpoint))
+                .route("/v1beta/models", web::get().to(list_models))
+                .route("/v1beta/openai/models", web::get().to(openai_list_models))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .expect("failed to bind mock upstream");
+
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        Self { state, addr }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Behavior used for every request with `key` once its script runs out.
+    pub fn set(&self, key: &str, behavior: Behavior) {
+        self.state
+            .lock()
+            .unwrap()
+            .defaults
+            .insert(key.to_string(), behavior);
+    }
+
+    /// Like [`MockUpstream::set`], only for requests to `model`.
+    pub fn set_for_model(&self, key: &str, model: &str, behavior: Behavior) {
+        self.set(&format!("{key}@{model}"), behavior);
+    }
+
+    /// Behaviors used, in order, for the next requests with `key`.
+    pub fn script(&self, key: &str, behaviors: impl IntoIterator<Item = Behavior>) {
+        self.state
+            .lock()
+            .unwrap()
+            .scripts
+            .entry(key.to_string())
+            .or_default()
+            .extend(behaviors);
+    }
+
+    /// Delays every response, to emulate generation time.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Like [`MockUpstream::set_latency`], only for requests with `key`.
+    pub fn set_latency_for(&self, key: &str, latency: Duration) {
+        self.state
+            .lock()
+            .unwrap()
+            .key_latency
+            .insert(key.to_string(), latency);
+    }
+
+    /// Makes each key handle one request at a time, like a per-key
+    /// concurrency limit upstream.
+    pub fn set_serial_per_key(&self, serial: bool) {
+        self.state.lock().unwrap().serial_per_key = serial;
+    }
+
+    pub fn hits(&self, key: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .hits
+            .get(key)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The body of the last request made with `key`.
+    pub fn last_body(&self, key: &str) -> Option<Value> {
+        self.state.lock().unwrap().bodies.get(key).cloned()
+    }
+
+    pub fn total_hits(&self) -> usize {
+        self.state.lock().unwrap().hits.values().sum()
+    }
+
+    /// How many responses went out compressed.
+    pub fn compressed(&self) -> usize {
+        self.state.lock().unwrap().compressed
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyQuery {
+    key: String,
+    alt: Option<String>,
+}
+
+enum Dialect {
+    Gemini,
+    OpenAi,
+}
+
+async fn dispatch(state: &Mutex<State>, key: &str, model: &str, body: &Value) -> Behavior {
+    let (behavior, latency, lock) = {
+        let mut state = state.lock().unwrap();
+        let lock = state
+            .serial_per_key
+            .then(|| state.busy.entry(key.to_string()).or_default().clone());
+        let latency = state.key_latency.get(key).copied().unwrap_or(state.latency);
+        (state.next(key, model, body), latency, lock)
+    };
+
+    let _guard = match &lock {
+        Some(lock) => Some(lock.lock().await),
+        None => None,
+    };
+    if !latency.is_zero() {
+        actix_web::rt::time::sleep(latency).await;
+    }
+
+    behavior
+}
+
+async fn generate_content(
+    path: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    body: web::Json<Value>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let model = path.into_inner();
+    match dispatch(&state, &query.key, &model, &body).await {
+        Behavior::Ok | Behavior::SlowStream { .. } | Behavior::BrokenStream { .. } => {
+            HttpResponse::Ok().json(gemini_chunk(&format!("hello from {}", query.key), true))
+        }
+        behavior => error_response(&behavior, &model, Dialect::Gemini),
+    }
+}
+
+/// `countTokens`, `embedContent` and `batchEmbedContents`, answered the way
+/// Gemini does, without usage metadata.
+async fn model_method(
+    path: web::Path<(String, String)>,
+    query: web::Query<KeyQuery>,
+    body: web::Json<Value>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let (model, method) = path.into_inner();
+    match dispatch(&state, &query.key, &model, &body).await {
+        Behavior::Ok | Behavior::SlowStream { .. } | Behavior::BrokenStream { .. } => {}
+        behavior => return error_response(&behavior, &model, Dialect::Gemini),
+    }
+
+    let embedding = json!({"values": [0.5, 0.25, 0.125]});
+    match method.as_str() {
+        "countTokens" => HttpResponse::Ok().json(json!({"totalTokens": 4})),
+        "embedContent" => HttpResponse::Ok().json(json!({"embedding": embedding})),
+        "batchEmbedContents" => {
+            let count = body["requests"].as_array().map_or(0, Vec::len);
+            HttpResponse::Ok().json(json!({"embeddings": vec![embedding; count]}))
+        }
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// The other OpenAI compatible endpoints. JSON bodies are recorded as they
+/// are, anything else (audio uploads) as its content type and size.
+async fn openai_endpoint(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let Some(key) = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let recorded = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| json!({"content_type": content_type, "size": body.len()}));
+    let model = recorded["model"].as_str().unwrap_or("none").to_string();
+    match dispatch(&state, key, &model, &recorded).await {
+        Behavior::Ok | Behavior::SlowStream { .. } | Behavior::BrokenStream { .. } => {}
+        behavior => return error_response(&behavior, &model, Dialect::OpenAi),
+    }
+
+    match path.as_str() {
+        "embeddings" => HttpResponse::Ok().json(json!({
+            "object": "list",
+            "data": [{"object": "embedding", "index": 0, "embedding": [0.5, 0.25, 0.125]}],
+            "model": model,
+            "usage": {"prompt_tokens": 2, "total_tokens": 2},
+        })),
+        "images/generations" => {
+            HttpResponse::Ok().json(json!({"data": [{"b64_json": "aW1hZ2U="}]}))
+        }
+        "audio/speech" => HttpResponse::Ok()
+            .content_type("audio/mpeg")
+            .body(vec![0xffu8, 0xfb, 0x90, 0x00]),
+        "audio/transcriptions" => HttpResponse::Ok().json(json!({"text": "hello"})),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+const LISTED_MODELS: [&str; 3] = ["gemini-2.5-flash", "gemini-2.5-pro", "gemini-embedding-001"];
+
+async fn list_models(query: web::Query<KeyQuery>, state: web::Data<Mutex<State>>) -> HttpResponse {
+    match dispatch(&state, &query.key, "models", &Value::Null).await {
+        Behavior::Ok | Behavior::SlowStream { .. } | Behavior::BrokenStream { .. } => {}
+        behavior => return error_response(&behavior, "models", Dialect::Gemini),
+    }
+
+    let models: Vec<Value> = LISTED_MODELS
+        .iter()
+        .map(|model| json!({"name": format!("models/{model}"), "displayName": model}))
+        .collect();
+    HttpResponse::Ok().json(json!({"models": models}))
+}
+
+async fn openai_list_models(req: HttpRequest, state: web::Data<Mutex<State>>) -> HttpResponse {
+    let Some(key) = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    match dispatch(&state, key, "models", &Value::Null).await {
+        Behavior::Ok | Behavior::SlowStream { .. } | Behavior::BrokenStream { .. } => {}
+        behavior => return error_response(&behavior, "models", Dialect::OpenAi),
+    }
+
+    let models: Vec<Value> = LISTED_MODELS
+        .iter()
+        .map(|model| json!({"id": format!("models/{model}"), "object": "model", "owned_by": "google"}))
+        .collect();
+    HttpResponse::Ok().json(json!({"object": "list", "data": models}))
+}
+
+async fn stream_generate_content(
+    path: web::Path<String>,
+    query: web::Query<KeyQuery>,
+    body: web::Json<Value>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let model = path.into_inner();
+    let (chunks, delay, break_off) = match dispatch(&state, &query.key, &model, &body).await {
+        Behavior::Ok => (3, Duration::ZERO, None),
+        Behavior::SlowStream { chunks, delay } => (chunks, delay, None),
+        Behavior::BrokenStream { after, in_band } => (after, Duration::ZERO, Some(in_band)),
+        behavior => return error_response(&behavior, &model, Dialect::Gemini),
+    };
+
+    let mut events: Vec<Value> = (0..chunks)
+        .map(|i| {
+            gemini_chunk(
+                &format!("chunk {i} "),
+                i + 1 == chunks && break_off.is_none(),
+            )
+        })
+        .collect();
+    if break_off == Some(true) {
+        events.push(minute_quota_error(&model));
+    }
+    let drop_connection = break_off == Some(false);
+
+    match query.alt.as_deref() {
+        Some("sse") => stream_response(
+            "text/event-stream",
+            events
+                .iter()
+                .map(|e| format!("data: {e}\r\n\r\n"))
+                .collect(),
+            delay,
+            drop_connection,
+        ),
+        _ => {
+            let mut parts: Vec<String> = events
+                .iter()
+                .enumerate()
+                .map(|(i, e)| format!("{}{e}", if i == 0 { "[" } else { "," }))
+                .collect();
+            if !drop_connection {
+                parts.push("]".to_string());
+            }
+            stream_response("application/json", parts, delay, drop_connection)
+        }
+    }
+}
+
+async fn chat_completions(
+    req: HttpRequest,
+    body: web::Json<Value>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let Some(key) = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    let model = body["model"]
+        .as_str()
+        .unwrap_or("gemini-2.5-flash")
+        .to_string();
+    let streaming = body["stream"].as_bool().unwrap_or(false);
+
+    let (chunks, delay, break_off) = match dispatch(&state, key, &model, &body).await {
+        Behavior::Ok => (3, Duration::ZERO, None),
+        Behavior::SlowStream { chunks, delay } => (chunks, delay, None),
+        Behavior::BrokenStream { after, in_band } if streaming => {
+            (after, Duration::ZERO, Some(in_band))
+        }
+        Behavior::BrokenStream { .. } => (3, Duration::ZERO, None),
+        behavior => return error_response(&behavior, &model, Dialect::OpenAi),
+    };
+
+    if !streaming {
+        return HttpResponse::Ok().json(json!({
+            "id": "chatcmpl-mock",
+            "object": "chat.completion",
+            "created": 0,
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": format!("hello from {key}")},
+                "finish_reason": "stop",
+            }],
+            "usage": {"prompt_tokens": 4, "completion_tokens": 3, "total_tokens": 7},
+        }));
+    }
+
+    let mut events: Vec<String> = (0..chunks)
+        .map(|i| {
+            let chunk = json!({
+                "id": "chatcmpl-mock",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": model,
+                "choices": [{
+                    "index": 0,
+                    "delta": {"role": "assistant", "content": format!("chunk {i} ")},
+                    "finish_reason": (i + 1 == chunks && break_off.is_none()).then_some("stop"),
+                }],
+            });
+            format!("data: {chunk}\n\n")
+        })
+        .collect();
+    match break_off {
+        Some(true) => {
+            let error = json!([minute_quota_error(&model)]);
+            events.push(format!("data: {error}\n\n"));
+            return stream_response("text/event-stream", events, delay, false);
+        }
+        Some(false) => return stream_response("text/event-stream", events, delay, true),
+        None => {}
+    }
+    if body["stream_options"]["include_usage"] == true {
+        let usage = json!({
+            "id": "chatcmpl-mock",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": model,
+            "choices": [],
+            "usage": {"prompt_tokens": 4, "completion_tokens": 3, "total_tokens": 7},
+        });
+        events.push(format!("data: {usage}\n\n"));
+    }
+    events.push("data: [DONE]\n\n".to_string());
+
+    stream_response("text/event-stream", events, delay, false)
+}
+
+/// Streams `parts` `delay` apart, then either ends the response or drops the
+/// connection halfway through it.
+fn stream_response(
+    content_type: &str,
+    parts: Vec<String>,
+    delay: Duration,
+    drop_connection: bool,
+) -> HttpResponse {
+    let body = match drop_connection {
+        true => delayed(parts, delay)
+            .chain(stream::once(async {
+                // Lets whatever came before reach the client first.
+                actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+                Err(actix_web::error::ErrorInternalServerError(
+                    "connection reset",
+                ))
+            }))
+            .boxed_local(),
+        false => delayed(parts, delay).boxed_local(),
+    };
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .streaming(body)
+}
+
+fn delayed(
+    parts: Vec<String>,
+    delay: Duration,
+) -> impl futures_util::Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    stream::unfold(parts.into_iter(), move |mut parts| async move {
+        let part = parts.next()?;
+        if !delay.is_zero() {
+            actix_web::rt::time::sleep(delay).await;
+        }
+        Some((Ok(web::Bytes::from(part)), parts))
+    })
+}
+
+fn gemini_chunk(text: &str, last: bool) -> Value {
+    let mut candidate = json!({
+        "content": {"parts": [{"text": text}], "role": "model"},
+        "index": 0,
+    });
+    if last {
+        candidate["finishReason"] = json!("STOP");
+    }
+    json!({
+        "candidates": [candidate],
+        "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 3, "totalTokenCount": 7},
+        "modelVersion": "mock",
+    })
+}
+
+fn minute_quota_error(model: &str) -> Value {
+    quota_error(
+        model,
+        "GenerateRequestsPerMinutePerProjectPerModel-FreeTier",
+        "30s",
+    )
+}
+
+fn quota_error(model: &str, quota_id: &str, retry_delay: &str) -> Value {
+    json!({
+        "error": {
+            "code": 429,
+            "message": format!(
+                "You exceeded your current quota, please check your plan and billing details. \
+                 Please retry in {retry_delay}."
+            ),
+            "status": "RESOURCE_EXHAUSTED",
+            "details": [
+                {
+                    "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                    "violations": [{
+                        "quotaMetric": "generativelanguage.googleapis.com/generate_content_free_tier_requests",
+                        "quotaId": quota_id,
+                        "quotaDimensions": {"location": "global", "model": model},
+                        "quotaValue": "10",
+                    }],
+                },
+                {
+                    "@type": "type.googleapis.com/google.rpc.Help",
+                    "links": [{
+                        "description": "Learn more about Gemini API quotas",
+                        "url": "https://ai.google.dev/gemini-api/docs/rate-limits",
+                    }],
+                },
+                {
+                    "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                    "retryDelay": retry_delay,
+                },
+            ],
+        }
+    })
+}
+
+fn error_response(behavior: &Behavior, model: &str, dialect: Dialect) -> HttpResponse {
+    let (status, body) = match behavior {
+        Behavior::DailyQuota => (
+            429,
+            quota_error(
+                model,
+                "GenerateRequestsPerDayPerProjectPerModel-FreeTier",
+                "43s",
+            ),
+        ),
+        Behavior::MinuteQuota { retry_delay } => (
+            429,
+            quota_error(
+                model,
+                "GenerateRequestsPerMinutePerProjectPerModel-FreeTier",
+                &format!("{}s", retry_delay.as_secs()),
+            ),
+        ),
+        Behavior::InvalidKey => (
+            400,
+            json!({
+                "error": {
+                    "code": 400,
+                    "message": "API key not valid. Please pass a valid API key.",
+                    "status": "INVALID_ARGUMENT",
+                    "details": [
+                        {
+                            "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                            "reason": "API_KEY_INVALID",
+                            "domain": "googleapis.com",
+                            "metadata": {"service": "generativelanguage.googleapis.com"},
+                        },
+                        {
+                            "@type": "type.googleapis.com/google.rpc.LocalizedMessage",
+                            "locale": "en-US",
+                            "message": "API key not valid. Please pass a valid API key.",
+                        },
+                    ],
+                }
+            }),
+        ),
+        Behavior::ServerError => (
+            503,
+            json!({
+                "error": {
+                    "code": 503,
+                    "message": "The model is overloaded. Please try again later.",
+                    "status": "UNAVAILABLE",
+                }
+            }),
+        ),
+        Behavior::Raw { status, body } => {
+            return HttpResponse::build(actix_web::http::StatusCode::from_u16(*status).unwrap())
+                .body(body.clone());
+        }
+        Behavior::Ok | Behavior::SlowStream { .. } | Behavior::BrokenStream { .. } => {
+            unreachable!("not an error behavior")
+        }
+    };
+
+    // The OpenAI compatible layer wraps Google's error object in a list.
+    let body = match dialect {
+        Dialect::Gemini => body,
+        Dialect::OpenAi => json!([body]),
+    };
+
+    HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap()).json(body)
+}
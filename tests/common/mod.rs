@@ -0,0 +1,219 @@
+#![allow(dead_code)]
+
+mod mock;
+
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use awc::Client;
+use serde_json::{Value, json};
+
+#[allow(unused_imports)]
+pub use mock::{Behavior, MockUpstream};
+
+pub const API_KEY: &str = "test-password";
+
+/// A `gemini-juggler` process running against a [`MockUpstream`], killed on
+/// drop.
+pub struct Juggler {
+    child: Child,
+    dir: PathBuf,
+    pub port: u16,
+}
+
+impl Juggler {
+    pub fn start(upstream: &MockUpstream, keys: &[&str]) -> Self {
+        Self::start_with(upstream, keys, "")
+    }
+
+    /// Like [`Juggler::start`], with `extra` appended to the `[config]`
+    /// table.
+    pub fn start_with(upstream: &MockUpstream, keys: &[&str], extra: &str) -> Self {
+        Self::start_with_keys(upstream, &serde_json::to_string(keys).unwrap(), extra)
+    }
+
+    /// Like [`Juggler::start_with`], with `keys` given as a TOML array.
+    pub fn start_with_keys(upstream: &MockUpstream, keys: &str, extra: &str) -> Self {
+        let (dir, port) = write_config(upstream, keys, extra);
+        let child = command(&dir)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start gemini-juggler");
+
+        let juggler = Self { child, dir, port };
+        juggler.wait_until_listening();
+        juggler
+    }
+
+    /// Starts `gemini-juggler` with a config it should refuse, returning
+    /// what it printed before giving up.
+    pub fn refuse(upstream: &MockUpstream, keys: &[&str], extra: &str) -> String {
+        let (dir, _) = write_config(upstream, &serde_json::to_string(keys).unwrap(), extra);
+        let mut child = command(&dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to start gemini-juggler");
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while child.try_wait().unwrap().is_none() {
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                panic!("gemini-juggler started with a config it should refuse");
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let output = child.wait_with_output().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    }
+
+    fn wait_until_listening(&self) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(("127.0.0.1", self.port)).is_err() {
+            assert!(Instant::now() < deadline, "gemini-juggler never came up");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{path}", self.port)
+    }
+
+    pub async fn generate(&self, model: &str) -> (u16, Value) {
+        let mut resp = client()
+            .post(self.url(&format!(
+                "/v1beta/models/{model}:generateContent?key={API_KEY}"
+            )))
+            .send_json(&json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]}))
+            .await
+            .expect("request to juggler failed");
+        let body = resp.body().limit(1 << 20).await.unwrap();
+        (
+            resp.status().as_u16(),
+            serde_json::from_slice(&body).unwrap_or(Value::Null),
+        )
+    }
+
+    pub async fn chat(&self, model: &str, stream: bool) -> (u16, String) {
+        self.post(
+            "/v1beta/openai/chat/completions",
+            &json!({
+                "model": model,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "hi"}],
+            }),
+        )
+        .await
+    }
+
+    /// Posts `body` to `path` with bearer auth, returning the raw response.
+    pub async fn post(&self, path: &str, body: &Value) -> (u16, String) {
+        let mut resp = client()
+            .post(self.url(path))
+            .insert_header(("Authorization", format!("Bearer {API_KEY}")))
+            .send_json(body)
+            .await
+            .expect("request to juggler failed");
+        let body = resp.body().limit(1 << 20).await.unwrap();
+        (
+            resp.status().as_u16(),
+            String::from_utf8_lossy(&body).into_owned(),
+        )
+    }
+
+    pub async fn status(&self) -> Value {
+        client()
+            .get(self.url("/status"))
+            .insert_header(("Authorization", format!("Bearer {API_KEY}")))
+            .send()
+            .await
+            .expect("request to juggler failed")
+            .json()
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for Juggler {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Writes a config for a juggler in front of `upstream` to a directory of
+/// its own, returning the directory and the port it will listen on.
+fn write_config(upstream: &MockUpstream, keys: &str, extra: &str) -> (PathBuf, u16) {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!(
+        "gemini-juggler-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let port = free_port();
+    let config = format!(
+        "[config]\n\
+         host = \"127.0.0.1\"\n\
+         port = {port}\n\
+         api_key = \"{API_KEY}\"\n\
+         keys = {keys}\n\
+         upstream_url = \"{upstream}\"\n\
+         {extra}\n",
+        upstream = upstream.url(),
+    );
+    std::fs::write(dir.join("config.toml"), config).unwrap();
+    (dir, port)
+}
+
+/// A `gemini-juggler` command reading the config in `dir`.
+fn command(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gemini-juggler"));
+    command
+        .arg("--config")
+        .arg(dir.join("config.toml"))
+        .arg("--verbosity")
+        .arg("warn");
+    command
+}
+
+/// `(event, data)` of every event in an SSE body that names its events.
+pub fn events(body: &str) -> Vec<(String, Value)> {
+    body.split("\n\n")
+        .filter(|event| !event.trim().is_empty())
+        .map(|event| {
+            let mut lines = event.lines();
+            let name = lines.next().unwrap().strip_prefix("event: ").unwrap();
+            let data = lines.next().unwrap().strip_prefix("data: ").unwrap();
+            (name.to_string(), serde_json::from_str(data).unwrap())
+        })
+        .collect()
+}
+
+/// Answers with `body` as it is.
+pub fn raw(body: Value) -> Behavior {
+    Behavior::Raw {
+        status: 200,
+        body: body.to_string(),
+    }
+}
+
+pub fn client() -> Client {
+    Client::builder().timeout(Duration::from_secs(30)).finish()
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+const EMBEDDING_MODEL: &str = "gemini-embedding-001";
+
+async fn call(juggler: &Juggler, model: &str, method: &str, body: Value) -> (u16, Value) {
+    let mut resp = client()
+        .post(juggler.url(&format!("/v1beta/models/{model}:{method}?key={API_KEY}")))
+        .send_json(&body)
+        .await
+        .unwrap();
+    let body = resp.body().await.unwrap();
+    (
+        resp.status().as_u16(),
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+fn content() -> Value {
+    json!({"parts": [{"text": "hi"}]})
+}
+
+fn minute_quota() -> Behavior {
+    Behavior::MinuteQuota {
+        retry_delay: Duration::from_secs(30),
+    }
+}
+
+#[actix_web::test]
+async fn forwards_token_counting_and_embeddings() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = call(
+        &juggler,
+        MODEL,
+        "countTokens",
+        json!({"contents": [content()]}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["totalTokens"], 4);
+
+    let (status, body) = call(
+        &juggler,
+        EMBEDDING_MODEL,
+        "embedContent",
+        json!({"content": content()}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["embedding"]["values"].as_array().unwrap().len(), 3);
+
+    let request = json!({"model": format!("models/{EMBEDDING_MODEL}"), "content": content()});
+    let (status, body) = call(
+        &juggler,
+        EMBEDDING_MODEL,
+        "batchEmbedContents",
+        json!({"requests": [request, request]}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["embeddings"].as_array().unwrap().len(), 2);
+    assert_eq!(mock.hits("key-a"), 3);
+}
+
+#[actix_web::test]
+async fn rotates_keys_for_embeddings() {
+    let mock = MockUpstream::start().await;
+    mock.script("key-a", [minute_quota()]);
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    let body = json!({"content": content()});
+    assert_eq!(
+        call(&juggler, EMBEDDING_MODEL, "embedContent", body.clone())
+            .await
+            .0,
+        200
+    );
+    assert_eq!(
+        call(&juggler, EMBEDDING_MODEL, "embedContent", body)
+            .await
+            .0,
+        200
+    );
+    assert_eq!(mock.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-b"), 2);
+}
+
+#[actix_web::test]
+async fn tracks_method_quotas_apart_from_generation() {
+    let mock = MockUpstream::start().await;
+    mock.script("key-a", [minute_quota()]);
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, _) = call(
+        &juggler,
+        MODEL,
+        "countTokens",
+        json!({"contents": [content()]}),
+    )
+    .await;
+    assert_eq!(status, 429);
+    // Running out of token counting quota leaves generation alone.
+    assert_eq!(juggler.generate(MODEL).await.0, 200);
+
+    let status = juggler.status().await;
+    let ratelimited = &status["keys"][0]["ratelimited_models"];
+    assert!(ratelimited[format!("{MODEL}:countTokens")].is_number());
+    assert!(ratelimited[MODEL].is_null());
+}
+
+#[actix_web::test]
+async fn follows_the_generate_content_override() {
+    let mock = MockUpstream::start().await;
+    let routed = MockUpstream::start().await;
+    let juggler = Juggler::start_with(
+        &mock,
+        &["key-a"],
+        &format!(
+            "upstream_overrides = {{ generate_content = \"{}\" }}",
+            routed.url()
+        ),
+    );
+
+    let (status, _) = call(
+        &juggler,
+        MODEL,
+        "countTokens",
+        json!({"contents": [content()]}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, _) = call(
+        &juggler,
+        EMBEDDING_MODEL,
+        "embedContent",
+        json!({"content": content()}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(routed.hits("key-a"), 2);
+    assert_eq!(mock.hits("key-a"), 0);
+}
@@ -0,0 +1,203 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+
+/// Keys are tried in order, so the first one is the one that breaks.
+const FILL_FIRST: &str = "strategy = \"fill-first\"";
+
+fn events(body: &str) -> Vec<Value> {
+    body.lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect()
+}
+
+async fn stream_gemini(juggler: &Juggler) -> Vec<Value> {
+    let mut resp = client()
+        .post(juggler.url(&format!(
+            "/v1beta/models/{MODEL}:streamGenerateContent?key={API_KEY}&alt=sse"
+        )))
+        .send_json(&json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]}))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body = resp.body().await.unwrap();
+    events(std::str::from_utf8(&body).unwrap())
+}
+
+fn gemini_texts(events: &[Value]) -> Vec<&str> {
+    events
+        .iter()
+        .filter_map(|event| {
+            event
+                .pointer("/candidates/0/content/parts/0/text")?
+                .as_str()
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn retries_streams_that_break_before_sending_anything() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::BrokenStream {
+            after: 0,
+            in_band: false,
+        },
+    );
+    let juggler = Juggler::start_with(&mock, &["key-a", "key-b"], FILL_FIRST);
+
+    let events = stream_gemini(&juggler).await;
+    assert_eq!(gemini_texts(&events), ["chunk 0 ", "chunk 1 ", "chunk 2 "]);
+    assert!(events.iter().all(|event| event.get("error").is_none()));
+    assert_eq!(mock.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-b"), 1);
+}
+
+#[actix_web::test]
+async fn waits_for_keys_the_stream_has_not_failed_on() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::BrokenStream {
+            after: 0,
+            in_band: false,
+        },
+    );
+    mock.script(
+        "key-b",
+        [Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(1),
+        }],
+    );
+    let juggler = Juggler::start_with(
+        &mock,
+        &["key-a", "key-b"],
+        &format!("{FILL_FIRST}\n[config.queue]\nmax_wait = \"5s\""),
+    );
+
+    // key-a is free again right away, but the stream already failed on it,
+    // so it waits for key-b to come back instead.
+    let events = stream_gemini(&juggler).await;
+    assert_eq!(gemini_texts(&events), ["chunk 0 ", "chunk 1 ", "chunk 2 "]);
+    assert_eq!(mock.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-b"), 2);
+}
+
+#[actix_web::test]
+async fn in_band_quota_errors_ratelimit_the_key() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::BrokenStream {
+            after: 0,
+            in_band: true,
+        },
+    );
+    let juggler = Juggler::start_with(&mock, &["key-a", "key-b"], FILL_FIRST);
+
+    let events = stream_gemini(&juggler).await;
+    assert_eq!(gemini_texts(&events), ["chunk 0 ", "chunk 1 ", "chunk 2 "]);
+
+    let status = juggler.status().await;
+    assert_eq!(status["models"][MODEL]["ratelimited_keys"], 1);
+
+    // The ratelimited key sits out the next request.
+    stream_gemini(&juggler).await;
+    assert_eq!(mock.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-b"), 2);
+}
+
+#[actix_web::test]
+async fn ends_partial_streams_with_an_error_event() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::BrokenStream {
+            after: 1,
+            in_band: false,
+        },
+    );
+    let juggler = Juggler::start_with(&mock, &["key-a", "key-b"], FILL_FIRST);
+
+    let events = stream_gemini(&juggler).await;
+    assert_eq!(gemini_texts(&events), ["chunk 0 "]);
+    let error = &events.last().unwrap()["error"];
+    assert_eq!(error["code"], 502);
+    assert_eq!(error["status"], "INTERNAL");
+    assert_eq!(mock.hits("key-b"), 0);
+}
+
+#[actix_web::test]
+async fn resumes_partial_streams_on_another_key() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::BrokenStream {
+            after: 1,
+            in_band: false,
+        },
+    );
+    let juggler = Juggler::start_with(
+        &mock,
+        &["key-a", "key-b"],
+        &format!("{FILL_FIRST}\n[config.streaming]\nresume = true"),
+    );
+
+    let events = stream_gemini(&juggler).await;
+    assert_eq!(
+        gemini_texts(&events),
+        ["chunk 0 ", "chunk 0 ", "chunk 1 ", "chunk 2 "]
+    );
+    assert!(events.iter().all(|event| event.get("error").is_none()));
+
+    // The next key is asked to carry on from what was already sent.
+    let body = mock.last_body("key-b").unwrap();
+    assert_eq!(
+        body["contents"][1],
+        json!({"role": "model", "parts": [{"text": "chunk 0 "}]})
+    );
+}
+
+#[actix_web::test]
+async fn openai_streams_fail_over_and_end_in_their_dialect() {
+    let mock = MockUpstream::start().await;
+    mock.script(
+        "key-a",
+        [
+            Behavior::BrokenStream {
+                after: 0,
+                in_band: true,
+            },
+            Behavior::BrokenStream {
+                after: 2,
+                in_band: false,
+            },
+        ],
+    );
+    let juggler = Juggler::start_with(&mock, &["key-a", "key-b"], FILL_FIRST);
+
+    let (status, body) = juggler.chat(MODEL, true).await;
+    assert_eq!(status, 200);
+    assert!(body.contains("data: [DONE]"));
+    assert!(!body.contains("\"error\""));
+    assert_eq!(mock.hits("key-b"), 1);
+
+    // A fresh instance has nowhere to go once key-a breaks mid-stream.
+    let juggler = Juggler::start_with(&mock, &["key-a"], FILL_FIRST);
+    let (status, body) = juggler.chat(MODEL, true).await;
+    assert_eq!(status, 200);
+    let events = events(&body);
+    assert_eq!(events.len(), 3);
+    let error = &events[2]["error"];
+    assert_eq!(error["type"], "upstream_error");
+    assert_eq!(error["code"], 502);
+    assert!(!body.contains("data: [DONE]"));
+}
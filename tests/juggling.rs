@@ -0,0 +1,272 @@
+mod common;
+
+use std::time::Duration;
+
+use chrono::Utc;
+use common::{Behavior, Juggler, MockUpstream};
+
+const MODEL: &str = "gemini-2.5-flash";
+
+#[actix_web::test]
+async fn rotates_away_from_daily_ratelimited_key() {
+    let mock = MockUpstream::start().await;
+    mock.set("key-a", Behavior::DailyQuota);
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    for _ in 0..3 {
+        let (status, body) = juggler.generate(MODEL).await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body["candidates"][0]["content"]["parts"][0]["text"],
+            "hello from key-b"
+        );
+    }
+
+    assert!(mock.hits("key-a") <= 1, "ratelimited key kept being used");
+    assert_eq!(mock.hits("key-b"), 3);
+
+    let status = juggler.status().await;
+    assert_eq!(status["total_keys"], 2);
+    assert_eq!(status["ratelimited_keys"], mock.hits("key-a"));
+}
+
+#[actix_web::test]
+async fn daily_quota_lasts_until_reset_boundary() {
+    let mock = MockUpstream::start().await;
+    mock.set("key-a", Behavior::DailyQuota);
+    let juggler = Juggler::start_with(
+        &mock,
+        &["key-a"],
+        "[config.cooldowns]\ndaily_reset_timezone = \"Asia/Tokyo\"",
+    );
+
+    let (status, _) = juggler.generate(MODEL).await;
+    assert_eq!(status, 429);
+
+    let now = Utc::now().with_timezone(&chrono_tz::Asia::Tokyo);
+    let midnight = now
+        .date_naive()
+        .succ_opt()
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_local_timezone(chrono_tz::Asia::Tokyo)
+        .unwrap();
+    let expected = (midnight - now).num_seconds();
+
+    let status = juggler.status().await;
+    let remaining = status["keys"][0]["ratelimited_models"][MODEL]
+        .as_i64()
+        .unwrap();
+    assert!(
+        (expected - remaining).abs() <= 5,
+        "{remaining}s left, expected {expected}s"
+    );
+}
+
+#[actix_web::test]
+async fn quota_exhaustion_is_tracked_per_model() {
+    const PRO: &str = "gemini-2.5-pro";
+
+    let mock = MockUpstream::start().await;
+    mock.set_for_model("key-a", PRO, Behavior::DailyQuota);
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, _) = juggler.generate(PRO).await;
+    assert_eq!(status, 429);
+
+    // The key is spent on pro, flash is a separate quota.
+    let (status, body) = juggler.generate(MODEL).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body["candidates"][0]["content"]["parts"][0]["text"],
+        "hello from key-a"
+    );
+
+    let status = juggler.status().await;
+    assert_eq!(status["models"][PRO]["active_keys"], 0);
+    assert_eq!(status["models"][PRO]["ratelimited_keys"], 1);
+    assert!(
+        status["models"][PRO]["seconds_until_available"]
+            .as_i64()
+            .unwrap()
+            > 0
+    );
+    assert_eq!(status["models"][MODEL]["active_keys"], 1);
+    assert!(status["keys"][0]["ratelimited_models"].get(MODEL).is_none());
+}
+
+#[actix_web::test]
+async fn fails_once_every_key_is_ratelimited() {
+    let mock = MockUpstream::start().await;
+    mock.set("key-a", Behavior::DailyQuota);
+    mock.set("key-b", Behavior::DailyQuota);
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    let (status, _) = juggler.generate(MODEL).await;
+    assert_eq!(status, 429);
+    assert_eq!(mock.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-b"), 1);
+
+    // Nothing left to try, the upstream isn't bothered again.
+    let (status, _) = juggler.generate(MODEL).await;
+    assert_eq!(status, 429);
+    assert_eq!(mock.total_hits(), 2);
+}
+
+#[actix_web::test]
+async fn removes_invalid_key_from_rotation() {
+    let mock = MockUpstream::start().await;
+    mock.set("key-a", Behavior::InvalidKey);
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    for _ in 0..3 {
+        let (status, _) = juggler.generate(MODEL).await;
+        assert_eq!(status, 200);
+    }
+
+    assert!(mock.hits("key-a") <= 1);
+    assert_eq!(juggler.status().await["total_keys"], 2 - mock.hits("key-a"));
+}
+
+#[actix_web::test]
+async fn openai_removes_invalid_key_from_rotation() {
+    let mock = MockUpstream::start().await;
+    mock.set("key-a", Behavior::InvalidKey);
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, _) = juggler.chat(MODEL, false).await;
+    assert_eq!(status, 429);
+    assert_eq!(juggler.status().await["total_keys"], 0);
+}
+
+#[actix_web::test]
+async fn keeps_key_on_errors_that_merely_mention_quotas() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::Raw {
+            status: 400,
+            body: r#"{"error":{"code":400,"message":"Invalid value at 'day' (TYPE_INT32), quota","status":"INVALID_ARGUMENT"}}"#
+                .to_string(),
+        },
+    );
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = juggler.generate(MODEL).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["status"], "INVALID_ARGUMENT");
+    assert_eq!(juggler.status().await["active_keys"], 1);
+}
+
+#[actix_web::test]
+async fn propagates_upstream_errors() {
+    let mock = MockUpstream::start().await;
+    mock.set("key-a", Behavior::ServerError);
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = juggler.generate(MODEL).await;
+    assert_eq!(status, 503);
+    assert_eq!(body["error"]["status"], "UNAVAILABLE");
+
+    // Server errors aren't the key's fault, it stays in rotation.
+    assert_eq!(juggler.status().await["active_keys"], 1);
+}
+
+#[actix_web::test]
+async fn per_minute_quota_cools_key_down_for_retry_delay() {
+    let mock = MockUpstream::start().await;
+    mock.script(
+        "key-a",
+        [Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(1),
+        }],
+    );
+    mock.script(
+        "key-b",
+        [Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(1),
+        }],
+    );
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    // Both keys get throttled, the client sees a 429 instead of a raw
+    // upstream error and is told when to come back.
+    let (status, _) = juggler.generate(MODEL).await;
+    assert_eq!(status, 429);
+    let status = juggler.status().await;
+    assert_eq!(status["ratelimited_keys"], 2);
+    for key in status["keys"].as_array().unwrap() {
+        assert!(key["ratelimited_models"][MODEL].as_i64().unwrap() <= 1);
+    }
+
+    actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+
+    let (status, _) = juggler.generate(MODEL).await;
+    assert_eq!(status, 200);
+    assert_eq!(juggler.status().await["ratelimited_keys"], 0);
+}
+
+#[actix_web::test]
+async fn rotates_away_from_per_minute_ratelimited_key() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(20),
+        },
+    );
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    for _ in 0..3 {
+        let (status, _) = juggler.generate(MODEL).await;
+        assert_eq!(status, 200);
+    }
+
+    assert!(mock.hits("key-a") <= 1);
+    if mock.hits("key-a") == 1 {
+        let status = juggler.status().await;
+        let key = status["keys"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|k| k["is_ratelimited"] == true)
+            .unwrap();
+        assert!(key["ratelimited_models"][MODEL].as_i64().unwrap() <= 20);
+    }
+}
+
+#[actix_web::test]
+async fn openai_rotates_away_from_ratelimited_key() {
+    let mock = MockUpstream::start().await;
+    mock.set("key-a", Behavior::DailyQuota);
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    for _ in 0..2 {
+        let (status, body) = juggler.chat(MODEL, false).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("hello from key-b"));
+    }
+
+    assert!(mock.hits("key-a") <= 1);
+}
+
+#[actix_web::test]
+async fn openai_streams_slow_responses_to_completion() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::SlowStream {
+            chunks: 5,
+            delay: Duration::from_millis(50),
+        },
+    );
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = juggler.chat(MODEL, true).await;
+    assert_eq!(status, 200);
+    for i in 0..5 {
+        assert!(body.contains(&format!("chunk {i} ")));
+    }
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+}
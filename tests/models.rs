@@ -0,0 +1,161 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+const ALIASES: &str = "[config.models.aliases]\nfast = \"gemini-2.5-flash\"";
+
+async fn get(juggler: &Juggler, path: &str, key: &str) -> (u16, Value) {
+    let mut resp = client()
+        .get(juggler.url(path))
+        .insert_header(("x-goog-api-key", key))
+        .send()
+        .await
+        .unwrap();
+    let body = resp.body().await.unwrap();
+    (
+        resp.status().as_u16(),
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+fn names<'a>(models: &'a Value, id: &str) -> Vec<&'a str> {
+    models
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|model| model[id].as_str().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn lists_models_with_aliases() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], ALIASES);
+
+    let (status, body) = get(&juggler, "/v1beta/models", API_KEY).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        names(&body["models"], "name"),
+        [
+            "models/gemini-2.5-flash",
+            "models/gemini-2.5-pro",
+            "models/gemini-embedding-001",
+            "models/fast",
+        ]
+    );
+    assert_eq!(body["models"][3]["displayName"], "gemini-2.5-flash");
+
+    let (status, body) = get(&juggler, "/v1beta/openai/models", API_KEY).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["object"], "list");
+    assert_eq!(names(&body["data"], "id").len(), 4);
+
+    let (status, body) = get(&juggler, "/v1beta/models/fast", API_KEY).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["name"], "models/fast");
+    let (status, body) = get(&juggler, "/v1beta/openai/models/gemini-2.5-pro", API_KEY).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["id"], "models/gemini-2.5-pro");
+    let (status, body) = get(&juggler, "/v1beta/models/gemini-0-nope", API_KEY).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["status"], "NOT_FOUND");
+}
+
+#[actix_web::test]
+async fn lists_models_from_the_generate_content_override() {
+    let mock = MockUpstream::start().await;
+    let routed = MockUpstream::start().await;
+    let juggler = Juggler::start_with(
+        &mock,
+        &["key-a"],
+        &format!(
+            "upstream_overrides = {{ generate_content = \"{}\" }}",
+            routed.url()
+        ),
+    );
+
+    let (status, body) = get(&juggler, "/v1beta/models", API_KEY).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["models"][0]["name"], "models/gemini-2.5-flash");
+    assert_eq!(routed.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-a"), 0);
+}
+
+#[actix_web::test]
+async fn caches_listings_and_skips_unhealthy_keys() {
+    let mock = MockUpstream::start().await;
+    mock.script(
+        "key-a",
+        [Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(30),
+        }],
+    );
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    for _ in 0..3 {
+        assert_eq!(get(&juggler, "/v1beta/models", API_KEY).await.0, 200);
+    }
+    assert_eq!(
+        get(&juggler, "/v1beta/models/gemini-2.5-pro", API_KEY)
+            .await
+            .0,
+        200
+    );
+    assert_eq!(mock.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-b"), 1);
+}
+
+#[actix_web::test]
+async fn filters_listings_to_allowed_models() {
+    let mock = MockUpstream::start().await;
+    let extra = format!(
+        "[[config.clients]]\n\
+         name = \"flash-only\"\n\
+         key = \"flash-secret\"\n\
+         policy = {{ models = [\"gemini-*-flash*\"] }}\n\
+         [config.models]\n\
+         filter_by_policy = true\n\
+         {ALIASES}"
+    );
+    let juggler = Juggler::start_with(&mock, &["key-a"], &extra);
+
+    let (_, body) = get(&juggler, "/v1beta/models", "flash-secret").await;
+    assert_eq!(
+        names(&body["models"], "name"),
+        ["models/gemini-2.5-flash", "models/fast"]
+    );
+    assert_eq!(
+        get(&juggler, "/v1beta/models/gemini-2.5-pro", "flash-secret")
+            .await
+            .0,
+        404
+    );
+    let (_, body) = get(&juggler, "/v1beta/models", API_KEY).await;
+    assert_eq!(names(&body["models"], "name").len(), 4);
+}
+
+#[actix_web::test]
+async fn accepts_aliases_in_requests() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], ALIASES);
+
+    assert_eq!(juggler.generate("fast").await.0, 200);
+    let (status, _) = juggler
+        .post(
+            "/v1beta/openai/chat/completions",
+            &json!({"model": "fast", "messages": [{"role": "user", "content": "hi"}]}),
+        )
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        mock.last_body("key-a").unwrap()["model"],
+        "gemini-2.5-flash"
+    );
+
+    let status = juggler.status().await;
+    assert!(status["models"]["gemini-2.5-flash"].is_object());
+    assert!(status["models"]["fast"].is_null());
+}
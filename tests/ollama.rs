@@ -0,0 +1,270 @@
+mod common;
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+const KEYLESS: &str = "ollama = { client = \"default\" }";
+
+async fn post(juggler: &Juggler, path: &str, body: &Value) -> (u16, String) {
+    let mut resp = client()
+        .post(juggler.url(path))
+        .send_json(body)
+        .await
+        .unwrap();
+    let body = resp.body().limit(1 << 20).await.unwrap();
+    (
+        resp.status().as_u16(),
+        String::from_utf8_lossy(&body).into_owned(),
+    )
+}
+
+async fn call(juggler: &Juggler, path: &str, body: &Value) -> (u16, Value) {
+    let (status, body) = post(juggler, path, body).await;
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+fn ndjson(body: &str) -> Vec<Value> {
+    body.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn translates_chats() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], KEYLESS);
+
+    let (status, body) = call(
+        &juggler,
+        "/api/chat",
+        &json!({
+            "model": format!("{MODEL}:latest"),
+            "stream": false,
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "What's this?", "images": ["iVBORw0K"]},
+            ],
+            "options": {"temperature": 0.2, "num_predict": 128, "num_ctx": 8192},
+            "format": "json",
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["model"], MODEL);
+    assert_eq!(
+        body["message"],
+        json!({"role": "assistant", "content": "hello from key-a"})
+    );
+    assert_eq!(body["done"], true);
+    assert_eq!(body["done_reason"], "stop");
+    assert_eq!(body["prompt_eval_count"], 4);
+    assert_eq!(body["eval_count"], 3);
+
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["systemInstruction"],
+        json!({"parts": [{"text": "Be brief."}]})
+    );
+    assert_eq!(
+        sent["contents"],
+        json!([{"role": "user", "parts": [
+            {"text": "What's this?"},
+            {"inlineData": {"mimeType": "image/png", "data": "iVBORw0K"}},
+        ]}])
+    );
+    assert_eq!(
+        sent["generationConfig"],
+        json!({"temperature": 0.2, "maxOutputTokens": 128, "responseMimeType": "application/json"})
+    );
+}
+
+#[actix_web::test]
+async fn streams_ndjson_by_default() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], KEYLESS);
+
+    let (status, body) = post(
+        &juggler,
+        "/api/chat",
+        &json!({"model": MODEL, "messages": [{"role": "user", "content": "hi"}]}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let events = ndjson(&body);
+    assert_eq!(events.len(), 4);
+    for (i, line) in events[..3].iter().enumerate() {
+        assert_eq!(line["done"], false);
+        assert_eq!(line["message"]["content"], format!("chunk {i} "));
+    }
+    assert_eq!(events[3]["done"], true);
+    assert_eq!(events[3]["message"]["content"], "");
+    assert_eq!(events[3]["eval_count"], 3);
+
+    let (status, body) = post(
+        &juggler,
+        "/api/generate",
+        &json!({"model": MODEL, "prompt": "hi", "system": "Be brief."}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let events = ndjson(&body);
+    let text: String = events
+        .iter()
+        .filter_map(|line| line["response"].as_str())
+        .collect();
+    assert_eq!(text, "chunk 0 chunk 1 chunk 2 ");
+    assert_eq!(events.last().unwrap()["done_reason"], "stop");
+    assert_eq!(events.last().unwrap()["context"], json!([]));
+
+    let (status, body) = call(
+        &juggler,
+        "/api/generate",
+        &json!({"model": MODEL, "prompt": "hi", "stream": false}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["response"], "hello from key-a");
+    assert_eq!(body["done"], true);
+}
+
+#[actix_web::test]
+async fn translates_tool_calls() {
+    let mock = MockUpstream::start().await;
+    let answer = json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [
+                {"functionCall": {"name": "lookup", "args": {"city": "Lisbon"}}},
+            ]},
+            "finishReason": "STOP",
+        }],
+    });
+    mock.script(
+        "key-a",
+        [Behavior::Raw {
+            status: 200,
+            body: answer.to_string(),
+        }],
+    );
+    let juggler = Juggler::start_with(&mock, &["key-a"], KEYLESS);
+
+    let tools = json!([{"type": "function", "function": {
+        "name": "lookup",
+        "description": "Looks a city up",
+        "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+    }}]);
+    let (_, body) = call(
+        &juggler,
+        "/api/chat",
+        &json!({
+            "model": MODEL,
+            "stream": false,
+            "tools": tools,
+            "messages": [{"role": "user", "content": "Weather?"}],
+        }),
+    )
+    .await;
+    assert_eq!(
+        body["message"]["tool_calls"],
+        json!([{"function": {"name": "lookup", "arguments": {"city": "Lisbon"}}}])
+    );
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["tools"][0]["functionDeclarations"][0]["name"],
+        "lookup"
+    );
+
+    let (status, _) = call(
+        &juggler,
+        "/api/chat",
+        &json!({
+            "model": MODEL,
+            "stream": false,
+            "tools": tools,
+            "messages": [
+                {"role": "user", "content": "Weather?"},
+                {"role": "assistant", "content": "", "tool_calls": body["message"]["tool_calls"]},
+                {"role": "tool", "content": "Sunny"},
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["contents"][1],
+        json!({"role": "model", "parts": [{"functionCall": {"name": "lookup", "args": {"city": "Lisbon"}}}]})
+    );
+    assert_eq!(
+        sent["contents"][2],
+        json!({"role": "user", "parts": [{"functionResponse": {
+            "name": "lookup",
+            "response": {"content": "Sunny"},
+        }}]})
+    );
+}
+
+#[actix_web::test]
+async fn lists_tags_and_embeds() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], KEYLESS);
+
+    let mut resp = client().get(juggler.url("/api/tags")).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let tags: Value = resp.json().await.unwrap();
+    let names: Vec<&str> = tags["models"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| tag["name"].as_str().unwrap())
+        .collect();
+    assert!(names.contains(&MODEL));
+    assert!(names.iter().all(|name| !name.starts_with("models/")));
+
+    let (status, body) = call(
+        &juggler,
+        "/api/embed",
+        &json!({"model": "gemini-embedding-001", "input": ["a", "b"]}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["model"], "gemini-embedding-001");
+    assert_eq!(body["embeddings"].as_array().unwrap().len(), 2);
+    assert_eq!(body["embeddings"][0], json!([0.5, 0.25, 0.125]));
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["requests"][1],
+        json!({"model": "models/gemini-embedding-001", "content": {"parts": [{"text": "b"}]}})
+    );
+}
+
+#[actix_web::test]
+async fn needs_a_key_unless_configured() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let request =
+        json!({"model": MODEL, "stream": false, "messages": [{"role": "user", "content": "hi"}]});
+    let (status, body) = call(&juggler, "/api/chat", &request).await;
+    assert_eq!(status, 401);
+    assert!(body["error"].as_str().unwrap().contains("Missing API key"));
+
+    let mut resp = client()
+        .post(juggler.url("/api/chat"))
+        .insert_header(("Authorization", format!("Bearer {API_KEY}")))
+        .send_json(&request)
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["message"]["content"], "hello from key-a");
+
+    // Other endpoints keep asking for a key.
+    let keyless = Juggler::start_with(&mock, &["key-a"], KEYLESS);
+    let resp = client()
+        .get(keyless.url(&format!("/v1beta/models/{MODEL}")))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
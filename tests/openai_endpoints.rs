@@ -0,0 +1,110 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+async fn post_json(juggler: &Juggler, path: &str, body: Value) -> (u16, Value) {
+    let (status, body) = juggler.post(&format!("/v1beta/openai/{path}"), &body).await;
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+#[actix_web::test]
+async fn forwards_embeddings_and_images_with_key_rotation() {
+    let mock = MockUpstream::start().await;
+    mock.script(
+        "key-a",
+        [Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(30),
+        }],
+    );
+    let juggler = Juggler::start(&mock, &["key-a", "key-b"]);
+
+    let (status, body) = post_json(
+        &juggler,
+        "embeddings",
+        json!({"model": "gemini-embedding-001", "input": "hi"}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"][0]["embedding"].as_array().unwrap().len(), 3);
+    assert_eq!(mock.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-b"), 1);
+
+    let (status, body) = post_json(
+        &juggler,
+        "images/generations",
+        json!({"model": "imagen-3.0-generate-002", "prompt": "a cat"}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"][0]["b64_json"], "aW1hZ2U=");
+
+    let status = juggler.status().await;
+    assert!(status["models"]["gemini-embedding-001"].is_object());
+}
+
+#[actix_web::test]
+async fn forwards_audio_as_is() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let mut resp = client()
+        .post(juggler.url("/v1beta/openai/audio/speech"))
+        .insert_header(("Authorization", format!("Bearer {API_KEY}")))
+        .send_json(
+            &json!({"model": "gemini-2.5-flash-preview-tts", "input": "hi", "voice": "Kore"}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "audio/mpeg");
+    assert_eq!(
+        resp.body().await.unwrap().as_ref(),
+        [0xff, 0xfb, 0x90, 0x00]
+    );
+
+    let boundary = "juggler-boundary";
+    let form = format!(
+        "--{boundary}\r\n\
+         Content-Disposition: form-data; name=\"model\"\r\n\r\n\
+         gemini-2.5-flash\r\n\
+         --{boundary}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"hi.wav\"\r\n\
+         Content-Type: audio/wav\r\n\r\n\
+         RIFF....WAVE\r\n\
+         --{boundary}--\r\n"
+    );
+    let content_type = format!("multipart/form-data; boundary={boundary}");
+    let mut resp = client()
+        .post(juggler.url("/v1beta/openai/audio/transcriptions"))
+        .insert_header(("Authorization", format!("Bearer {API_KEY}")))
+        .insert_header(("Content-Type", content_type.as_str()))
+        .send_body(form.clone())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["text"], "hello");
+
+    let recorded = mock.last_body("key-a").unwrap();
+    assert_eq!(recorded["content_type"], content_type);
+    assert_eq!(recorded["size"], form.len());
+    // Uploads are juggled under the model named in their form.
+    let status = juggler.status().await;
+    assert!(status["models"]["gemini-2.5-flash"].is_object());
+    // The streamed bodies gave the client's in-flight slots back.
+    assert_eq!(status["clients"][0]["in_flight"], 0);
+}
+
+#[actix_web::test]
+async fn refuses_paths_outside_the_allowlist() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = post_json(&juggler, "files", json!({})).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], 404);
+    assert_eq!(mock.total_hits(), 0);
+}
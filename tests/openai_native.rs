@@ -0,0 +1,294 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Behavior, Juggler, MockUpstream};
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+const NATIVE: &str = "openai = { native = true }";
+const COMPLETIONS: &str = "/v1beta/openai/chat/completions";
+
+async fn complete(juggler: &Juggler, body: &Value) -> (u16, Value) {
+    let (status, body) = juggler.post(COMPLETIONS, body).await;
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+/// The data of every event in an SSE body.
+fn events(body: &str) -> Vec<String> {
+    body.split("\n\n")
+        .filter(|event| !event.trim().is_empty())
+        .map(|event| event.strip_prefix("data: ").unwrap().to_string())
+        .collect()
+}
+
+#[actix_web::test]
+async fn translates_chat_completions() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], NATIVE);
+
+    let (status, body) = complete(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What's this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0K"}},
+                ]},
+            ],
+            "max_completion_tokens": 128,
+            "temperature": 0.2,
+            "stop": "END",
+            "response_format": {"type": "json_schema", "json_schema": {"name": "answer", "schema": {"type": "object"}}},
+            "extra_body": {"google": {
+                "thinking_config": {"thinking_budget": 512, "include_thoughts": true},
+                "safety_settings": [{"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_NONE"}],
+                "cached_content": "cachedContents/abc",
+            }},
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["object"], "chat.completion");
+    assert_eq!(body["model"], MODEL);
+    assert!(body["id"].as_str().unwrap().starts_with("chatcmpl-"));
+    assert_eq!(
+        body["choices"][0]["message"],
+        json!({"role": "assistant", "content": "hello from key-a"})
+    );
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    assert_eq!(body["usage"]["prompt_tokens"], 4);
+    assert_eq!(body["usage"]["completion_tokens"], 3);
+    assert_eq!(body["usage"]["total_tokens"], 7);
+
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["systemInstruction"],
+        json!({"parts": [{"text": "Be brief."}]})
+    );
+    assert_eq!(
+        sent["contents"],
+        json!([{"role": "user", "parts": [
+            {"text": "What's this?"},
+            {"inlineData": {"mimeType": "image/png", "data": "iVBORw0K"}},
+        ]}])
+    );
+    assert_eq!(
+        sent["generationConfig"],
+        json!({
+            "maxOutputTokens": 128,
+            "temperature": 0.2,
+            "stopSequences": ["END"],
+            "responseMimeType": "application/json",
+            "responseJsonSchema": {"type": "object"},
+            "thinkingConfig": {"thinkingBudget": 512, "includeThoughts": true},
+        })
+    );
+    assert_eq!(
+        sent["safetySettings"],
+        json!([{"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_NONE"}])
+    );
+    assert_eq!(sent["cachedContent"], "cachedContents/abc");
+}
+
+#[actix_web::test]
+async fn translates_tool_calls() {
+    let mock = MockUpstream::start().await;
+    let answer = json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [
+                {"text": "Checking.", "thought": true},
+                {"functionCall": {"name": "lookup", "args": {"city": "Lisbon"}}, "thoughtSignature": "c2ln"},
+            ]},
+            "finishReason": "STOP",
+        }],
+        "usageMetadata": {"promptTokenCount": 20, "candidatesTokenCount": 5, "thoughtsTokenCount": 2, "totalTokenCount": 27},
+    });
+    mock.script(
+        "key-a",
+        [Behavior::Raw {
+            status: 200,
+            body: answer.to_string(),
+        }],
+    );
+    let juggler = Juggler::start_with(&mock, &["key-a"], NATIVE);
+
+    let tools = json!([{"type": "function", "function": {
+        "name": "lookup",
+        "description": "Looks a city up",
+        "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+    }}]);
+    let (status, body) = complete(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "messages": [{"role": "user", "content": "Weather?"}],
+            "tools": tools,
+            "tool_choice": {"type": "function", "function": {"name": "lookup"}},
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let sent = mock.last_body("key-a").unwrap();
+    let declaration = &sent["tools"][0]["functionDeclarations"][0];
+    assert_eq!(declaration["name"], "lookup");
+    assert_eq!(declaration["parametersJsonSchema"]["type"], "object");
+    assert_eq!(
+        sent["toolConfig"]["functionCallingConfig"],
+        json!({"mode": "ANY", "allowedFunctionNames": ["lookup"]})
+    );
+
+    let choice = &body["choices"][0];
+    assert_eq!(choice["finish_reason"], "tool_calls");
+    assert_eq!(choice["message"]["content"], Value::Null);
+    assert_eq!(choice["message"]["reasoning_content"], "Checking.");
+    let call = &choice["message"]["tool_calls"][0];
+    assert_eq!(call["type"], "function");
+    assert_eq!(call["function"]["name"], "lookup");
+    let arguments: Value =
+        serde_json::from_str(call["function"]["arguments"].as_str().unwrap()).unwrap();
+    assert_eq!(arguments, json!({"city": "Lisbon"}));
+    assert_eq!(call["extra_content"]["google"]["thought_signature"], "c2ln");
+    assert_eq!(body["usage"]["completion_tokens"], 7);
+    assert_eq!(
+        body["usage"]["completion_tokens_details"]["reasoning_tokens"],
+        2
+    );
+    let call_id = call["id"].as_str().unwrap();
+
+    let (status, _) = complete(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "tools": tools,
+            "messages": [
+                {"role": "user", "content": "Weather?"},
+                {"role": "assistant", "content": null, "tool_calls": [call]},
+                {"role": "tool", "tool_call_id": call_id, "content": "Sunny"},
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["contents"][1],
+        json!({"role": "model", "parts": [{
+            "functionCall": {"id": call_id, "name": "lookup", "args": {"city": "Lisbon"}},
+            "thoughtSignature": "c2ln",
+        }]})
+    );
+    assert_eq!(
+        sent["contents"][2],
+        json!({"role": "user", "parts": [{"functionResponse": {
+            "id": call_id,
+            "name": "lookup",
+            "response": {"content": "Sunny"},
+        }}]})
+    );
+}
+
+#[actix_web::test]
+async fn streams_chunks() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a"], NATIVE);
+
+    let (status, body) = juggler
+        .post(
+            COMPLETIONS,
+            &json!({
+                "model": MODEL,
+                "stream": true,
+                "stream_options": {"include_usage": true},
+                "messages": [{"role": "user", "content": "hi"}],
+            }),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let events = events(&body);
+    assert_eq!(events.last().unwrap(), "[DONE]");
+    let chunks: Vec<Value> = events[..events.len() - 1]
+        .iter()
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect();
+    assert!(
+        chunks
+            .iter()
+            .all(|chunk| chunk["object"] == "chat.completion.chunk"
+                && chunk["id"] == chunks[0]["id"])
+    );
+    assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+    let text: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(text, "chunk 0 chunk 1 chunk 2 ");
+    let finished: Vec<&Value> = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["finish_reason"].as_str().map(|_| chunk))
+        .collect();
+    assert_eq!(finished.len(), 1);
+    assert_eq!(finished[0]["choices"][0]["finish_reason"], "stop");
+    let usage = chunks.last().unwrap();
+    assert_eq!(usage["choices"], json!([]));
+    assert_eq!(usage["usage"]["total_tokens"], 7);
+
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["contents"],
+        json!([{"role": "user", "parts": [{"text": "hi"}]}])
+    );
+}
+
+#[actix_web::test]
+async fn translates_only_configured_models() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(
+        &mock,
+        &["key-a"],
+        "openai = { native_models = [\"gemini-2.5-*\"] }",
+    );
+
+    let messages = json!([{"role": "user", "content": "hi"}]);
+    let (status, _) = complete(&juggler, &json!({"model": MODEL, "messages": messages})).await;
+    assert_eq!(status, 200);
+    assert!(mock.last_body("key-a").unwrap().get("contents").is_some());
+
+    let (status, _) = complete(
+        &juggler,
+        &json!({"model": "gemini-2.0-flash", "messages": messages}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(mock.last_body("key-a").unwrap()["messages"], messages);
+}
+
+#[actix_web::test]
+async fn answers_errors_in_the_openai_format() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(30),
+        },
+    );
+    let juggler = Juggler::start_with(&mock, &["key-a"], NATIVE);
+
+    let messages = json!([{"role": "user", "content": "hi"}]);
+    let (status, body) = complete(&juggler, &json!({"model": MODEL, "messages": messages})).await;
+    assert_eq!(status, 429);
+    assert_eq!(body["error"]["type"], "rate_limit_error");
+
+    let (status, body) = complete(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "messages": [{"role": "tool", "tool_call_id": "call_1", "content": "Sunny"}],
+        }),
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+}
@@ -0,0 +1,287 @@
+mod common;
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client};
+use serde_json::{Value, json};
+
+const MODEL: &str = "llama-3.1-8b";
+
+/// A juggler that sends `llama-*` models to the mock's OpenAI-compatible
+/// endpoint, as a provider with a key of its own.
+fn start(mock: &MockUpstream) -> Juggler {
+    Juggler::start_with(
+        mock,
+        &["key-a"],
+        &format!(
+            "providers = [{{ name = \"local\", url = \"{}/v1beta/openai\", key = \"local-key\", models = [\"llama-*\"] }}]",
+            mock.url()
+        ),
+    )
+}
+
+async fn generate(juggler: &Juggler, model: &str, body: &Value) -> (u16, Value) {
+    let mut resp = client()
+        .post(juggler.url(&format!(
+            "/v1beta/models/{model}:generateContent?key={API_KEY}"
+        )))
+        .send_json(body)
+        .await
+        .unwrap();
+    let body = resp.body().await.unwrap();
+    (
+        resp.status().as_u16(),
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+#[actix_web::test]
+async fn routes_models_to_providers() {
+    let mock = MockUpstream::start().await;
+    let juggler = start(&mock);
+
+    let (status, body) = generate(
+        &juggler,
+        MODEL,
+        &json!({
+            "systemInstruction": {"parts": [{"text": "Be brief."}]},
+            "contents": [{"role": "user", "parts": [{"text": "hi"}]}],
+            "generationConfig": {
+                "maxOutputTokens": 128,
+                "temperature": 0.2,
+                "stopSequences": ["END"],
+                "responseMimeType": "application/json",
+                "responseSchema": {"type": "OBJECT", "properties": {"a": {"type": "STRING"}}},
+            },
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body["candidates"][0]["content"],
+        json!({"role": "model", "parts": [{"text": "hello from local-key"}]})
+    );
+    assert_eq!(body["candidates"][0]["finishReason"], "STOP");
+    assert_eq!(
+        body["usageMetadata"],
+        json!({"promptTokenCount": 4, "candidatesTokenCount": 3, "totalTokenCount": 7})
+    );
+
+    let sent = mock.last_body("local-key").unwrap();
+    assert_eq!(
+        sent,
+        json!({
+            "model": MODEL,
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "hi"},
+            ],
+            "max_tokens": 128,
+            "temperature": 0.2,
+            "stop": ["END"],
+            "response_format": {"type": "json_schema", "json_schema": {
+                "name": "response",
+                "schema": {"type": "object", "properties": {"a": {"type": "string"}}},
+            }},
+        })
+    );
+    assert_eq!(mock.hits("key-a"), 0);
+
+    // Other models still go to Gemini.
+    let (status, body) = generate(
+        &juggler,
+        "gemini-2.5-flash",
+        &json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body["candidates"][0]["content"]["parts"][0]["text"],
+        "hello from key-a"
+    );
+
+    // So do the APIs translated into native requests.
+    let mut resp = client()
+        .post(juggler.url("/v1/messages"))
+        .insert_header(("x-api-key", API_KEY))
+        .send_json(&json!({
+            "model": MODEL,
+            "max_tokens": 64,
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let message: Value = resp.json().await.unwrap();
+    assert_eq!(message["content"][0]["text"], "hello from local-key");
+}
+
+#[actix_web::test]
+async fn streams_from_providers() {
+    let mock = MockUpstream::start().await;
+    let juggler = start(&mock);
+    let request = json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]});
+
+    let mut resp = client()
+        .post(juggler.url(&format!(
+            "/v1beta/models/{MODEL}:streamGenerateContent?key={API_KEY}&alt=sse"
+        )))
+        .send_json(&request)
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body = resp.body().await.unwrap();
+    let events: Vec<Value> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+    let texts: Vec<&str> = events
+        .iter()
+        .filter_map(|event| {
+            event
+                .pointer("/candidates/0/content/parts/0/text")?
+                .as_str()
+        })
+        .collect();
+    assert_eq!(texts, ["chunk 0 ", "chunk 1 ", "chunk 2 "]);
+    let last = events.last().unwrap();
+    assert_eq!(last["candidates"][0]["finishReason"], "STOP");
+    assert_eq!(last["usageMetadata"]["totalTokenCount"], 7);
+
+    let sent = mock.last_body("local-key").unwrap();
+    assert_eq!(sent["stream"], true);
+    assert_eq!(sent["stream_options"], json!({"include_usage": true}));
+
+    // Without `alt=sse`, events come as a JSON array.
+    let mut resp = client()
+        .post(juggler.url(&format!(
+            "/v1beta/models/{MODEL}:streamGenerateContent?key={API_KEY}"
+        )))
+        .send_json(&request)
+        .await
+        .unwrap();
+    let events: Value = resp.json().await.unwrap();
+    assert_eq!(events.as_array().unwrap().len(), 3);
+}
+
+#[actix_web::test]
+async fn translates_function_calls() {
+    let mock = MockUpstream::start().await;
+    let completion = json!({
+        "id": "chatcmpl-1",
+        "model": MODEL,
+        "choices": [{
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": null,
+                "reasoning_content": "Checking.",
+                "tool_calls": [{"id": "call_abc", "type": "function", "function": {
+                    "name": "lookup",
+                    "arguments": "{\"city\":\"Lisbon\"}",
+                }}],
+            },
+            "finish_reason": "tool_calls",
+        }],
+    });
+    mock.script(
+        "local-key",
+        [Behavior::Raw {
+            status: 200,
+            body: completion.to_string(),
+        }],
+    );
+    let juggler = start(&mock);
+
+    let tools = json!([{"functionDeclarations": [{
+        "name": "lookup",
+        "description": "Looks a city up",
+        "parameters": {"type": "OBJECT", "properties": {"city": {"type": "STRING"}}},
+    }]}]);
+    let (status, body) = generate(
+        &juggler,
+        MODEL,
+        &json!({
+            "contents": [{"role": "user", "parts": [{"text": "Weather?"}]}],
+            "tools": tools,
+            "toolConfig": {"functionCallingConfig": {"mode": "ANY"}},
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body["candidates"][0]["content"]["parts"],
+        json!([
+            {"text": "Checking.", "thought": true},
+            {"functionCall": {"id": "call_abc", "name": "lookup", "args": {"city": "Lisbon"}}},
+        ])
+    );
+    let sent = mock.last_body("local-key").unwrap();
+    assert_eq!(
+        sent["tools"],
+        json!([{"type": "function", "function": {
+            "name": "lookup",
+            "description": "Looks a city up",
+            "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+        }}])
+    );
+    assert_eq!(sent["tool_choice"], "required");
+
+    // Calls and responses without ids are matched by name.
+    let (status, _) = generate(
+        &juggler,
+        MODEL,
+        &json!({
+            "contents": [
+                {"role": "user", "parts": [{"text": "Weather?"}]},
+                {"role": "model", "parts": [{"functionCall": {"name": "lookup", "args": {"city": "Lisbon"}}}]},
+                {"role": "user", "parts": [{"functionResponse": {"name": "lookup", "response": {"content": "Sunny"}}}]},
+            ],
+            "tools": tools,
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let sent = mock.last_body("local-key").unwrap();
+    assert_eq!(
+        sent["messages"],
+        json!([
+            {"role": "user", "content": "Weather?"},
+            {"role": "assistant", "content": "", "tool_calls": [{
+                "id": "call_0",
+                "type": "function",
+                "function": {"name": "lookup", "arguments": "{\"city\":\"Lisbon\"}"},
+            }]},
+            {"role": "tool", "tool_call_id": "call_0", "content": "Sunny"},
+        ])
+    );
+}
+
+#[actix_web::test]
+async fn answers_provider_errors_in_the_gemini_format() {
+    let mock = MockUpstream::start().await;
+    mock.script(
+        "local-key",
+        [Behavior::Raw {
+            status: 400,
+            body: json!({"error": {"message": "context too long", "type": "BadRequestError", "code": 400}})
+                .to_string(),
+        }],
+    );
+    let juggler = start(&mock);
+    let request = json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]});
+
+    let (status, body) = generate(&juggler, MODEL, &request).await;
+    assert_eq!(status, 400);
+    assert_eq!(
+        body["error"],
+        json!({"code": 400, "message": "context too long", "status": "INVALID_ARGUMENT"})
+    );
+
+    let mut grounded = request.clone();
+    grounded["tools"] = json!([{"googleSearch": {}}]);
+    let (status, body) = generate(&juggler, MODEL, &grounded).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["status"], "INVALID_ARGUMENT");
+    assert_eq!(mock.hits("local-key"), 1);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client};
+use futures_util::future::join_all;
+use serde_json::json;
+
+const MODEL: &str = "gemini-2.5-flash";
+
+fn queue(max_wait: &str, max_depth: usize) -> String {
+    format!("[config.queue]\nmax_wait = \"{max_wait}\"\nmax_depth = {max_depth}")
+}
+
+fn minute_quota(seconds: u64) -> Behavior {
+    Behavior::MinuteQuota {
+        retry_delay: Duration::from_secs(seconds),
+    }
+}
+
+#[actix_web::test]
+async fn waits_for_a_key_to_come_back() {
+    let mock = MockUpstream::start().await;
+    mock.script("key-a", [minute_quota(1)]);
+    let juggler = Juggler::start_with(&mock, &["key-a"], &queue("5s", 10));
+
+    let started = Instant::now();
+    assert_eq!(juggler.generate(MODEL).await.0, 200);
+    assert!(started.elapsed() >= Duration::from_millis(900));
+    assert_eq!(mock.hits("key-a"), 2);
+}
+
+#[actix_web::test]
+async fn fails_right_away_when_no_key_is_back_in_time() {
+    let mock = MockUpstream::start().await;
+    mock.script("key-a", [minute_quota(30)]);
+    let juggler = Juggler::start_with(&mock, &["key-a"], &queue("2s", 10));
+
+    let started = Instant::now();
+    assert_eq!(juggler.generate(MODEL).await.0, 429);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[actix_web::test]
+async fn turns_requests_away_when_the_queue_is_full() {
+    let mock = MockUpstream::start().await;
+    mock.script("key-a", [minute_quota(2)]);
+    let juggler = Juggler::start_with(&mock, &["key-a"], &queue("5s", 1));
+
+    let waiting = juggler.generate(MODEL);
+    let turned_away = async {
+        actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+        client()
+            .post(juggler.url(&format!(
+                "/v1beta/models/{MODEL}:generateContent?key={API_KEY}"
+            )))
+            .send_json(&json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]}))
+            .await
+            .unwrap()
+    };
+
+    let ((status, _), resp) = futures_util::join!(waiting, turned_away);
+    assert_eq!(status, 200);
+    assert_eq!(resp.status(), 503);
+    let retry_after: i64 = resp
+        .headers()
+        .get("Retry-After")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=2).contains(&retry_after));
+}
+
+#[actix_web::test]
+async fn serves_waiting_requests_in_arrival_order() {
+    let mock = MockUpstream::start().await;
+    mock.script("key-a", [minute_quota(1)]);
+    mock.set_serial_per_key(true);
+    mock.set_latency(Duration::from_millis(50));
+    let juggler = Juggler::start_with(&mock, &["key-a"], &queue("5s", 10));
+
+    let done = Arc::new(Mutex::new(Vec::new()));
+    let requests = (0..4).map(|i| {
+        let (juggler, done) = (&juggler, done.clone());
+        async move {
+            actix_web::rt::time::sleep(Duration::from_millis(150 * i)).await;
+            assert_eq!(juggler.generate(MODEL).await.0, 200);
+            done.lock().unwrap().push(i);
+        }
+    });
+    join_all(requests).await;
+
+    assert_eq!(*done.lock().unwrap(), [0, 1, 2, 3]);
+}
+
+#[actix_web::test]
+async fn keeps_a_retried_request_at_the_front() {
+    let mock = MockUpstream::start().await;
+    // The request at the front is ratelimited again when its key comes back.
+    mock.script("key-a", [minute_quota(1), minute_quota(1)]);
+    let juggler = Juggler::start_with(&mock, &["key-a"], &queue("5s", 10));
+
+    let done = Arc::new(Mutex::new(Vec::new()));
+    let requests = (0..2).map(|i| {
+        let (juggler, done) = (&juggler, done.clone());
+        async move {
+            actix_web::rt::time::sleep(Duration::from_millis(300 * i)).await;
+            assert_eq!(juggler.generate(MODEL).await.0, 200);
+            done.lock().unwrap().push(i);
+        }
+    });
+    join_all(requests).await;
+
+    assert_eq!(*done.lock().unwrap(), [0, 1]);
+    assert_eq!(mock.hits("key-a"), 4);
+}
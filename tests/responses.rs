@@ -0,0 +1,331 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client, events, raw};
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+
+async fn respond(juggler: &Juggler, body: &Value) -> (u16, Value) {
+    let (status, body) = juggler.post("/v1/responses", body).await;
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+async fn stored(juggler: &Juggler, method: &str, id: &str) -> (u16, Value) {
+    let mut resp = client()
+        .request(
+            method.parse().unwrap(),
+            juggler.url(&format!("/v1/responses/{id}")),
+        )
+        .insert_header(("Authorization", format!("Bearer {API_KEY}")))
+        .send()
+        .await
+        .unwrap();
+    let body = resp.body().await.unwrap();
+    (
+        resp.status().as_u16(),
+        serde_json::from_slice(&body).unwrap_or(Value::Null),
+    )
+}
+
+fn function_call_answer() -> Behavior {
+    let answer = json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [
+                {"functionCall": {"name": "lookup", "args": {"city": "Lisbon"}}, "thoughtSignature": "c2ln"},
+            ]},
+            "finishReason": "STOP",
+        }],
+        "usageMetadata": {"promptTokenCount": 20, "candidatesTokenCount": 5, "thoughtsTokenCount": 2, "totalTokenCount": 27},
+    });
+    raw(answer)
+}
+
+#[actix_web::test]
+async fn translates_responses() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = respond(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "instructions": "Be brief.",
+            "input": "hi",
+            "max_output_tokens": 256,
+            "text": {"format": {"type": "json_schema", "name": "answer", "schema": {"type": "object"}}},
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["object"], "response");
+    assert_eq!(body["status"], "completed");
+    assert_eq!(body["model"], MODEL);
+    assert_eq!(body["instructions"], "Be brief.");
+    assert!(body["id"].as_str().unwrap().starts_with("resp_"));
+    let message = &body["output"][0];
+    assert_eq!(message["type"], "message");
+    assert_eq!(message["role"], "assistant");
+    assert_eq!(message["content"][0]["type"], "output_text");
+    assert_eq!(message["content"][0]["text"], "hello from key-a");
+    assert_eq!(body["usage"]["input_tokens"], 4);
+    assert_eq!(body["usage"]["output_tokens"], 3);
+    assert_eq!(body["usage"]["total_tokens"], 7);
+
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["systemInstruction"],
+        json!({"parts": [{"text": "Be brief."}]})
+    );
+    assert_eq!(
+        sent["contents"],
+        json!([{"role": "user", "parts": [{"text": "hi"}]}])
+    );
+    assert_eq!(sent["generationConfig"]["maxOutputTokens"], 256);
+    assert_eq!(
+        sent["generationConfig"]["responseMimeType"],
+        "application/json"
+    );
+    assert_eq!(
+        sent["generationConfig"]["responseJsonSchema"],
+        json!({"type": "object"})
+    );
+}
+
+#[actix_web::test]
+async fn continues_from_stored_responses() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (_, first) = respond(
+        &juggler,
+        &json!({"model": MODEL, "instructions": "Be brief.", "input": "hi"}),
+    )
+    .await;
+    let id = first["id"].as_str().unwrap();
+    assert_eq!(stored(&juggler, "GET", id).await.1["id"], id);
+
+    let (status, second) = respond(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "previous_response_id": id,
+            "input": [{"role": "user", "content": [{"type": "input_text", "text": "again"}]}],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(second["previous_response_id"], id);
+    let sent = mock.last_body("key-a").unwrap();
+    // Instructions don't carry over to the next turn.
+    assert!(sent.get("systemInstruction").is_none());
+    assert_eq!(
+        sent["contents"],
+        json!([
+            {"role": "user", "parts": [{"text": "hi"}]},
+            {"role": "model", "parts": [{"text": "hello from key-a"}]},
+            {"role": "user", "parts": [{"text": "again"}]},
+        ])
+    );
+
+    assert_eq!(stored(&juggler, "DELETE", id).await.1["deleted"], true);
+    assert_eq!(stored(&juggler, "GET", id).await.0, 404);
+    let (status, body) = respond(
+        &juggler,
+        &json!({"model": MODEL, "previous_response_id": id, "input": "again"}),
+    )
+    .await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+
+    let (_, unstored) = respond(
+        &juggler,
+        &json!({"model": MODEL, "input": "hi", "store": false}),
+    )
+    .await;
+    assert_eq!(unstored["store"], false);
+    let unstored = unstored["id"].as_str().unwrap();
+    assert_eq!(stored(&juggler, "GET", unstored).await.0, 404);
+}
+
+#[actix_web::test]
+async fn translates_function_calls() {
+    let mock = MockUpstream::start().await;
+    mock.script("key-a", [function_call_answer()]);
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, first) = respond(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "input": [{"role": "user", "content": "What's the weather?"}],
+            "tools": [{
+                "type": "function",
+                "name": "lookup",
+                "description": "Looks a city up",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+            }],
+            "tool_choice": "required",
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let sent = mock.last_body("key-a").unwrap();
+    let declaration = &sent["tools"][0]["functionDeclarations"][0];
+    assert_eq!(declaration["name"], "lookup");
+    assert_eq!(declaration["parametersJsonSchema"]["type"], "object");
+    assert_eq!(sent["toolConfig"]["functionCallingConfig"]["mode"], "ANY");
+
+    let output = first["output"].as_array().unwrap();
+    assert_eq!(output[0]["type"], "reasoning");
+    assert_eq!(output[0]["encrypted_content"], "c2ln");
+    assert_eq!(output[1]["type"], "function_call");
+    assert_eq!(output[1]["name"], "lookup");
+    assert_eq!(output[1]["status"], "completed");
+    let arguments: Value = serde_json::from_str(output[1]["arguments"].as_str().unwrap()).unwrap();
+    assert_eq!(arguments, json!({"city": "Lisbon"}));
+    assert_eq!(
+        first["usage"]["output_tokens_details"]["reasoning_tokens"],
+        2
+    );
+    let call_id = output[1]["call_id"].as_str().unwrap();
+
+    let (status, _) = respond(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "previous_response_id": first["id"],
+            "input": [{"type": "function_call_output", "call_id": call_id, "output": "Sunny"}],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(
+        sent["contents"][1]["parts"][0],
+        json!({
+            "functionCall": {"id": call_id, "name": "lookup", "args": {"city": "Lisbon"}},
+            "thoughtSignature": "c2ln",
+        })
+    );
+    assert_eq!(
+        sent["contents"][2],
+        json!({"role": "user", "parts": [{"functionResponse": {
+            "id": call_id,
+            "name": "lookup",
+            "response": {"output": "Sunny"},
+        }}]})
+    );
+
+    // Clients that don't store responses send the whole conversation back,
+    // reasoning items included.
+    let (status, _) = respond(
+        &juggler,
+        &json!({
+            "model": MODEL,
+            "store": false,
+            "input": [
+                {"role": "user", "content": "What's the weather?"},
+                output[0],
+                output[1],
+                {"type": "function_call_output", "call_id": call_id, "output": "Sunny"},
+                {"role": "user", "content": [{"type": "input_image", "image_url": "data:image/png;base64,iVBORw0K"}]},
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let sent = mock.last_body("key-a").unwrap();
+    assert_eq!(sent["contents"][1]["parts"][0]["thoughtSignature"], "c2ln");
+    assert_eq!(
+        sent["contents"][2]["parts"][0]["functionResponse"]["name"],
+        "lookup"
+    );
+    assert_eq!(
+        sent["contents"][2]["parts"][1],
+        json!({"inlineData": {"mimeType": "image/png", "data": "iVBORw0K"}})
+    );
+}
+
+#[actix_web::test]
+async fn streams_response_events() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = juggler
+        .post(
+            "/v1/responses",
+            &json!({"model": MODEL, "input": "hi", "stream": true}),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let events = events(&body);
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "response.created",
+            "response.in_progress",
+            "response.output_item.added",
+            "response.content_part.added",
+            "response.output_text.delta",
+            "response.output_text.delta",
+            "response.output_text.delta",
+            "response.output_text.done",
+            "response.content_part.done",
+            "response.output_item.done",
+            "response.completed",
+        ]
+    );
+    for (sequence, (name, data)) in events.iter().enumerate() {
+        assert_eq!(&data["type"], name);
+        assert_eq!(data["sequence_number"], sequence);
+    }
+    assert_eq!(events[4].1["delta"], "chunk 0 ");
+    assert_eq!(events[7].1["text"], "chunk 0 chunk 1 chunk 2 ");
+
+    let completed = &events[10].1["response"];
+    assert_eq!(completed["status"], "completed");
+    assert_eq!(
+        completed["output"][0]["content"][0]["text"],
+        "chunk 0 chunk 1 chunk 2 "
+    );
+    assert_eq!(completed["usage"]["total_tokens"], 7);
+    let id = completed["id"].as_str().unwrap();
+    assert_eq!(stored(&juggler, "GET", id).await.1, *completed);
+}
+
+#[actix_web::test]
+async fn answers_errors_in_the_openai_format() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::MinuteQuota {
+            retry_delay: Duration::from_secs(30),
+        },
+    );
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let (status, body) = respond(&juggler, &json!({"model": MODEL, "input": "hi"})).await;
+    assert_eq!(status, 429);
+    assert_eq!(body["error"]["type"], "rate_limit_error");
+
+    let unauthorized = client()
+        .post(juggler.url("/v1/responses"))
+        .send_json(&json!({"model": MODEL, "input": "hi"}))
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(unauthorized["error"]["type"], "authentication_error");
+
+    let (status, body) = respond(
+        &juggler,
+        &json!({"model": MODEL, "input": "hi", "tools": [{"type": "computer_use_preview"}]}),
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Behavior, Juggler, MockUpstream};
+use serde_json::Value;
+
+const MODEL: &str = "gemini-2.5-flash";
+
+fn strategy(name: &str) -> String {
+    format!("strategy = \"{name}\"")
+}
+
+fn served_by(body: &Value) -> String {
+    body.pointer("/candidates/0/content/parts/0/text")
+        .and_then(Value::as_str)
+        .and_then(|text| text.strip_prefix("hello from "))
+        .expect("unexpected response body")
+        .to_string()
+}
+
+#[actix_web::test]
+async fn round_robin_goes_through_keys_in_order() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(
+        &mock,
+        &["key-a", "key-b", "key-c"],
+        &strategy("round-robin"),
+    );
+
+    let mut order = Vec::new();
+    for _ in 0..4 {
+        let (status, body) = juggler.generate(MODEL).await;
+        assert_eq!(status, 200);
+        order.push(served_by(&body));
+    }
+    assert_eq!(order, ["key-a", "key-b", "key-c", "key-a"]);
+}
+
+#[actix_web::test]
+async fn fill_first_drains_one_key_before_the_next() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with(&mock, &["key-a", "key-b"], &strategy("fill-first"));
+
+    for _ in 0..3 {
+        assert_eq!(juggler.generate(MODEL).await.0, 200);
+    }
+    assert_eq!(mock.hits("key-a"), 3);
+    assert_eq!(mock.hits("key-b"), 0);
+
+    mock.set("key-a", Behavior::DailyQuota);
+    for _ in 0..2 {
+        let (status, body) = juggler.generate(MODEL).await;
+        assert_eq!(status, 200);
+        assert_eq!(served_by(&body), "key-b");
+    }
+}
+
+#[actix_web::test]
+async fn weighted_random_follows_key_weights() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start_with_keys(
+        &mock,
+        r#"[{ key = "key-a", weight = 0 }, { key = "key-b", weight = 3 }, "key-c"]"#,
+        &strategy("weighted-random"),
+    );
+
+    for _ in 0..40 {
+        assert_eq!(juggler.generate(MODEL).await.0, 200);
+    }
+    assert_eq!(mock.hits("key-a"), 0);
+    assert!(mock.hits("key-b") > mock.hits("key-c"));
+}
+
+#[actix_web::test]
+async fn least_latency_prefers_the_fastest_key() {
+    let mock = MockUpstream::start().await;
+    mock.set_latency_for("key-a", Duration::from_millis(150));
+    let juggler = Juggler::start_with(&mock, &["key-a", "key-b"], &strategy("least-latency"));
+
+    // Both keys get tried once before latencies are known.
+    for _ in 0..6 {
+        assert_eq!(juggler.generate(MODEL).await.0, 200);
+    }
+    assert_eq!(mock.hits("key-a"), 1);
+    assert_eq!(mock.hits("key-b"), 5);
+}
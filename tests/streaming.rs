@@ -0,0 +1,153 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{API_KEY, Behavior, Juggler, MockUpstream, client};
+use futures_util::StreamExt;
+use serde_json::{Value, json};
+
+const MODEL: &str = "gemini-2.5-flash";
+
+fn request() -> Value {
+    json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]})
+}
+
+fn stream_url(juggler: &Juggler, alt: Option<&str>) -> String {
+    let alt = alt.map(|alt| format!("&alt={alt}")).unwrap_or_default();
+    juggler.url(&format!(
+        "/v1beta/models/{MODEL}:streamGenerateContent?key={API_KEY}{alt}"
+    ))
+}
+
+fn texts(events: &[Value]) -> Vec<&str> {
+    events
+        .iter()
+        .map(|event| {
+            event
+                .pointer("/candidates/0/content/parts/0/text")
+                .and_then(Value::as_str)
+                .unwrap()
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn streams_server_sent_events() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let mut resp = client()
+        .post(stream_url(&juggler, Some("sse")))
+        .send_json(&request())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+
+    let body = resp.body().await.unwrap();
+    let events: Vec<Value> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+    assert_eq!(texts(&events), ["chunk 0 ", "chunk 1 ", "chunk 2 "]);
+}
+
+#[actix_web::test]
+async fn streams_a_json_array_without_alt() {
+    let mock = MockUpstream::start().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    let mut resp = client()
+        .post(stream_url(&juggler, None))
+        .send_json(&request())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/json"
+    );
+
+    let events: Vec<Value> = serde_json::from_slice(&resp.body().await.unwrap()).unwrap();
+    assert_eq!(texts(&events), ["chunk 0 ", "chunk 1 ", "chunk 2 "]);
+}
+
+#[actix_web::test]
+async fn forwards_events_as_they_arrive() {
+    let mock = MockUpstream::start().await;
+    mock.set(
+        "key-a",
+        Behavior::SlowStream {
+            chunks: 3,
+            delay: Duration::from_millis(400),
+        },
+    );
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    for alt in [Some("sse"), None] {
+        let started = Instant::now();
+        let mut resp = client()
+            .post(stream_url(&juggler, alt))
+            .send_json(&request())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+
+        let first = resp.next().await.unwrap().unwrap();
+        assert!(String::from_utf8_lossy(&first).contains("chunk 0"));
+        assert!(started.elapsed() < Duration::from_millis(1000));
+        while resp.next().await.is_some() {}
+    }
+}
+
+#[actix_web::test]
+async fn decodes_compressed_upstream_responses() {
+    let mock = MockUpstream::start_compressed().await;
+    let juggler = Juggler::start(&mock, &["key-a"]);
+
+    // Upstream bodies arrive gzipped and are decoded before being relayed, so
+    // they can be read for usage and errors, and reach clients without a stale
+    // Content-Encoding.
+    let mut resp = client()
+        .post(juggler.url(&format!(
+            "/v1beta/models/{MODEL}:generateContent?key={API_KEY}"
+        )))
+        .insert_header(("Accept-Encoding", "identity"))
+        .no_decompress()
+        .send_json(&request())
+        .await
+        .unwrap();
+    assert!(resp.headers().get("content-encoding").is_none());
+    let body: Value = serde_json::from_slice(&resp.body().await.unwrap()).unwrap();
+    assert_eq!(
+        body["candidates"][0]["content"]["parts"][0]["text"],
+        "hello from key-a"
+    );
+    assert_eq!(juggler.status().await["clients"][0]["num_tokens"], 7);
+    assert_eq!(mock.compressed(), 1);
+
+    let mut resp = client()
+        .post(stream_url(&juggler, Some("sse")))
+        .send_json(&request())
+        .await
+        .unwrap();
+    let body = resp.body().await.unwrap();
+    let events: Vec<Value> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+    assert_eq!(texts(&events), ["chunk 0 ", "chunk 1 ", "chunk 2 "]);
+
+    let (status, body) = juggler.chat(MODEL, false).await;
+    assert_eq!(status, 200);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], "hello from key-a");
+    assert_eq!(mock.compressed(), 3);
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{Juggler, MockUpstream};
+use futures_util::future::join_all;
+
+const REQUESTS: usize = 16;
+const LATENCY: Duration = Duration::from_millis(100);
+
+/// The routes the load is put through.
+#[derive(Clone, Copy)]
+enum Route {
+    Gemini,
+    OpenAi { stream: bool },
+}
+
+impl Route {
+    async fn call(self, juggler: &Juggler) -> u16 {
+        match self {
+            Route::Gemini => juggler.generate("gemini-2.5-flash").await.0,
+            Route::OpenAi { stream } => juggler.chat("gemini-2.5-flash", stream).await.0,
+        }
+    }
+}
+
+/// Fires `REQUESTS` concurrent requests through `route` of a juggler with
+/// `keys` keys at an upstream that serves each key one request at a time.
+async fn elapsed_with(route: Route, keys: &[&str]) -> Duration {
+    let mock = MockUpstream::start().await;
+    mock.set_latency(LATENCY);
+    mock.set_serial_per_key(true);
+    let juggler = Juggler::start(&mock, keys);
+
+    let start = Instant::now();
+    let statuses = join_all((0..REQUESTS).map(|_| route.call(&juggler))).await;
+    let elapsed = start.elapsed();
+
+    assert!(statuses.iter().all(|status| *status == 200));
+    for key in keys {
+        assert_eq!(mock.hits(key), REQUESTS / keys.len(), "uneven spread");
+    }
+
+    elapsed
+}
+
+async fn assert_scales(route: Route) {
+    let one = elapsed_with(route, &["key-a"]).await;
+    let four = elapsed_with(route, &["key-a", "key-b", "key-c", "key-d"]).await;
+
+    // One key is bound by the upstream to REQUESTS * LATENCY no matter what,
+    // four keys only get there if the juggler keeps them all busy at once.
+    assert!(one >= LATENCY * REQUESTS as u32);
+    assert!(
+        four * 5 / 2 < one,
+        "four keys took {four:?}, one key took {one:?}"
+    );
+}
+
+#[actix_web::test]
+async fn throughput_scales_with_key_count() {
+    assert_scales(Route::Gemini).await;
+}
+
+#[actix_web::test]
+async fn openai_throughput_scales_with_key_count() {
+    assert_scales(Route::OpenAi { stream: false }).await;
+}
+
+#[actix_web::test]
+async fn streaming_throughput_scales_with_key_count() {
+    assert_scales(Route::OpenAi { stream: true }).await;
+}